@@ -1,5 +1,11 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use voice_pa_core::audio::{AudioRecorder, AudioConfig};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use voice_pa_core::audio::ring::capture_ring;
+use voice_pa_core::audio::AudioConfig;
+
+/// A typical 10 ms callback at 48 kHz stereo
+const CALLBACK_SAMPLES: usize = 960;
 
 fn benchmark_audio_recording(c: &mut Criterion) {
     c.bench_function("audio_config_creation", |b| {
@@ -9,5 +15,44 @@ fn benchmark_audio_recording(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, benchmark_audio_recording);
+fn benchmark_capture_callback(c: &mut Criterion) {
+    let data = vec![0.25f32; CALLBACK_SAMPLES];
+    let mut group = c.benchmark_group("capture_callback");
+
+    // Previous design: lock a shared Vec and append to it on the audio thread
+    group.bench_function("mutex_vec", |b| {
+        let buffer = Arc::new(Mutex::new(Vec::<f32>::new()));
+        b.iter(|| {
+            let mut buffer = buffer.lock().unwrap();
+            buffer.extend_from_slice(black_box(&data));
+            // Keep memory bounded without hiding the reallocation cost
+            if buffer.len() > 48_000 * 2 * 600 {
+                *buffer = Vec::new();
+            }
+        });
+    });
+
+    // Current design: push into a preallocated ring; draining happens on
+    // another thread in the recorder, so it is excluded from the timing here
+    group.bench_function("spsc_ring", |b| {
+        let (mut producer, mut consumer) = capture_ring(48_000 * 2 * 2);
+        let mut scratch = Vec::with_capacity(48_000 * 2 * 2);
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                let start = Instant::now();
+                producer.push(black_box(&data));
+                elapsed += start.elapsed();
+
+                scratch.clear();
+                consumer.drain_into(&mut scratch);
+            }
+            elapsed
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_audio_recording, benchmark_capture_callback);
 criterion_main!(benches);
@@ -0,0 +1,29 @@
+use voice_pa_core::audio::{list_hosts, list_input_devices};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    println!("🎙️  Voice PA Core - Input Devices");
+    println!("=================================\n");
+
+    println!("Hosts:");
+    for host in list_hosts() {
+        let marker = if host.is_default { " (default)" } else { "" };
+        println!("   {} [{}]{}", host.name, host.id, marker);
+    }
+
+    println!("\nInput devices:");
+    for device in list_input_devices() {
+        let marker = if device.is_default { " (default)" } else { "" };
+        println!("\n   {}{}", device.name, marker);
+        println!("   id: {}", device.id);
+        for config in &device.supported_configs {
+            println!(
+                "     - {} ch, {}-{} Hz, {:?}",
+                config.channels, config.min_sample_rate, config.max_sample_rate, config.sample_format
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -19,6 +19,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         sample_rate: 16000,
         channels: 1,
         format: AudioFormat::Wav,
+        ..Default::default()
     };
     
     let mut recorder = match AudioRecorder::with_config(config) {
@@ -52,11 +53,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Encode to WAV
     println!("\n4. Encoding to WAV...");
     let encoder = WavEncoder::new();
-    let actual_rate = recorder.actual_sample_rate();
-    let actual_channels = recorder.actual_channels();
-    println!("   ℹ️  Actual config: {} Hz, {} channels", actual_rate, actual_channels);
+    println!(
+        "   ℹ️  Device config: {} Hz, {} channels",
+        recorder.actual_sample_rate(),
+        recorder.actual_channels()
+    );
+    let output_rate = recorder.output_sample_rate();
+    let output_channels = recorder.output_channels();
     
-    let wav_data = encoder.encode(&audio_data, actual_rate, actual_channels)?;
+    let wav_data = encoder.encode(&audio_data, output_rate, output_channels)?;
     println!("   ✓ Encoded to WAV");
     println!("   📦 WAV file size: {} bytes", wav_data.len());
 
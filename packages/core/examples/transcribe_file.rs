@@ -1,6 +1,6 @@
+use voice_pa_core::audio::{AudioEncoder, FileDecoder, FlacEncoder};
 use voice_pa_core::transcription::WhisperClient;
 use std::env;
-use std::fs;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -12,7 +12,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Get file path from args
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        eprintln!("Usage: {} <audio_file.wav>", args[0]);
+        eprintln!("Usage: {} <audio_file>", args[0]);
+        eprintln!("Supports WAV, FLAC, MP3, AAC/M4A and Ogg/Vorbis");
         eprintln!("\nExample:");
         eprintln!("  cargo run --example transcribe_file recording.wav");
         return Ok(());
@@ -24,18 +25,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let api_key = env::var("OPENAI_API_KEY")
         .expect("OPENAI_API_KEY environment variable not set");
 
-    // Read audio file
-    println!("1. Reading audio file: {}", file_path);
-    let audio_data = fs::read(file_path)?;
-    println!("   ✓ File loaded: {} bytes", audio_data.len());
+    // Decode audio file
+    println!("1. Decoding audio file: {}", file_path);
+    let decoded = FileDecoder::new().decode_file(file_path)?;
+    println!("   ✓ Decoded: {:.2}s at {} Hz, {} channel(s)",
+        decoded.duration().as_secs_f64(),
+        decoded.sample_rate,
+        decoded.channels
+    );
+
+    // Re-encode as 16 kHz mono FLAC, all Whisper needs
+    println!("\n2. Encoding for upload...");
+    let speech = decoded.convert(16000, 1)?;
+    let encoder = FlacEncoder::new();
+    let audio_data = encoder.encode(&speech.samples, speech.sample_rate, speech.channels)?;
+    println!("   ✓ Encoded: {} bytes", audio_data.len());
 
     // Transcribe with Whisper
-    println!("\n2. Transcribing with OpenAI Whisper...");
+    println!("\n3. Transcribing with OpenAI Whisper...");
     println!("   (This may take a few seconds...)");
     
     let client = WhisperClient::new(api_key);
     
-    match client.transcribe(&audio_data).await {
+    match client.transcribe_encoded(&audio_data, &encoder, None).await {
         Ok(transcript) => {
             println!("   ✓ Transcription complete\n");
             
@@ -65,7 +77,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             eprintln!("   ✗ Transcription failed: {}", e);
             eprintln!("\nTroubleshooting:");
             eprintln!("  - Make sure OPENAI_API_KEY is set correctly");
-            eprintln!("  - Check that the audio file is in WAV format");
             eprintln!("  - Verify you have internet connectivity");
             return Err(e.into());
         }
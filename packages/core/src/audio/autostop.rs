@@ -0,0 +1,184 @@
+use std::time::Duration;
+use crate::audio::preprocessing::VoiceActivityDetector;
+
+/// Size of the WAV header counted against [`StopPolicy::max_bytes`]
+const WAV_HEADER_BYTES: u64 = 44;
+
+/// Window the voice activity detector judges at a time
+const VAD_WINDOW: Duration = Duration::from_millis(100);
+
+/// Audio kept after the last speech so trailing words are not clipped
+const SPEECH_TAIL: Duration = Duration::from_millis(300);
+
+/// Conditions under which a recording stops by itself
+///
+/// Limits apply to the recorded output audio, so time spent paused does not
+/// count. When a policy fires the recording is trimmed to shortly after the
+/// last speech the voice activity detector heard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopPolicy {
+    /// Stop once this much audio has been recorded
+    pub max_duration: Option<Duration>,
+    /// Stop after this much continuous silence
+    pub silence_timeout: Option<Duration>,
+    /// RMS level below which [`VoiceActivityDetector`] hears silence
+    pub silence_threshold: f32,
+    /// Stop before the recording as a 16-bit WAV file would exceed this size
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for StopPolicy {
+    fn default() -> Self {
+        Self {
+            max_duration: None,
+            silence_timeout: None,
+            silence_threshold: 0.01,
+            max_bytes: None,
+        }
+    }
+}
+
+impl StopPolicy {
+    /// Whether any condition is set
+    pub fn is_enabled(&self) -> bool {
+        self.max_duration.is_some() || self.silence_timeout.is_some() || self.max_bytes.is_some()
+    }
+}
+
+/// Which stop policy ended a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    MaxDuration,
+    Silence,
+    MaxBytes,
+}
+
+/// Applies a [`StopPolicy`] to output audio as it is recorded
+pub(crate) struct StopMonitor {
+    vad: VoiceActivityDetector,
+    channels: usize,
+    window_frames: usize,
+    tail_frames: u64,
+    limit: Option<(u64, StopReason)>,
+    silence_limit: Option<u64>,
+    pending: Vec<f32>,
+    frames: u64,
+    silence_start: u64,
+    last_speech_end: Option<u64>,
+}
+
+impl StopMonitor {
+    pub fn new(policy: &StopPolicy, sample_rate: u32, channels: u16) -> Self {
+        let to_frames = |duration: Duration| (duration.as_secs_f64() * sample_rate as f64) as u64;
+        let channels = channels.max(1) as usize;
+
+        let duration_limit = policy
+            .max_duration
+            .map(|duration| (to_frames(duration), StopReason::MaxDuration));
+        let bytes_limit = policy.max_bytes.map(|bytes| {
+            let frames = bytes.saturating_sub(WAV_HEADER_BYTES) / (2 * channels as u64);
+            (frames, StopReason::MaxBytes)
+        });
+        let limit = match (duration_limit, bytes_limit) {
+            (Some(a), Some(b)) => Some(if b.0 < a.0 { b } else { a }),
+            (a, b) => a.or(b),
+        };
+
+        Self {
+            vad: VoiceActivityDetector::new(policy.silence_threshold),
+            channels,
+            window_frames: to_frames(VAD_WINDOW).max(1) as usize,
+            tail_frames: to_frames(SPEECH_TAIL),
+            limit,
+            silence_limit: policy.silence_timeout.map(to_frames),
+            pending: Vec::new(),
+            frames: 0,
+            silence_start: 0,
+            last_speech_end: None,
+        }
+    }
+
+    /// Take in interleaved output samples; returns how many of them belong
+    /// to the recording and, once a policy fires, why it should stop
+    pub fn admit(&mut self, samples: &[f32]) -> (usize, Option<StopReason>) {
+        let mut frames = samples.len() / self.channels;
+        let mut reason = None;
+        if let Some((limit, why)) = self.limit {
+            let remaining = limit.saturating_sub(self.frames);
+            if frames as u64 >= remaining {
+                frames = remaining as usize;
+                reason = Some(why);
+            }
+        }
+
+        let window_len = self.window_frames * self.channels;
+        for (i, frame) in samples[..frames * self.channels]
+            .chunks_exact(self.channels)
+            .enumerate()
+        {
+            self.pending.extend_from_slice(frame);
+            if self.pending.len() < window_len {
+                continue;
+            }
+
+            let end = self.frames + i as u64 + 1;
+            if self.vad.detect(&self.pending) {
+                self.last_speech_end = Some(end);
+                self.silence_start = end;
+            } else if self
+                .silence_limit
+                .is_some_and(|limit| end - self.silence_start >= limit)
+            {
+                self.frames = end;
+                return ((i + 1) * self.channels, Some(StopReason::Silence));
+            }
+            self.pending.clear();
+        }
+
+        self.frames += frames as u64;
+        (frames * self.channels, reason)
+    }
+
+    /// Frames to keep once stopped: up to shortly after the last speech, or
+    /// everything when no speech was heard
+    pub fn keep_frames(&self) -> u64 {
+        match self.last_speech_end {
+            Some(end) => (end + self.tail_frames).min(self.frames),
+            None => self.frames,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silence_timeout_trims_to_last_speech() {
+        let policy = StopPolicy {
+            silence_timeout: Some(Duration::from_secs(2)),
+            ..StopPolicy::default()
+        };
+        let mut monitor = StopMonitor::new(&policy, 1000, 1);
+
+        // One second of speech, then silence until the timeout fires
+        assert_eq!(monitor.admit(&[0.5; 1000]), (1000, None));
+        assert_eq!(monitor.admit(&[0.0; 1500]), (1500, None));
+        assert_eq!(monitor.admit(&[0.0; 1000]), (500, Some(StopReason::Silence)));
+        assert_eq!(monitor.keep_frames(), 1300);
+    }
+
+    #[test]
+    fn test_smallest_limit_wins() {
+        let policy = StopPolicy {
+            max_duration: Some(Duration::from_secs(10)),
+            max_bytes: Some(44 + 2 * 2 * 500),
+            ..StopPolicy::default()
+        };
+        let mut monitor = StopMonitor::new(&policy, 1000, 2);
+
+        assert_eq!(monitor.admit(&[0.5; 800]), (800, None));
+        assert_eq!(monitor.admit(&[0.5; 800]), (200, Some(StopReason::MaxBytes)));
+        assert_eq!(monitor.keep_frames(), 500);
+    }
+}
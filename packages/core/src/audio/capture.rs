@@ -1,8 +1,28 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Stream, StreamConfig};
-use std::sync::{Arc, Mutex};
+use cpal::SampleFormat;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{broadcast, watch};
+use crate::audio::autostop::{StopMonitor, StopPolicy};
+use crate::audio::channels::ChannelMapping;
+use crate::audio::devices::DeviceSelector;
+use crate::audio::encoding::WavEncoder;
+use crate::audio::events::{RecorderEvent, RecoveryPolicy};
+use crate::audio::frames::{FrameDispatcher, FrameSubscription};
+use crate::audio::metering::InputLevel;
+use crate::audio::pipeline::{CapturePipeline, ConsumerThread, SampleSink, StopHandler};
+use crate::audio::preroll::PreRollBuffer;
+use crate::audio::recording::{PauseInterval, Recording, StreamGap};
+use crate::audio::resampler::{AudioResampler, ResampleQuality};
+use crate::audio::source::{AudioSource, CaptureClock, CpalSource};
+use crate::audio::spill::SpillWriter;
+use crate::audio::stream::{ring_for, ActiveStream, StreamSupervisor, SupervisorContext};
 use crate::utils::error::{Result, VoicePAError};
 
+/// Recorder events queued per subscriber before the oldest are dropped
+const EVENT_CAPACITY: usize = 32;
+
 #[derive(Debug, Clone, Copy)]
 pub enum AudioFormat {
     Wav,
@@ -15,6 +35,27 @@ pub struct AudioConfig {
     pub sample_rate: u32,
     pub channels: u16,
     pub format: AudioFormat,
+    /// Input device to record from; ignored by recorders created with
+    /// [`AudioRecorder::with_source`]
+    pub device: DeviceSelector,
+    /// Quality of the conversion to `sample_rate`
+    pub resample_quality: ResampleQuality,
+    /// How input channels become the recorded `channels`
+    pub channel_mapping: ChannelMapping,
+    /// Return audio in the device's native rate and channel layout
+    /// instead of `sample_rate` and `channels`
+    pub native_format: bool,
+    /// Write audio to this WAV file as it is captured instead of keeping it
+    /// in memory. A file already at this path, e.g. one left behind by a
+    /// crash, is repaired and renamed when the next recording starts; see
+    /// [`SpillWriter::create`].
+    pub spill_path: Option<PathBuf>,
+    /// Sample format, dither and metadata of the spill file
+    pub spill_encoder: WavEncoder,
+    /// What to do when the input stream fails mid-recording
+    pub recovery: RecoveryPolicy,
+    /// When to stop recording without being asked
+    pub stop_policy: StopPolicy,
 }
 
 impl Default for AudioConfig {
@@ -23,17 +64,66 @@ impl Default for AudioConfig {
             sample_rate: 16000,
             channels: 1,
             format: AudioFormat::Wav,
+            device: DeviceSelector::Default,
+            resample_quality: ResampleQuality::default(),
+            channel_mapping: ChannelMapping::default(),
+            native_format: false,
+            spill_path: None,
+            spill_encoder: WavEncoder::new().with_dither(false),
+            recovery: RecoveryPolicy::default(),
+            stop_policy: StopPolicy::default(),
         }
     }
 }
 
-pub struct AudioRecorder {
+/// Snapshot of what the recorder is actually capturing
+#[derive(Debug, Clone)]
+pub struct RecorderDiagnostics {
+    pub device_name: String,
+    /// Sample format delivered by the device, known once recording started
+    pub native_sample_format: Option<SampleFormat>,
+    pub native_sample_rate: u32,
+    pub native_channels: u16,
+    pub output_sample_rate: u32,
+    pub output_channels: u16,
+    pub dropped_samples: u64,
+    /// Delay between capture and delivery of the latest audio, for sources
+    /// that report timestamps
+    pub capture_latency: Option<Duration>,
+}
+
+/// Records from an [`AudioSource`], by default a physical input device
+pub struct AudioRecorder<S: AudioSource = CpalSource> {
     config: AudioConfig,
-    device: Device,
-    stream: Option<Stream>,
-    buffer: Arc<Mutex<Vec<f32>>>,
+    source: S,
+    supervisor: Option<StreamSupervisor>,
+    frames: Arc<Mutex<FrameDispatcher>>,
+    consumer: Option<ConsumerThread>,
+    recorded_frames: Arc<AtomicU64>,
+    paused: Arc<AtomicBool>,
+    dropped_samples: Arc<AtomicU64>,
+    clock: Arc<CaptureClock>,
+    /// Start of a recording armed from standby; otherwise the clock's anchor
+    /// is used
+    started_at: Option<SystemTime>,
+    pauses: Vec<PauseInterval>,
+    pause_started: Option<(PauseInterval, Instant)>,
+    gaps: Arc<Mutex<Vec<StreamGap>>>,
+    events: broadcast::Sender<RecorderEvent>,
+    levels: Arc<watch::Sender<InputLevel>>,
     is_recording: Arc<Mutex<bool>>,
-    actual_config: Arc<Mutex<Option<StreamConfig>>>,
+    capture_ended: Arc<AtomicBool>,
+    active_stream: Arc<Mutex<Option<ActiveStream>>>,
+    /// Output rate and channels of the current or last recording
+    output_format: Option<(u32, u16)>,
+    standby: Option<Standby>,
+}
+
+/// Capture running into a pre-roll buffer, waiting for a recording
+struct Standby {
+    pre_roll: Duration,
+    since: Instant,
+    sinks: mpsc::Sender<SampleSink>,
 }
 
 impl AudioRecorder {
@@ -42,97 +132,429 @@ impl AudioRecorder {
         Self::with_config(AudioConfig::default())
     }
 
+    /// Create a new AudioRecorder that records from a specific input device
+    pub fn with_device(device: DeviceSelector) -> Result<Self> {
+        Self::with_config(AudioConfig {
+            device,
+            ..AudioConfig::default()
+        })
+    }
+
     /// Create a new AudioRecorder with custom configuration
     pub fn with_config(config: AudioConfig) -> Result<Self> {
-        let host = cpal::default_host();
-        let device = host
-            .default_input_device()
-            .ok_or_else(|| VoicePAError::AudioDevice("No input device available".to_string()))?;
+        let source = CpalSource::new(&config.device)?;
 
-        log::info!("Using audio device: {}", device.name().unwrap_or_default());
+        log::info!("Using audio device: {}", source.name());
 
-        Ok(Self {
+        Ok(Self::with_source(source, config))
+    }
+}
+
+impl<S: AudioSource> AudioRecorder<S> {
+    /// Create a recorder capturing from `source`, e.g. a file or generated
+    /// signal
+    pub fn with_source(source: S, config: AudioConfig) -> Self {
+        Self {
             config,
-            device,
-            stream: None,
-            buffer: Arc::new(Mutex::new(Vec::new())),
+            source,
+            supervisor: None,
+            frames: Arc::new(Mutex::new(FrameDispatcher::new())),
+            consumer: None,
+            recorded_frames: Arc::new(AtomicU64::new(0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            dropped_samples: Arc::new(AtomicU64::new(0)),
+            clock: Arc::new(CaptureClock::new()),
+            started_at: None,
+            pauses: Vec::new(),
+            pause_started: None,
+            gaps: Arc::new(Mutex::new(Vec::new())),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            levels: Arc::new(watch::channel(InputLevel::default()).0),
             is_recording: Arc::new(Mutex::new(false)),
-            actual_config: Arc::new(Mutex::new(None)),
-        })
+            capture_ended: Arc::new(AtomicBool::new(false)),
+            active_stream: Arc::new(Mutex::new(None)),
+            output_format: None,
+            standby: None,
+        }
     }
 
-
     /// Start recording audio
+    ///
+    /// From standby the buffered pre-roll becomes the start of the
+    /// recording, and `duration()` and frame timestamps include it.
     pub async fn start_recording(&mut self) -> Result<()> {
-        let mut is_recording = self.is_recording.lock().unwrap();
+        let is_recording = Arc::clone(&self.is_recording);
+        let mut is_recording = is_recording.lock().unwrap();
         if *is_recording {
             return Err(VoicePAError::AudioStream("Already recording".to_string()));
         }
 
-        // Clear previous buffer
-        self.buffer.lock().unwrap().clear();
+        // Clear previous session bookkeeping
+        self.pauses.clear();
+        self.pause_started = None;
+        self.paused.store(false, Ordering::Release);
 
-        // Get the device's default input config
-        let supported_config = self.device
-            .default_input_config()
-            .map_err(|e| VoicePAError::AudioDevice(format!("Failed to get default input config: {}", e)))?;
+        match self.standby.take() {
+            Some(standby) => {
+                let (rate, channels) = self.output_format.unwrap_or((
+                    self.config.sample_rate,
+                    self.config.channels,
+                ));
+                let sink = self.create_sink(rate, channels)?;
+                standby.sinks.send(sink).map_err(|_| {
+                    VoicePAError::AudioStream("Capture thread is gone".to_string())
+                })?;
 
-        log::info!("Device default config: {:?}", supported_config);
+                // Gaps while in standby happened before the recording
+                self.gaps.lock().unwrap().clear();
+                let buffered = standby.since.elapsed().min(standby.pre_roll);
+                let started_at = SystemTime::now() - buffered;
+                self.started_at = Some(match self.clock.anchor() {
+                    Some(anchor) => started_at.max(anchor),
+                    None => started_at,
+                });
+                log::info!("Recording started from standby with {:?} of pre-roll", buffered);
+            }
+            None => {
+                self.open_capture(None)?;
+                self.started_at = None;
+            }
+        }
 
-        // Use the device's supported configuration
-        let stream_config: StreamConfig = supported_config.into();
+        *is_recording = true;
+        Ok(())
+    }
 
-        let buffer = Arc::clone(&self.buffer);
-        let err_fn = |err| {
-            log::error!("Audio stream error: {}", err);
-        };
+    /// Keep the last `pre_roll` of audio in a fixed-size buffer without
+    /// recording anything, so the next `start_recording` does not miss the
+    /// opening words
+    pub async fn start_standby(&mut self, pre_roll: Duration) -> Result<()> {
+        if *self.is_recording.lock().unwrap() {
+            return Err(VoicePAError::AudioStream("Already recording".to_string()));
+        }
+        if self.standby.is_some() {
+            return Err(VoicePAError::AudioStream("Already in standby".to_string()));
+        }
 
-        let stream = self.device.build_input_stream(
-            &stream_config,
-            move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                let mut buffer = buffer.lock().unwrap();
-                buffer.extend_from_slice(data);
-            },
-            err_fn,
-            None,
-        )?;
+        let sinks = self.open_capture(Some(pre_roll))?;
+        self.standby = Some(Standby {
+            pre_roll,
+            since: Instant::now(),
+            sinks,
+        });
+        Ok(())
+    }
 
-        stream.play()?;
-        self.stream = Some(stream);
-        *is_recording = true;
-        
-        let mut actual = self.actual_config.lock().unwrap();
-        *actual = Some(stream_config.clone());
+    /// Leave standby and discard the buffered audio
+    pub async fn stop_standby(&mut self) -> Result<()> {
+        if self.standby.take().is_none() {
+            return Err(VoicePAError::AudioStream("Not in standby".to_string()));
+        }
 
-        log::info!("Recording started with config: {:?}", stream_config);
+        if let Some(supervisor) = self.supervisor.take() {
+            supervisor.stop()?;
+        }
+        if let Some(consumer) = self.consumer.take() {
+            consumer.stop()?;
+        }
+        log::info!("Standby stopped");
         Ok(())
     }
 
+    /// Check if the recorder is buffering pre-roll without recording
+    pub fn is_standby(&self) -> bool {
+        self.standby.is_some()
+    }
+
+    /// Open the source and start the capture threads, feeding either a
+    /// pre-roll buffer or the configured sink; returns where to send the
+    /// real sink once a pre-roll is armed
+    fn open_capture(&mut self, pre_roll: Option<Duration>) -> Result<mpsc::Sender<SampleSink>> {
+        self.gaps.lock().unwrap().clear();
+        self.capture_ended.store(false, Ordering::Release);
+
+        // Capture in the source's native format and convert afterwards
+        let format = self.source.format()?;
+
+        let (output_rate, mut output_channels) = if self.config.native_format {
+            (format.sample_rate, format.channels)
+        } else {
+            (self.config.sample_rate, self.config.channels)
+        };
+        if self.config.channel_mapping == ChannelMapping::Separate {
+            output_channels = format.channels;
+        }
+        let resampler = AudioResampler::new(
+            format.sample_rate,
+            format.channels,
+            output_rate,
+            output_channels,
+            self.config.resample_quality,
+        )?
+        .with_channel_mapping(self.config.channel_mapping);
+        let sink = match pre_roll {
+            Some(pre_roll) => {
+                let frames = (pre_roll.as_secs_f64() * output_rate as f64) as usize;
+                SampleSink::PreRoll(PreRollBuffer::new(frames, output_channels))
+            }
+            None => self.create_sink(output_rate, output_channels)?,
+        };
+        let mut pipeline = CapturePipeline::new(
+            resampler,
+            sink,
+            Arc::clone(&self.frames),
+            Arc::clone(&self.recorded_frames),
+            Arc::clone(&self.levels),
+        );
+        if self.config.stop_policy.is_enabled() {
+            let monitor = StopMonitor::new(&self.config.stop_policy, output_rate, output_channels);
+            pipeline = pipeline.with_stop_monitor(monitor);
+        }
+
+        // The callback only copies into a preallocated ring; a consumer
+        // thread converts the audio and hands it to the sink and subscribers.
+        // The stream itself lives on a supervisor thread that can reopen it.
+        self.dropped_samples = Arc::new(AtomicU64::new(0));
+        self.clock = Arc::new(CaptureClock::new());
+        let (producer, consumer) = ring_for(&format, Arc::clone(&self.dropped_samples));
+        let (segments, segment_receiver) = mpsc::channel();
+        let (sinks, sink_receiver) = mpsc::channel();
+
+        let context = SupervisorContext {
+            policy: self.config.recovery,
+            output_rate,
+            output_channels,
+            quality: self.config.resample_quality,
+            mapping: self.config.channel_mapping,
+            segments,
+            events: self.events.clone(),
+            active: Arc::clone(&self.active_stream),
+            gaps: Arc::clone(&self.gaps),
+            recorded_frames: Arc::clone(&self.recorded_frames),
+            dropped: Arc::clone(&self.dropped_samples),
+            clock: Arc::clone(&self.clock),
+            failed: Arc::clone(&self.capture_ended),
+        };
+        let supervisor = StreamSupervisor::spawn(self.source.clone(), format, producer, context)?;
+
+        // When a stop policy fires, close the stream and tell subscribers;
+        // the audio is collected by `finish_recording` as usual
+        let release = supervisor.releaser();
+        let events = self.events.clone();
+        let capture_ended = Arc::clone(&self.capture_ended);
+        let on_stop: StopHandler = Box::new(move |reason| {
+            log::info!("Recording stopped automatically: {:?}", reason);
+            capture_ended.store(true, Ordering::Release);
+            release();
+            let _ = events.send(RecorderEvent::AutoStopped { reason });
+        });
+
+        let consumer = match ConsumerThread::spawn(
+            consumer,
+            pipeline,
+            segment_receiver,
+            sink_receiver,
+            Arc::clone(&self.paused),
+            Some(on_stop),
+        ) {
+            Ok(consumer) => consumer,
+            Err(e) => {
+                let _ = supervisor.stop();
+                return Err(e);
+            }
+        };
+        self.consumer = Some(consumer);
+        self.supervisor = Some(supervisor);
+        self.output_format = Some((output_rate, output_channels));
+
+        log::info!("Capture started from {} in {:?}", self.source.name(), format);
+        Ok(sinks)
+    }
+
+    fn create_sink(&self, sample_rate: u32, channels: u16) -> Result<SampleSink> {
+        Ok(match &self.config.spill_path {
+            Some(path) => SampleSink::File(SpillWriter::create(
+                path,
+                &self.config.spill_encoder,
+                sample_rate,
+                channels,
+            )?),
+            None => SampleSink::Memory(Vec::new()),
+        })
+    }
+
     /// Stop recording and return the audio data
+    ///
+    /// Samples are interleaved at `output_sample_rate()` with
+    /// `output_channels()` channels. Recordings that spill to disk are
+    /// rejected with a [`VoicePAError::Config`] and keep running; use
+    /// [`finish_recording`](Self::finish_recording) to get their file.
     pub async fn stop_recording(&mut self) -> Result<Vec<f32>> {
+        if let Some(path) = &self.config.spill_path {
+            return Err(VoicePAError::Config(format!(
+                "Recording is written to {}; use finish_recording to get the file",
+                path.display()
+            )));
+        }
+        Ok(self.finish_recording().await?.samples)
+    }
+
+    /// Stop recording and return the audio together with session bookkeeping
+    pub async fn finish_recording(&mut self) -> Result<Recording> {
         let mut is_recording = self.is_recording.lock().unwrap();
         if !*is_recording {
             return Err(VoicePAError::AudioStream("Not recording".to_string()));
         }
 
         // Stop the stream
-        if let Some(stream) = self.stream.take() {
-            drop(stream);
+        if let Some(supervisor) = self.supervisor.take() {
+            supervisor.stop()?;
         }
-
         *is_recording = false;
+        let output = match self.consumer.take() {
+            Some(consumer) => consumer.stop()?,
+            None => return Err(VoicePAError::AudioStream("Capture thread missing".to_string())),
+        };
 
-        // Get the recorded data
-        let buffer = self.buffer.lock().unwrap();
-        let data = buffer.clone();
+        if let Some((mut pause, since)) = self.pause_started.take() {
+            pause.duration = since.elapsed();
+            self.pauses.push(pause);
+        }
+        self.paused.store(false, Ordering::Release);
 
-        log::info!("Recording stopped. Captured {} samples", data.len());
-        Ok(data)
+        let frames = self.recorded_frames.load(Ordering::Relaxed);
+        log::info!("Recording stopped. Captured {} frames", frames);
+
+        Ok(Recording {
+            samples: output.samples,
+            frames,
+            file: output.file,
+            sample_rate: self.output_sample_rate(),
+            channels: self.output_channels(),
+            started_at: self
+                .started_at
+                .or_else(|| self.clock.anchor())
+                .unwrap_or_else(SystemTime::now),
+            pauses: self.pauses.clone(),
+            gaps: self.gaps.lock().unwrap().clone(),
+            dropouts: output.dropouts,
+            stop_reason: output.stop_reason,
+        })
+    }
+
+    /// Pause recording without ending the session
+    ///
+    /// Audio captured while paused is discarded; the pause is recorded so
+    /// positions in the audio can be mapped back to wall-clock time.
+    pub async fn pause_recording(&mut self) -> Result<()> {
+        if !self.is_recording() {
+            return Err(VoicePAError::AudioStream("Not recording".to_string()));
+        }
+        if self.pause_started.is_some() {
+            return Err(VoicePAError::AudioStream("Already paused".to_string()));
+        }
+
+        self.paused.store(true, Ordering::Release);
+        let pause = PauseInterval {
+            position: Duration::from_secs_f64(self.duration()),
+            started_at: SystemTime::now(),
+            duration: Duration::ZERO,
+        };
+        self.pause_started = Some((pause, Instant::now()));
+
+        log::info!("Recording paused");
+        Ok(())
     }
 
-    /// Check if currently recording
+    /// Resume a paused recording
+    pub async fn resume_recording(&mut self) -> Result<()> {
+        let Some((mut pause, since)) = self.pause_started.take() else {
+            return Err(VoicePAError::AudioStream("Not paused".to_string()));
+        };
+
+        pause.duration = since.elapsed();
+        self.pauses.push(pause);
+        self.paused.store(false, Ordering::Release);
+
+        log::info!("Recording resumed");
+        Ok(())
+    }
+
+    /// Check if the current recording is paused
+    pub fn is_paused(&self) -> bool {
+        self.pause_started.is_some()
+    }
+
+    /// Pauses completed so far in the current recording
+    pub fn pause_intervals(&self) -> &[PauseInterval] {
+        &self.pauses
+    }
+
+    /// Stream gaps closed so far in the current recording
+    pub fn stream_gaps(&self) -> Vec<StreamGap> {
+        self.gaps.lock().unwrap().clone()
+    }
+
+    /// Subscribe to stream errors, losses and recoveries
+    ///
+    /// Only events sent after subscribing are received. A subscriber that
+    /// falls more than a few dozen events behind skips the oldest ones.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<RecorderEvent> {
+        self.events.subscribe()
+    }
+
+    /// Subscribe to audio frames while recording
+    ///
+    /// Frames hold `frame_size` sample frames in the same format as
+    /// `stop_recording` returns; the last frame of a recording may be
+    /// shorter. Up to `capacity` frames are queued before new ones are
+    /// dropped. The subscription ends when recording stops and can be taken
+    /// out before or during a recording.
+    pub fn subscribe_frames(&self, frame_size: usize, capacity: usize) -> FrameSubscription {
+        self.frames.lock().unwrap().subscribe(frame_size, capacity)
+    }
+
+    /// Input level over the most recent 50 ms window; silent when not
+    /// recording
+    ///
+    /// Levels are measured on the native audio, before conversion, and keep
+    /// updating while paused so a meter can show the input is still live.
+    pub fn input_level(&self) -> InputLevel {
+        *self.levels.borrow()
+    }
+
+    /// Watch the input level as it updates, about every 50 ms while recording
+    pub fn subscribe_levels(&self) -> watch::Receiver<InputLevel> {
+        self.levels.subscribe()
+    }
+
+    /// Describe the device, native capture format and output format
+    pub fn diagnostics(&self) -> RecorderDiagnostics {
+        RecorderDiagnostics {
+            device_name: self.device_name(),
+            native_sample_format: self.active_stream().map(|active| active.format.sample_format),
+            native_sample_rate: self.actual_sample_rate(),
+            native_channels: self.actual_channels(),
+            output_sample_rate: self.output_sample_rate(),
+            output_channels: self.output_channels(),
+            dropped_samples: self.dropped_samples(),
+            capture_latency: self.clock.latency(),
+        }
+    }
+
+    /// Number of samples dropped in the current or last recording because
+    /// the consumer thread could not keep up with the device
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped_samples.load(Ordering::Relaxed)
+    }
+
+    /// Check if currently recording; a paused session or one waiting for
+    /// its stream to be reopened still counts, one whose stream failed for
+    /// good or that a stop policy ended does not
+    ///
+    /// Such a recording must still be finished to collect its audio.
     pub fn is_recording(&self) -> bool {
-        *self.is_recording.lock().unwrap()
+        *self.is_recording.lock().unwrap() && !self.capture_ended.load(Ordering::Acquire)
     }
 
     /// Get the current configuration
@@ -140,31 +562,65 @@ impl AudioRecorder {
         &self.config
     }
 
+    /// Get the name of the input device being recorded from
+    ///
+    /// After a stream recovery this is the device capture moved to.
+    pub fn device_name(&self) -> String {
+        match self.active_stream() {
+            Some(active) => active.name,
+            None => self.source.name(),
+        }
+    }
+
     /// Get the actual sample rate being used
     pub fn actual_sample_rate(&self) -> u32 {
-        let actual = self.actual_config.lock().unwrap();
-        actual.as_ref().map(|c| c.sample_rate.0).unwrap_or(self.config.sample_rate)
+        self.active_stream()
+            .map(|active| active.format.sample_rate)
+            .unwrap_or(self.config.sample_rate)
     }
 
     /// Get the actual channels being used
     pub fn actual_channels(&self) -> u16 {
-        let actual = self.actual_config.lock().unwrap();
-        actual.as_ref().map(|c| c.channels).unwrap_or(self.config.channels)
+        self.active_stream()
+            .map(|active| active.format.channels)
+            .unwrap_or(self.config.channels)
+    }
+
+    /// Get the sample rate of the audio returned by `stop_recording`
+    pub fn output_sample_rate(&self) -> u32 {
+        if self.config.native_format {
+            self.output_format.map(|(rate, _)| rate).unwrap_or_else(|| self.actual_sample_rate())
+        } else {
+            self.config.sample_rate
+        }
+    }
+
+    /// Get the channel count of the audio returned by `stop_recording`
+    pub fn output_channels(&self) -> u16 {
+        if self.config.native_format || self.config.channel_mapping == ChannelMapping::Separate {
+            self.output_format.map(|(_, channels)| channels).unwrap_or_else(|| self.actual_channels())
+        } else {
+            self.config.channels
+        }
+    }
+
+    fn active_stream(&self) -> Option<ActiveStream> {
+        self.active_stream.lock().unwrap().clone()
     }
 
     /// Get the duration of recorded audio in seconds
     pub fn duration(&self) -> f64 {
-        let buffer = self.buffer.lock().unwrap();
-        let samples = buffer.len() as f64;
-        let rate = self.actual_sample_rate() as f64;
-        let channels = self.actual_channels() as f64;
-        samples / (rate * channels)
+        let frames = self.recorded_frames.load(Ordering::Relaxed) as f64;
+        frames / self.output_sample_rate() as f64
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::audio::autostop::StopReason;
+    use crate::audio::signal::{Signal, SignalSource, SyntheticSpeaker};
+    use crate::audio::source::Pacing;
 
     #[test]
     fn test_audio_config_default() {
@@ -175,15 +631,171 @@ mod tests {
 
     #[tokio::test]
     async fn test_recorder_creation() {
-        // This test may fail if no audio device is available
-        match AudioRecorder::new() {
-            Ok(recorder) => {
-                assert!(!recorder.is_recording());
-                assert_eq!(recorder.config().sample_rate, 16000);
-            }
-            Err(e) => {
-                println!("Skipping test - no audio device: {}", e);
+        let source = SignalSource::new(Signal::Sine { frequency: 440.0, amplitude: 0.5 }, 48000, 2);
+        let mut recorder = AudioRecorder::with_source(source, AudioConfig::default());
+
+        assert!(!recorder.is_recording());
+        assert_eq!(recorder.config().sample_rate, 16000);
+        assert_eq!(recorder.device_name(), "Sine 440 Hz");
+        assert_eq!((recorder.output_sample_rate(), recorder.output_channels()), (16000, 1));
+        assert_eq!(recorder.duration(), 0.0);
+        assert!(recorder.stop_recording().await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs an audio input device"]
+    async fn test_device_recorder_creation() {
+        let recorder = AudioRecorder::new().unwrap();
+        assert!(!recorder.is_recording());
+        assert_eq!(recorder.config().sample_rate, 16000);
+    }
+
+    #[tokio::test]
+    async fn test_records_generated_signal() {
+        let source = SignalSource::new(
+            Signal::Sine {
+                frequency: 440.0,
+                amplitude: 0.5,
+            },
+            48000,
+            2,
+        )
+        .with_length(Duration::from_secs(1))
+        .with_pacing(Pacing::Unthrottled);
+        let mut recorder = AudioRecorder::with_source(source, AudioConfig::default());
+        let mut events = recorder.subscribe_events();
+
+        recorder.start_recording().await.unwrap();
+        let ended = tokio::time::timeout(Duration::from_secs(10), events.recv()).await;
+        assert_eq!(ended.unwrap().unwrap(), RecorderEvent::SourceEnded);
+        let level = recorder.input_level();
+        assert!((level.peak_dbfs + 6.02).abs() < 0.1, "{:?}", level);
+        assert!((level.rms - 0.5 / 2f32.sqrt()).abs() < 0.01, "{:?}", level);
+
+        let recording = recorder.finish_recording().await.unwrap();
+        assert_eq!(recorder.input_level(), InputLevel::default());
+
+        assert_eq!(recording.sample_rate, 16000);
+        assert_eq!(recording.frames, 16000);
+        assert_eq!(recording.samples.len(), 16000);
+        assert!(recording.dropouts.is_empty());
+        let peak = recording.samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 0.5).abs() < 0.02, "peak {}", peak);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_recorder_stops_its_threads() {
+        let source = SignalSource::new(Signal::Silence, 16000, 1);
+        let mut recorder = AudioRecorder::with_source(source, AudioConfig::default());
+        let mut frames = recorder.subscribe_frames(160, 64);
+        recorder.start_recording().await.unwrap();
+        assert!(frames.recv().await.is_some());
+
+        // The supervisor thread shares the active stream, the consumer
+        // thread the frame dispatcher
+        let supervisor = Arc::downgrade(&recorder.active_stream);
+        let consumer = Arc::downgrade(&recorder.frames);
+        drop(recorder);
+        assert!(supervisor.upgrade().is_none());
+        assert!(consumer.upgrade().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stop_recording_rejects_spilled_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill.wav");
+        let source = SignalSource::new(Signal::Silence, 16000, 1)
+            .with_length(Duration::from_secs(1))
+            .with_pacing(Pacing::Unthrottled);
+        let config = AudioConfig {
+            spill_path: Some(path.clone()),
+            ..AudioConfig::default()
+        };
+        let mut recorder = AudioRecorder::with_source(source, config);
+        let mut events = recorder.subscribe_events();
+
+        recorder.start_recording().await.unwrap();
+        let ended = tokio::time::timeout(Duration::from_secs(10), events.recv()).await;
+        assert_eq!(ended.unwrap().unwrap(), RecorderEvent::SourceEnded);
+
+        match recorder.stop_recording().await {
+            Err(VoicePAError::Config(message)) => {
+                assert!(message.contains(&path.display().to_string()), "{}", message)
             }
+            other => panic!("expected a spill file error, got {:?}", other.map(|s| s.len())),
         }
+        assert!(recorder.is_recording());
+
+        let recording = recorder.finish_recording().await.unwrap();
+        assert_eq!(recording.file.as_deref(), Some(path.as_path()));
+        assert_eq!(hound::WavReader::open(&path).unwrap().duration(), 16000);
+    }
+
+    #[tokio::test]
+    async fn test_silence_policy_stops_and_trims() {
+        let speaker = SyntheticSpeaker { pitch: 150.0, amplitude: 0.5 };
+        let source = SignalSource::new(
+            Signal::Conversation {
+                speakers: vec![speaker],
+                turn: Duration::from_secs(1),
+                pause: Duration::from_secs(10),
+            },
+            16000,
+            1,
+        )
+        .with_length(Duration::from_secs(10))
+        .with_pacing(Pacing::Unthrottled);
+        let config = AudioConfig {
+            stop_policy: StopPolicy {
+                silence_timeout: Some(Duration::from_secs(2)),
+                ..StopPolicy::default()
+            },
+            ..AudioConfig::default()
+        };
+        let mut recorder = AudioRecorder::with_source(source, config);
+        let mut events = recorder.subscribe_events();
+
+        recorder.start_recording().await.unwrap();
+        let stopped = tokio::time::timeout(Duration::from_secs(10), events.recv()).await;
+        assert_eq!(
+            stopped.unwrap().unwrap(),
+            RecorderEvent::AutoStopped { reason: StopReason::Silence }
+        );
+        assert!(!recorder.is_recording());
+
+        // One second of speech plus a short tail survives the trim
+        let recording = recorder.finish_recording().await.unwrap();
+        assert_eq!(recording.stop_reason, Some(StopReason::Silence));
+        assert_eq!(recording.samples.len() as u64, recording.frames);
+        assert!((19200..=22400).contains(&recording.frames), "{}", recording.frames);
+    }
+
+    #[tokio::test]
+    async fn test_standby_prepends_pre_roll() {
+        let source = SignalSource::new(
+            Signal::Sine {
+                frequency: 440.0,
+                amplitude: 0.5,
+            },
+            16000,
+            1,
+        );
+        let mut recorder = AudioRecorder::with_source(source, AudioConfig::default());
+
+        recorder.start_standby(Duration::from_millis(500)).await.unwrap();
+        assert!(recorder.is_standby() && !recorder.is_recording());
+        tokio::time::sleep(Duration::from_millis(800)).await;
+        assert_eq!(recorder.duration(), 0.0);
+
+        let armed_at = SystemTime::now();
+        recorder.start_recording().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let recording = recorder.finish_recording().await.unwrap();
+
+        // Half a second of pre-roll plus what was recorded after arming
+        let seconds = recording.duration().as_secs_f64();
+        assert!((0.7..0.95).contains(&seconds), "{}", seconds);
+        let lead = armed_at.duration_since(recording.started_at).unwrap();
+        assert!((450..=550).contains(&lead.as_millis()), "{:?}", lead);
     }
 }
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+/// Block over which [`ChannelMapping::Loudest`] compares channels
+const LOUDEST_BLOCK: Duration = Duration::from_millis(100);
+
+/// How the input's channels become the recorded channels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelMapping {
+    /// Average input channels down to the configured channel count
+    #[default]
+    Average,
+    /// Record only this input channel, counting from 0; out of range picks
+    /// the last channel
+    Pick(u16),
+    /// Record whichever input channel is loudest in each 100 ms block, e.g.
+    /// for an interface with one microphone per speaker
+    Loudest,
+    /// Keep every input channel, ignoring the configured channel count;
+    /// [`Recording::split_channels`](crate::audio::Recording::split_channels)
+    /// separates them into tracks
+    Separate,
+}
+
+impl ChannelMapping {
+    /// Channels left after mapping `input` channels, before conversion to
+    /// the output layout
+    pub fn mapped_channels(&self, input: u16) -> u16 {
+        match self {
+            ChannelMapping::Average | ChannelMapping::Separate => input,
+            ChannelMapping::Pick(_) | ChannelMapping::Loudest => 1,
+        }
+    }
+
+    /// Map a whole interleaved buffer; returns the samples and their channel
+    /// count
+    pub fn apply(&self, samples: &[f32], channels: u16, sample_rate: u32) -> (Vec<f32>, u16) {
+        let mut mapper = ChannelMapper::new(*self, channels, sample_rate);
+        let mut output = Vec::with_capacity(samples.len());
+        mapper.process(samples, &mut output);
+        mapper.flush(&mut output);
+        (output, mapper.output_channels())
+    }
+}
+
+/// Streaming form of a [`ChannelMapping`]
+pub(crate) struct ChannelMapper {
+    mapping: ChannelMapping,
+    channels: usize,
+    block_frames: usize,
+    /// Loudest: input held back until its block is complete
+    block: Vec<f32>,
+}
+
+impl ChannelMapper {
+    pub fn new(mapping: ChannelMapping, channels: u16, sample_rate: u32) -> Self {
+        let block_frames = (LOUDEST_BLOCK.as_secs_f64() * sample_rate as f64) as usize;
+        Self {
+            mapping,
+            channels: channels.max(1) as usize,
+            block_frames: block_frames.max(1),
+            block: Vec::new(),
+        }
+    }
+
+    pub fn output_channels(&self) -> u16 {
+        self.mapping.mapped_channels(self.channels as u16)
+    }
+
+    /// Map interleaved input onto `out`; `Loudest` holds back up to one block
+    pub fn process(&mut self, samples: &[f32], out: &mut Vec<f32>) {
+        match self.mapping {
+            ChannelMapping::Average | ChannelMapping::Separate => out.extend_from_slice(samples),
+            ChannelMapping::Pick(channel) => {
+                let channel = (channel as usize).min(self.channels - 1);
+                out.extend(samples.chunks_exact(self.channels).map(|frame| frame[channel]));
+            }
+            ChannelMapping::Loudest => {
+                let block_len = self.block_frames * self.channels;
+                let mut samples = samples;
+                while !samples.is_empty() {
+                    let take = (block_len - self.block.len()).min(samples.len());
+                    self.block.extend_from_slice(&samples[..take]);
+                    samples = &samples[take..];
+                    if self.block.len() == block_len {
+                        self.emit_loudest(out);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Emit whatever input is still held back
+    pub fn flush(&mut self, out: &mut Vec<f32>) {
+        if !self.block.is_empty() {
+            self.emit_loudest(out);
+        }
+    }
+
+    fn emit_loudest(&mut self, out: &mut Vec<f32>) {
+        let mut energy = vec![0.0f64; self.channels];
+        for frame in self.block.chunks_exact(self.channels) {
+            for (sum, &sample) in energy.iter_mut().zip(frame) {
+                *sum += (sample as f64) * (sample as f64);
+            }
+        }
+        let loudest = energy
+            .iter()
+            .enumerate()
+            .fold(0, |best, (channel, &e)| if e > energy[best] { channel } else { best });
+
+        out.extend(self.block.chunks_exact(self.channels).map(|frame| frame[loudest]));
+        self.block.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_and_separate() {
+        let stereo = [0.1, 0.9, 0.2, 0.8, 0.3, 0.7];
+
+        assert_eq!(ChannelMapping::Pick(1).apply(&stereo, 2, 1000), (vec![0.9, 0.8, 0.7], 1));
+        assert_eq!(ChannelMapping::Pick(5).apply(&stereo, 2, 1000).0, vec![0.9, 0.8, 0.7]);
+        assert_eq!(ChannelMapping::Separate.apply(&stereo, 2, 1000), (stereo.to_vec(), 2));
+    }
+
+    #[test]
+    fn test_loudest_channel_per_block() {
+        // 100 ms blocks at 100 Hz are 10 frames: the left channel talks in
+        // the first block, the right one in the second and the partial third
+        let mut stereo = Vec::new();
+        for frame in 0..25 {
+            let (left, right) = if frame < 10 { (0.5, 0.1) } else { (0.1, -0.6) };
+            stereo.extend([left, right]);
+        }
+
+        let mut mapper = ChannelMapper::new(ChannelMapping::Loudest, 2, 100);
+        let mut mono = Vec::new();
+        for chunk in stereo.chunks(6) {
+            mapper.process(chunk, &mut mono);
+        }
+        assert_eq!(mono.len(), 20);
+        mapper.flush(&mut mono);
+
+        assert_eq!(mono, [vec![0.5; 10], vec![-0.6; 15]].concat());
+    }
+}
@@ -0,0 +1,467 @@
+use std::io::{Cursor, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{
+    CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS,
+};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, Packet};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+#[cfg(feature = "opus")]
+use crate::audio::opus::OpusPacketDecoder;
+use crate::audio::resampler::{AudioResampler, ResampleQuality};
+use crate::audio::webm;
+use crate::utils::error::{Result, VoicePAError};
+
+/// Interleaved samples decoded from a file, normalized to -1.0..=1.0
+#[derive(Debug, Clone)]
+pub struct DecodedAudio {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl DecodedAudio {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// Convert to another sample rate and channel count, e.g. 16 kHz mono
+    /// before voice activity detection or transcription
+    pub fn convert(&self, sample_rate: u32, channels: u16) -> Result<DecodedAudio> {
+        let samples = AudioResampler::convert(
+            &self.samples,
+            self.sample_rate,
+            self.channels,
+            sample_rate,
+            channels,
+            ResampleQuality::Balanced,
+        )?;
+        Ok(DecodedAudio {
+            samples,
+            sample_rate,
+            channels,
+        })
+    }
+}
+
+pub trait AudioDecoder {
+    fn decode(&self, data: &[u8]) -> Result<DecodedAudio>;
+}
+
+/// Decodes WAV (any bit depth), FLAC, MP3, AAC/M4A and Ogg/Vorbis, and the
+/// audio track of WebM/Matroska and MP4 recordings
+///
+/// The container is detected from the data itself; a file extension only
+/// serves as a hint. Opus tracks, as recorded by browsers, need the `opus`
+/// feature.
+#[derive(Default)]
+pub struct FileDecoder {
+    extension: Option<String>,
+}
+
+impl FileDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extension of the original file, without the dot, to help detect
+    /// formats without a clear signature such as raw AAC
+    pub fn with_extension_hint(mut self, extension: impl Into<String>) -> Self {
+        self.extension = Some(extension.into());
+        self
+    }
+
+    /// Decode a file from disk, using its extension as a hint
+    pub fn decode_file(&self, path: impl AsRef<Path>) -> Result<DecodedAudio> {
+        let path = path.as_ref();
+        let extension = self
+            .extension
+            .clone()
+            .or_else(|| path.extension().map(|e| e.to_string_lossy().into_owned()));
+        decode_data(std::fs::read(path)?, extension.as_deref())
+    }
+}
+
+impl AudioDecoder for FileDecoder {
+    fn decode(&self, data: &[u8]) -> Result<DecodedAudio> {
+        decode_data(data.to_vec(), self.extension.as_deref())
+    }
+}
+
+fn decode_data(data: Vec<u8>, extension: Option<&str>) -> Result<DecodedAudio> {
+    // Symphonia needs cues to read Matroska, which unfinalized browser
+    // recordings lack, so WebM goes through our own demuxer
+    if webm::is_matroska(&data) {
+        let track = webm::demux(&data)?;
+        let mut decoder = TrackDecoder::new(&track.params)?;
+        let origin = track.packets.iter().find_map(|packet| packet.timestamp);
+        for (index, packet) in track.packets.into_iter().enumerate() {
+            // Audio after a gap in the recording stays at its own time
+            if let (Some(timestamp), Some(origin)) = (packet.timestamp, origin) {
+                decoder.pad_to(timestamp.saturating_sub(origin));
+            }
+            decoder.push(&Packet::new_from_slice(0, index as u64, 0, packet.data))?;
+        }
+        return decoder.finish();
+    }
+
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+    // Gapless playback trims the encoder delay and padding of MP3 and AAC
+    let format_options = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &format_options,
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| VoicePAError::Decoding("No audio track found".to_string()))?;
+    let mut track_id = track.id;
+    let mut decoder = TrackDecoder::new(&track.codec_params)?;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            // The next stream of a chained Ogg file begins; its audio is
+            // appended if it has the same format
+            Err(SymphoniaError::ResetRequired) => {
+                let track = format
+                    .tracks()
+                    .iter()
+                    .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+                    .ok_or_else(|| {
+                        VoicePAError::Decoding("Chained stream has no audio track".to_string())
+                    })?;
+                track_id = track.id;
+                decoder.restart(&track.codec_params)?;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() == track_id {
+            decoder.push(&packet)?;
+        }
+    }
+    decoder.finish()
+}
+
+/// Packets starting less than this after the audio decoded before them are
+/// taken to follow on directly; smaller differences come from decoder delay
+/// and timecode rounding
+const MIN_GAP: Duration = Duration::from_millis(50);
+
+/// Turns one track's packets into interleaved samples
+struct TrackDecoder {
+    codec: Codec,
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+enum Codec {
+    Symphonia {
+        decoder: Box<dyn Decoder>,
+        buffer: Option<SampleBuffer<f32>>,
+    },
+    #[cfg(feature = "opus")]
+    Opus(OpusPacketDecoder),
+}
+
+impl TrackDecoder {
+    fn new(params: &CodecParameters) -> Result<Self> {
+        let (codec, sample_rate, channels) = make_codec(params)?;
+        Ok(Self {
+            codec,
+            samples: Vec::new(),
+            sample_rate,
+            channels,
+        })
+    }
+
+    /// Continue with the packets of another stream, keeping the audio
+    /// decoded so far
+    fn restart(&mut self, params: &CodecParameters) -> Result<()> {
+        let (codec, sample_rate, channels) = make_codec(params)?;
+        self.codec = codec;
+        if sample_rate == 0 || channels == 0 {
+            return Ok(());
+        }
+        let format = (sample_rate, channels);
+        check_format(&self.samples, (self.sample_rate, self.channels), format)?;
+        (self.sample_rate, self.channels) = format;
+        Ok(())
+    }
+
+    /// Fill with silence up to `position` on the track's timeline if the
+    /// audio decoded so far ends at least [`MIN_GAP`] before it
+    fn pad_to(&mut self, position: Duration) {
+        if self.samples.is_empty() || self.sample_rate == 0 {
+            return;
+        }
+        let channels = self.channels.max(1) as usize;
+        let sample_rate = self.sample_rate as f64;
+        let frames = |duration: Duration| (duration.as_secs_f64() * sample_rate).round() as usize;
+
+        let decoded = self.samples.len() / channels;
+        let target = frames(position);
+        if target >= decoded + frames(MIN_GAP) {
+            log::debug!("Filling {} missing frames before {:?}", target - decoded, position);
+            self.samples.resize(target * channels, 0.0);
+        }
+    }
+
+    fn push(&mut self, packet: &Packet) -> Result<()> {
+        match &mut self.codec {
+            Codec::Symphonia { decoder, buffer } => {
+                let decoded = match decoder.decode(packet) {
+                    Ok(decoded) => decoded,
+                    // A corrupt packet only loses its own audio
+                    Err(SymphoniaError::DecodeError(e)) => {
+                        log::warn!("Skipping undecodable packet: {}", e);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                let spec = *decoded.spec();
+                let format = (spec.rate, spec.channels.count() as u16);
+                check_format(&self.samples, (self.sample_rate, self.channels), format)?;
+                (self.sample_rate, self.channels) = format;
+                let needed = decoded.capacity() * spec.channels.count();
+                if buffer.as_ref().is_none_or(|b| b.capacity() < needed) {
+                    *buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                }
+                if let Some(buffer) = buffer.as_mut() {
+                    buffer.copy_interleaved_ref(decoded);
+                    self.samples.extend_from_slice(buffer.samples());
+                }
+            }
+            #[cfg(feature = "opus")]
+            Codec::Opus(decoder) => {
+                if let Err(e) = decoder.decode(&packet.data, &mut self.samples) {
+                    log::warn!("Skipping undecodable packet: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<DecodedAudio> {
+        if self.sample_rate == 0 || self.channels == 0 {
+            return Err(VoicePAError::Decoding(
+                "Audio track has no sample rate or channel layout".to_string(),
+            ));
+        }
+        Ok(DecodedAudio {
+            samples: self.samples,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+        })
+    }
+}
+
+/// Audio in a new `(sample_rate, channels)` format cannot be appended to
+/// samples decoded in another
+fn check_format(samples: &[f32], current: (u32, u16), new: (u32, u16)) -> Result<()> {
+    if samples.is_empty() || current == new {
+        return Ok(());
+    }
+    Err(VoicePAError::Decoding(format!(
+        "Audio format changes mid-stream from {} Hz, {} channels to {} Hz, {} channels",
+        current.0, current.1, new.0, new.1
+    )))
+}
+
+/// Decoder for `params` with the sample rate and channel count it reports,
+/// zero where unknown
+fn make_codec(params: &CodecParameters) -> Result<(Codec, u32, u16)> {
+    let sample_rate = params.sample_rate.unwrap_or(0);
+    let channels = params
+        .channels
+        .map(|channels| channels.count() as u16)
+        .unwrap_or(0);
+
+    if params.codec == CODEC_TYPE_OPUS {
+        return opus_codec(params, channels);
+    }
+    let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+    let codec = Codec::Symphonia {
+        decoder,
+        buffer: None,
+    };
+    Ok((codec, sample_rate, channels))
+}
+
+/// Opus is decoded by libopus at 48 kHz rather than by Symphonia
+#[cfg(feature = "opus")]
+fn opus_codec(params: &CodecParameters, channels: u16) -> Result<(Codec, u32, u16)> {
+    let decoder = OpusPacketDecoder::new(params.extra_data.as_deref(), channels)?;
+    let channels = decoder.channels();
+    Ok((Codec::Opus(decoder), 48000, channels))
+}
+
+#[cfg(not(feature = "opus"))]
+fn opus_codec(_: &CodecParameters, _: u16) -> Result<(Codec, u32, u16)> {
+    Err(VoicePAError::Decoding(
+        "Decoding Opus audio requires the opus feature".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::encoding::AudioEncoder;
+    use crate::audio::flac::FlacEncoder;
+    use hound::{SampleFormat, WavSpec, WavWriter};
+    use symphonia::core::audio::Channels;
+    use symphonia::core::codecs::CODEC_TYPE_PCM_S16LE;
+
+    fn ramp(frames: usize, channels: u16) -> Vec<f32> {
+        (0..frames * channels as usize)
+            .map(|i| (i % 200) as f32 / 100.0 - 1.0)
+            .collect()
+    }
+
+    fn wav(samples: &[f32], bits: u16, format: SampleFormat) -> Vec<u8> {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: bits,
+            sample_format: format,
+        };
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut cursor, spec).unwrap();
+        let scale = ((1i64 << (bits - 1)) - 1) as f32;
+        for &sample in samples {
+            match (format, bits) {
+                (SampleFormat::Float, _) => writer.write_sample(sample).unwrap(),
+                (_, 8) => writer.write_sample((sample * scale) as i8).unwrap(),
+                (_, 16) => writer.write_sample((sample * scale) as i16).unwrap(),
+                _ => writer.write_sample((sample * scale) as i32).unwrap(),
+            }
+        }
+        writer.finalize().unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn test_decodes_wav_bit_depths() {
+        let samples = ramp(1000, 2);
+        for (bits, format, tolerance) in [
+            (8, SampleFormat::Int, 0.02),
+            (16, SampleFormat::Int, 1e-4),
+            (24, SampleFormat::Int, 1e-6),
+            (32, SampleFormat::Int, 1e-6),
+            (32, SampleFormat::Float, 0.0),
+        ] {
+            let decoded = FileDecoder::new().decode(&wav(&samples, bits, format)).unwrap();
+            assert_eq!((decoded.sample_rate, decoded.channels), (22050, 2));
+            assert_eq!(decoded.samples.len(), samples.len(), "{} bit", bits);
+            for (a, b) in decoded.samples.iter().zip(&samples) {
+                assert!((a - b).abs() <= tolerance, "{} bit: {} vs {}", bits, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decodes_flac_and_converts() {
+        let samples = ramp(4410, 1);
+        let flac = FlacEncoder::new().encode(&samples, 44100, 1).unwrap();
+
+        let decoded = FileDecoder::new().decode(&flac).unwrap();
+        assert_eq!((decoded.sample_rate, decoded.channels), (44100, 1));
+        assert_eq!(decoded.duration(), Duration::from_millis(100));
+
+        let converted = decoded.convert(16000, 1).unwrap();
+        assert_eq!(converted.frames(), 1600);
+
+        assert!(FileDecoder::new().decode(b"definitely not audio").is_err());
+    }
+
+    /// Zero crossings per second and RMS level of `audio` after its first
+    /// 100 ms, for mono test tones
+    fn tone_stats(audio: &DecodedAudio) -> (f64, f32) {
+        let steady = &audio.samples[audio.sample_rate as usize / 10..];
+        let crossings = steady.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count();
+        let rms = (steady.iter().map(|s| s * s).sum::<f32>() / steady.len() as f32).sqrt();
+        let seconds = steady.len() as f64 / audio.sample_rate as f64;
+        (crossings as f64 / seconds, rms)
+    }
+
+    #[cfg(feature = "opus")]
+    #[test]
+    fn test_decodes_mediarecorder_webm_opus() {
+        // A 440 Hz tone at half scale in 20 ms libopus packets, muxed like
+        // Chrome's MediaRecorder output; see testdata/README.md
+        let webm = include_bytes!("../../testdata/tone-mediarecorder.webm");
+        let decoded = FileDecoder::new().decode(webm).unwrap();
+        assert_eq!((decoded.sample_rate, decoded.channels), (48000, 1));
+        // 51 packets of 960 samples, less the 312 samples of pre-skip
+        assert_eq!(decoded.frames(), 48648);
+
+        let (crossings, rms) = tone_stats(&decoded);
+        assert!((crossings - 880.0).abs() < 5.0, "{}", crossings);
+        assert!((rms - 0.3536).abs() < 0.02, "{}", rms);
+    }
+
+    #[test]
+    fn test_decodes_fragmented_mp4_aac() {
+        // AAC-LC in three movie fragments with no sample tables in the
+        // movie header, as MediaRecorder writes MP4; see testdata/README.md
+        let mp4 = include_bytes!("../../testdata/tone-fragmented.m4a");
+        let decoded = FileDecoder::new().decode(mp4).unwrap();
+        assert_eq!((decoded.sample_rate, decoded.channels), (48000, 1));
+        assert_eq!(decoded.frames(), 47 * 1024);
+
+        // A 1171.875 Hz tone at quarter scale
+        let (crossings, rms) = tone_stats(&decoded);
+        assert!((crossings - 2343.75).abs() < 5.0, "{}", crossings);
+        assert!((rms - 0.1768).abs() < 0.01, "{}", rms);
+    }
+
+    #[test]
+    fn test_chained_streams_must_keep_their_format() {
+        let pcm = |sample_rate, channels| {
+            let mut params = CodecParameters::new();
+            params
+                .for_codec(CODEC_TYPE_PCM_S16LE)
+                .with_sample_rate(sample_rate)
+                .with_channels(channels)
+                .with_bits_per_coded_sample(16)
+                .with_bits_per_sample(16)
+                .with_max_frames_per_packet(64);
+            params
+        };
+        let packet = Packet::new_from_slice(0, 0, 0, &[0u8; 64]);
+
+        let mut decoder = TrackDecoder::new(&pcm(8000, Channels::FRONT_LEFT)).unwrap();
+        decoder.push(&packet).unwrap();
+        decoder.restart(&pcm(8000, Channels::FRONT_LEFT)).unwrap();
+        decoder.push(&packet).unwrap();
+
+        let stereo = pcm(16000, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        assert!(matches!(decoder.restart(&stereo), Err(VoicePAError::Decoding(_))));
+        assert_eq!(decoder.finish().unwrap().frames(), 64);
+    }
+}
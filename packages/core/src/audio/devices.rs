@@ -0,0 +1,277 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host, SampleFormat};
+use crate::utils::error::{Result, VoicePAError};
+
+/// Selects which input device a recorder opens
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// The default input device of the default host
+    #[default]
+    Default,
+    /// A stable device ID as reported by [`list_input_devices`]
+    Id(String),
+    /// A case-insensitive substring of the device name
+    Name(String),
+    /// A source capturing what the system plays, such as a PulseAudio or
+    /// PipeWire `.monitor` source
+    Monitor,
+}
+
+/// An audio host (backend) available on this platform
+#[derive(Debug, Clone)]
+pub struct HostInfo {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// A range of stream configurations supported by an input device
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupportedInputConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: SampleFormat,
+}
+
+/// An input device together with the configurations it supports
+#[derive(Debug, Clone)]
+pub struct InputDeviceInfo {
+    /// Stable ID of the form `<host>:<device name>`, with a `#n` suffix
+    /// when a host reports several devices under the same name
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    pub is_default: bool,
+    pub supported_configs: Vec<SupportedInputConfig>,
+}
+
+/// List the audio hosts compiled into this build
+pub fn list_hosts() -> Vec<HostInfo> {
+    let default_id = cpal::default_host().id();
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| HostInfo {
+            id: host_key(id),
+            name: id.name().to_string(),
+            is_default: id == default_id,
+        })
+        .collect()
+}
+
+/// List the input devices of every available host
+///
+/// Enumeration is best effort: a host that cannot be opened, such as JACK
+/// without a running server, or a device that does not report its name or
+/// configurations is logged and skipped rather than failing the whole list.
+pub fn list_input_devices() -> Vec<InputDeviceInfo> {
+    let mut devices = Vec::new();
+
+    for (host, entries) in enumerate_hosts() {
+        let host_name = host.id().name().to_string();
+        for entry in entries {
+            let supported_configs = entry
+                .device
+                .supported_input_configs()
+                .map(|configs| {
+                    configs
+                        .map(|c| SupportedInputConfig {
+                            channels: c.channels(),
+                            min_sample_rate: c.min_sample_rate().0,
+                            max_sample_rate: c.max_sample_rate().0,
+                            sample_format: c.sample_format(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_else(|e| {
+                    log::warn!("Failed to query configs of {}: {}", entry.name, e);
+                    Vec::new()
+                });
+
+            devices.push(InputDeviceInfo {
+                id: entry.id,
+                name: entry.name,
+                host: host_name.clone(),
+                is_default: entry.is_default,
+                supported_configs,
+            });
+        }
+    }
+
+    devices
+}
+
+/// Open the device described by `selector`, falling back to the default
+/// input device when the requested one is not present
+pub(crate) fn resolve_device(selector: &DeviceSelector) -> Result<Device> {
+    if *selector != DeviceSelector::Default {
+        let mut candidates = Vec::new();
+        for (_, entries) in enumerate_hosts() {
+            candidates.extend(entries);
+        }
+
+        let names: Vec<(&str, &str)> = candidates
+            .iter()
+            .map(|e| (e.id.as_str(), e.name.as_str()))
+            .collect();
+
+        if let Some(index) = select_device(selector, &names)? {
+            return Ok(candidates.swap_remove(index).device);
+        }
+    }
+
+    cpal::default_host()
+        .default_input_device()
+        .ok_or_else(|| VoicePAError::AudioDevice("No input device available".to_string()))
+}
+
+struct DeviceEntry {
+    id: String,
+    name: String,
+    is_default: bool,
+    device: Device,
+}
+
+fn host_key(id: cpal::HostId) -> String {
+    id.name().to_lowercase()
+}
+
+fn enumerate_hosts() -> Vec<(Host, Vec<DeviceEntry>)> {
+    let mut hosts = Vec::new();
+
+    for host_id in cpal::available_hosts() {
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(e) => {
+                log::debug!("Skipping unavailable host {}: {}", host_id.name(), e);
+                continue;
+            }
+        };
+
+        let default_name = host.default_input_device().and_then(|d| d.name().ok());
+        let devices = match host.input_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                log::warn!("Failed to list devices for {}: {}", host_id.name(), e);
+                continue;
+            }
+        };
+
+        let mut seen: Vec<String> = Vec::new();
+        let mut entries = Vec::new();
+        for device in devices {
+            let name = match device.name() {
+                Ok(name) => name,
+                Err(e) => {
+                    log::debug!("Skipping unnamed device on {}: {}", host_id.name(), e);
+                    continue;
+                }
+            };
+            let occurrence = seen.iter().filter(|n| **n == name).count();
+            seen.push(name.clone());
+
+            entries.push(DeviceEntry {
+                id: device_id(&host_key(host_id), &name, occurrence),
+                is_default: occurrence == 0 && default_name.as_deref() == Some(name.as_str()),
+                name,
+                device,
+            });
+        }
+
+        hosts.push((host, entries));
+    }
+
+    hosts
+}
+
+fn device_id(host: &str, name: &str, occurrence: usize) -> String {
+    if occurrence == 0 {
+        format!("{}:{}", host, name)
+    } else {
+        format!("{}:{}#{}", host, name, occurrence + 1)
+    }
+}
+
+/// Index of the device to open for `selector`, or `None` to fall back to
+/// the default input device
+fn select_device(selector: &DeviceSelector, devices: &[(&str, &str)]) -> Result<Option<usize>> {
+    match find_device(selector, devices) {
+        Some(index) => Ok(Some(index)),
+        // Recording the microphone in place of the system audio would
+        // silently produce the wrong track
+        None if *selector == DeviceSelector::Monitor => Err(VoicePAError::AudioDevice(
+            "No system audio monitor input found".to_string(),
+        )),
+        None => {
+            if *selector != DeviceSelector::Default {
+                log::warn!(
+                    "Requested input device {:?} not found, falling back to default",
+                    selector
+                );
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Index of the first `(id, name)` pair matching `selector`
+fn find_device(selector: &DeviceSelector, devices: &[(&str, &str)]) -> Option<usize> {
+    match selector {
+        DeviceSelector::Default => None,
+        DeviceSelector::Id(id) => devices.iter().position(|(d, _)| d == id),
+        DeviceSelector::Name(pattern) => {
+            let pattern = pattern.to_lowercase();
+            devices
+                .iter()
+                .position(|(_, name)| name.to_lowercase().contains(&pattern))
+        }
+        DeviceSelector::Monitor => devices
+            .iter()
+            .position(|(_, name)| name.to_lowercase().contains("monitor")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_ids_are_unique_per_host() {
+        assert_eq!(device_id("alsa", "USB Audio", 0), "alsa:USB Audio");
+        assert_eq!(device_id("alsa", "USB Audio", 1), "alsa:USB Audio#2");
+    }
+
+    #[test]
+    fn test_find_device() {
+        let devices = [
+            ("alsa:default", "default"),
+            ("alsa:Jabra Speak 510", "Jabra Speak 510"),
+            ("alsa:HD Webcam C920", "HD Webcam C920"),
+        ];
+
+        let by_id = DeviceSelector::Id("alsa:HD Webcam C920".to_string());
+        assert_eq!(find_device(&by_id, &devices), Some(2));
+
+        let by_name = DeviceSelector::Name("jabra".to_string());
+        assert_eq!(find_device(&by_name, &devices), Some(1));
+
+        let missing = DeviceSelector::Name("Yeti".to_string());
+        assert_eq!(find_device(&missing, &devices), None);
+        assert_eq!(find_device(&DeviceSelector::Monitor, &devices), None);
+
+        let with_monitor = [
+            ("alsa:default", "default"),
+            ("alsa:Monitor of Built-in Audio", "Monitor of Built-in Audio"),
+        ];
+        assert_eq!(find_device(&DeviceSelector::Monitor, &with_monitor), Some(1));
+    }
+
+    #[test]
+    fn test_missing_monitor_does_not_fall_back() {
+        let devices = [("alsa:default", "default"), ("alsa:Jabra Speak 510", "Jabra Speak 510")];
+
+        let missing = DeviceSelector::Name("Yeti".to_string());
+        assert_eq!(select_device(&missing, &devices).unwrap(), None);
+        assert!(select_device(&DeviceSelector::Monitor, &devices).is_err());
+    }
+}
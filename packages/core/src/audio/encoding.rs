@@ -1,57 +1,656 @@
-use hound::{WavSpec, WavWriter};
-use std::io::Cursor;
-use crate::utils::error::Result;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::utils::error::{Result, VoicePAError};
+
+/// WAVE_FORMAT_EXTENSIBLE sub-format GUID tail shared by PCM and float
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Seed of the dither noise, so identical input encodes identically
+const DITHER_SEED: u32 = 0x9E37_79B9;
 
 pub trait AudioEncoder {
     fn encode(&self, samples: &[f32], sample_rate: u32, channels: u16) -> Result<Vec<u8>>;
+
+    /// Start encoding into `sink` chunk by chunk; encoders that can only
+    /// encode whole recordings return an error
+    fn open_stream(
+        &self,
+        sink: Box<dyn EncoderSink>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Box<dyn StreamingEncoder>> {
+        let _ = (sink, sample_rate, channels);
+        Err(VoicePAError::Encoding(
+            "This encoder does not support streaming".to_string(),
+        ))
+    }
+
+    /// Start encoding into a new file at `path`
+    fn create_file(
+        &self,
+        path: &Path,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Box<dyn StreamingEncoder>> {
+        let file = File::create(path).map_err(|e| {
+            VoicePAError::Storage(format!("Failed to create {}: {}", path.display(), e))
+        })?;
+        self.open_stream(Box::new(BufWriter::new(file)), sample_rate, channels)
+    }
+
+    /// MIME type of the encoded data, for uploads
+    fn mime_type(&self) -> &'static str {
+        "application/octet-stream"
+    }
+
+    /// File extension of the encoded data, without the dot
+    fn file_extension(&self) -> &'static str {
+        "bin"
+    }
+}
+
+/// Where a [`StreamingEncoder`] writes, typically a buffered file
+pub trait EncoderSink: Write + Seek + Send {}
+
+impl<T: Write + Seek + Send> EncoderSink for T {}
+
+/// Encoder that writes audio to its sink as it arrives
+///
+/// Every format keeps what was written readable: about once per second of
+/// audio the headers are brought up to date and the sink is flushed, and
+/// [`repair_audio_file`](crate::audio::spill::repair_audio_file) recovers a
+/// file that was never finalized.
+pub trait StreamingEncoder: Send {
+    /// Encode interleaved samples
+    fn write(&mut self, samples: &[f32]) -> Result<()>;
+
+    /// Sample frames written so far
+    fn frames(&self) -> u64;
+
+    /// Encode any buffered audio and complete the headers
+    fn finalize(self: Box<Self>) -> Result<()>;
+}
+
+/// Sample format of a WAV file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WavSampleFormat {
+    #[default]
+    Int16,
+    Int24,
+    Int32,
+    Float32,
 }
 
-pub struct WavEncoder;
+impl WavSampleFormat {
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            WavSampleFormat::Int16 => 16,
+            WavSampleFormat::Int24 => 24,
+            WavSampleFormat::Int32 | WavSampleFormat::Float32 => 32,
+        }
+    }
+}
+
+/// Descriptive metadata embedded in LIST/INFO and BWF `bext` chunks
+#[derive(Debug, Clone, Default)]
+pub struct WavMetadata {
+    /// Meeting title
+    pub title: Option<String>,
+    /// Person or organisation that made the recording
+    pub originator: Option<String>,
+    /// Wall-clock time of the first sample, stored in UTC
+    pub started_at: Option<SystemTime>,
+    pub comment: Option<String>,
+}
+
+/// WAV encoder, 16-bit integer by default
+///
+/// Integer formats below 24 bits get TPDF dither, since truncating f32
+/// samples to 16 bits otherwise adds distortion correlated with the signal.
+#[derive(Debug, Clone)]
+pub struct WavEncoder {
+    format: WavSampleFormat,
+    dither: bool,
+    metadata: Option<WavMetadata>,
+}
+
+impl Default for WavEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl WavEncoder {
     pub fn new() -> Self {
-        Self
+        Self {
+            format: WavSampleFormat::Int16,
+            dither: true,
+            metadata: None,
+        }
+    }
+
+    pub fn with_sample_format(mut self, format: WavSampleFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Turn dither off, e.g. when the samples already hold 16-bit values
+    pub fn with_dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Embed LIST/INFO and `bext` chunks describing the recording
+    pub fn with_metadata(mut self, metadata: WavMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub(crate) fn stream<W: Write + Seek>(
+        &self,
+        sink: W,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<WavStream<W>> {
+        WavStream::new(sink, self, sample_rate, channels)
     }
 }
 
 impl AudioEncoder for WavEncoder {
     fn encode(&self, samples: &[f32], sample_rate: u32, channels: u16) -> Result<Vec<u8>> {
-        let spec = WavSpec {
+        let mut stream = self.stream(Cursor::new(Vec::new()), sample_rate, channels)?;
+        stream.write(samples)?;
+        Ok(stream.finish()?.into_inner())
+    }
+
+    fn open_stream(
+        &self,
+        sink: Box<dyn EncoderSink>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Box<dyn StreamingEncoder>> {
+        Ok(Box::new(self.stream(sink, sample_rate, channels)?))
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "audio/wav"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "wav"
+    }
+}
+
+/// Streaming WAV writer
+///
+/// The data chunk comes last, so a file cut short is valid once its sizes
+/// are fixed by [`repair_wav`](crate::audio::spill::repair_wav).
+pub(crate) struct WavStream<W: Write + Seek> {
+    sink: W,
+    format: WavSampleFormat,
+    dither: Option<Tpdf>,
+    channels: u16,
+    /// Offset of the `fact` chunk's frame count, for float data
+    fact_at: Option<u64>,
+    /// Offset of the data chunk's size field
+    data_size_at: u64,
+    data_len: u64,
+    frames: u64,
+    checkpoint_interval: u64,
+    frames_since_checkpoint: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write + Seek> WavStream<W> {
+    fn new(mut sink: W, encoder: &WavEncoder, sample_rate: u32, channels: u16) -> Result<Self> {
+        if sample_rate == 0 || channels == 0 {
+            return Err(VoicePAError::Encoding(format!(
+                "Unsupported WAV format: {} Hz, {} channels",
+                sample_rate, channels
+            )));
+        }
+        let format = encoder.format;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        write_chunk(&mut header, b"fmt ", &fmt_chunk(format, sample_rate, channels)?);
+        let mut fact_at = None;
+        if format == WavSampleFormat::Float32 {
+            fact_at = Some(header.len() as u64 + 8);
+            write_chunk(&mut header, b"fact", &0u32.to_le_bytes());
+        }
+        if let Some(metadata) = &encoder.metadata {
+            write_chunk(&mut header, b"bext", &bext_chunk(metadata, sample_rate));
+            write_chunk(&mut header, b"LIST", &info_list(metadata));
+        }
+        header.extend_from_slice(b"data");
+        let data_size_at = header.len() as u64;
+        header.extend_from_slice(&0u32.to_le_bytes());
+        sink.write_all(&header)?;
+
+        let bits = format.bits_per_sample();
+        Ok(Self {
+            sink,
+            format,
+            dither: (encoder.dither && bits < 24 && format != WavSampleFormat::Float32)
+                .then_some(Tpdf(DITHER_SEED)),
             channels,
-            sample_rate,
-            bits_per_sample: 16,
-            sample_format: hound::SampleFormat::Int,
-        };
+            fact_at,
+            data_size_at,
+            data_len: 0,
+            frames: 0,
+            checkpoint_interval: sample_rate as u64,
+            frames_since_checkpoint: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        let bits = self.format.bits_per_sample();
+        let len = samples.len() as u64 * bits as u64 / 8;
+        if self.data_start() + self.data_len + len > u32::MAX as u64 {
+            return Err(VoicePAError::Encoding(
+                "Audio is too long for a WAV file".to_string(),
+            ));
+        }
+
+        self.buffer.clear();
+        if self.format == WavSampleFormat::Float32 {
+            for &sample in samples {
+                self.buffer.extend_from_slice(&sample.to_le_bytes());
+            }
+        } else {
+            let bytes = bits as usize / 8;
+            let scale = (1i64 << (bits - 1)) as f64;
+            let (min, max) = (-scale, scale - 1.0);
+            for &sample in samples {
+                let noise = self.dither.as_mut().map_or(0.0, Tpdf::next);
+                let value = (sample as f64 * scale + noise).round().clamp(min, max) as i32;
+                self.buffer.extend_from_slice(&value.to_le_bytes()[..bytes]);
+            }
+        }
+        self.sink.write_all(&self.buffer)?;
+        self.data_len += len;
+
+        let frames = (samples.len() / self.channels as usize) as u64;
+        self.frames += frames;
+        self.frames_since_checkpoint += frames;
+        if self.frames_since_checkpoint >= self.checkpoint_interval {
+            self.update_header(0)?;
+            self.frames_since_checkpoint = 0;
+        }
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
 
-        let mut cursor = Cursor::new(Vec::new());
-        let mut writer = WavWriter::new(&mut cursor, spec)?;
+    /// Bytes per sample frame
+    pub fn block_align(&self) -> u64 {
+        self.channels as u64 * self.format.bits_per_sample() as u64 / 8
+    }
 
-        // Convert f32 samples to i16
-        for &sample in samples {
-            let amplitude = (sample * i16::MAX as f32) as i16;
-            writer.write_sample(amplitude)?;
+    /// Pad the data chunk, write the final sizes and hand back the sink
+    pub fn finish(mut self) -> Result<W> {
+        let pad = self.data_len % 2;
+        if pad == 1 {
+            self.sink.write_all(&[0])?;
         }
+        self.update_header(pad)?;
+        Ok(self.sink)
+    }
 
-        writer.finalize()?;
-        Ok(cursor.into_inner())
+    fn data_start(&self) -> u64 {
+        self.data_size_at + 4
+    }
+
+    /// Write the current sizes into the header and flush, counting `pad`
+    /// bytes written after the data in the RIFF size
+    fn update_header(&mut self, pad: u64) -> Result<()> {
+        let end = self.data_start() + self.data_len + pad;
+        let riff_len = end - 8;
+        self.sink.seek(SeekFrom::Start(4))?;
+        self.sink.write_all(&(riff_len as u32).to_le_bytes())?;
+        if let Some(fact_at) = self.fact_at {
+            self.sink.seek(SeekFrom::Start(fact_at))?;
+            self.sink.write_all(&(self.frames as u32).to_le_bytes())?;
+        }
+        self.sink.seek(SeekFrom::Start(self.data_size_at))?;
+        self.sink.write_all(&(self.data_len as u32).to_le_bytes())?;
+        self.sink.seek(SeekFrom::Start(end))?;
+        self.sink.flush()?;
+        Ok(())
     }
 }
 
+impl<W: Write + Seek + Send> StreamingEncoder for WavStream<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        WavStream::write(self, samples)
+    }
+
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn finalize(self: Box<Self>) -> Result<()> {
+        self.finish().map(drop)
+    }
+}
+
+/// Triangular dither of one LSB peak, the sum of two uniform variables
+struct Tpdf(u32);
+
+impl Tpdf {
+    fn next(&mut self) -> f64 {
+        self.uniform() + self.uniform() - 1.0
+    }
+
+    /// xorshift32 mapped to 0.0..1.0
+    fn uniform(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f64 / u32::MAX as f64
+    }
+}
+
+/// Append a chunk, padded to an even length
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Plain PCM or float format up to 16 bits and stereo, WAVE_FORMAT_EXTENSIBLE
+/// beyond that as Microsoft recommends
+fn fmt_chunk(format: WavSampleFormat, sample_rate: u32, channels: u16) -> Result<Vec<u8>> {
+    let bits = format.bits_per_sample();
+    let tag: u16 = if format == WavSampleFormat::Float32 { 3 } else { 1 };
+    let extensible = bits > 16 || channels > 2;
+
+    // Both sizes must fit their header fields
+    let block_align = u16::try_from(channels as u32 * bits as u32 / 8);
+    let byte_rate = block_align
+        .ok()
+        .and_then(|align| u32::try_from(sample_rate as u64 * align as u64).ok());
+    let (Ok(block_align), Some(byte_rate)) = (block_align, byte_rate) else {
+        return Err(VoicePAError::Encoding(format!(
+            "Unsupported WAV format: {} Hz, {} channels of {} bits",
+            sample_rate, channels, bits
+        )));
+    };
+
+    let mut fmt = Vec::with_capacity(40);
+    fmt.extend_from_slice(&(if extensible { 0xFFFE } else { tag }).to_le_bytes());
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&byte_rate.to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&bits.to_le_bytes());
+    if extensible {
+        let mask = if channels >= 32 { u32::MAX } else { (1u32 << channels) - 1 };
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt.extend_from_slice(&mask.to_le_bytes());
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+    }
+    Ok(fmt)
+}
+
+/// Broadcast Wave Format `bext` chunk, version 1 (EBU Tech 3285)
+fn bext_chunk(metadata: &WavMetadata, sample_rate: u32) -> Vec<u8> {
+    let mut bext = Vec::with_capacity(602);
+    push_fixed(&mut bext, metadata.title.as_deref().unwrap_or(""), 256);
+    push_fixed(&mut bext, metadata.originator.as_deref().unwrap_or(""), 32);
+    push_fixed(&mut bext, "", 32);
+
+    let (date, time, time_reference) = match metadata.started_at {
+        Some(started_at) => {
+            let (date, time) = utc_date_time(started_at);
+            let since_epoch = started_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+            let since_midnight = since_epoch.as_secs_f64() % 86_400.0;
+            (date, time, (since_midnight * sample_rate as f64) as u64)
+        }
+        None => (String::new(), String::new(), 0),
+    };
+    push_fixed(&mut bext, &date, 10);
+    push_fixed(&mut bext, &time, 8);
+    bext.extend_from_slice(&time_reference.to_le_bytes());
+    bext.extend_from_slice(&1u16.to_le_bytes());
+    // UMID and reserved bytes
+    bext.resize(bext.len() + 64 + 190, 0);
+    bext
+}
+
+/// LIST chunk of INFO sub-chunks
+fn info_list(metadata: &WavMetadata) -> Vec<u8> {
+    let date = metadata.started_at.map(|at| utc_date_time(at).0);
+    let entries = [
+        (b"INAM", metadata.title.as_deref()),
+        (b"IART", metadata.originator.as_deref()),
+        (b"ICRD", date.as_deref()),
+        (b"ICMT", metadata.comment.as_deref()),
+        (b"ISFT", Some(concat!("voice-pa-core ", env!("CARGO_PKG_VERSION")))),
+    ];
+
+    let mut list = b"INFO".to_vec();
+    for (id, value) in entries {
+        if let Some(value) = value {
+            let mut text = value.as_bytes().to_vec();
+            text.push(0);
+            write_chunk(&mut list, id, &text);
+        }
+    }
+    list
+}
+
+/// Write `text` into a zero-padded field of `len` bytes, truncating it
+fn push_fixed(out: &mut Vec<u8>, text: &str, len: usize) {
+    let mut end = text.len().min(len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    out.extend_from_slice(&text.as_bytes()[..end]);
+    out.resize(out.len() + len - end, 0);
+}
+
+/// `yyyy-mm-dd` and `hh:mm:ss` in UTC
+fn utc_date_time(at: SystemTime) -> (String, String) {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Gregorian calendar from days since 1970-01-01 (Howard Hinnant's
+    // civil_from_days)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!(
+            "{:02}:{:02}:{:02}",
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60
+        ),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Cursor;
+    use std::time::Duration;
 
     #[test]
     fn test_wav_encoding() {
         let encoder = WavEncoder::new();
         let samples: Vec<f32> = vec![0.0, 0.5, -0.5, 1.0, -1.0];
         let result = encoder.encode(&samples, 16000, 1);
-        
+
         assert!(result.is_ok());
         let data = result.unwrap();
         assert!(!data.is_empty());
-        
+
         // WAV files should start with "RIFF"
         assert_eq!(&data[0..4], b"RIFF");
     }
+
+    #[test]
+    fn test_sample_formats_round_trip() {
+        let samples: Vec<f32> = (0..2000).map(|i| ((i as f32) * 0.01).sin() * 0.8).collect();
+
+        for format in [WavSampleFormat::Int24, WavSampleFormat::Int32, WavSampleFormat::Float32] {
+            let wav = WavEncoder::new().with_sample_format(format).encode(&samples, 48000, 2).unwrap();
+            let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+            let spec = reader.spec();
+            assert_eq!((spec.bits_per_sample, spec.channels), (format.bits_per_sample(), 2));
+
+            let decoded: Vec<f32> = if format == WavSampleFormat::Float32 {
+                reader.samples::<f32>().map(|s| s.unwrap()).collect()
+            } else {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader.samples::<i32>().map(|s| s.unwrap() as f32 / scale).collect()
+            };
+            assert_eq!(decoded.len(), samples.len());
+            for (a, b) in decoded.iter().zip(&samples) {
+                assert!((a - b).abs() < 1e-6, "{:?}: {} vs {}", format, a, b);
+            }
+        }
+
+        // Dithered 16-bit output stays within a couple of LSBs and is
+        // unbiased; without dither it rounds exactly
+        let wav = WavEncoder::new().encode(&samples, 48000, 1).unwrap();
+        let decoded: Vec<i16> = hound::WavReader::new(Cursor::new(wav))
+            .unwrap()
+            .samples::<i16>()
+            .map(|s| s.unwrap())
+            .collect();
+        let errors: Vec<f32> = decoded
+            .iter()
+            .zip(&samples)
+            .map(|(&d, &s)| d as f32 - s * 32768.0)
+            .collect();
+        assert!(errors.iter().all(|e| e.abs() <= 1.5));
+        assert!((errors.iter().sum::<f32>() / errors.len() as f32).abs() < 0.05);
+        assert!(errors.iter().any(|e| e.abs() > 0.5));
+
+        let plain = WavEncoder::new().with_dither(false).encode(&[0.25, -1.5], 8000, 1).unwrap();
+        assert_eq!(&plain[plain.len() - 4..], &[0x00, 0x20, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot() {
+        let samples: Vec<f32> = (0..30_001).map(|i| ((i as f32) * 0.003).sin() * 0.6).collect();
+        for encoder in [
+            WavEncoder::new(),
+            WavEncoder::new().with_sample_format(WavSampleFormat::Int24),
+            WavEncoder::new().with_sample_format(WavSampleFormat::Float32),
+        ] {
+            let mut stream = encoder.stream(Cursor::new(Vec::new()), 8000, 1).unwrap();
+            for chunk in samples.chunks(441) {
+                stream.write(chunk).unwrap();
+            }
+            assert_eq!(stream.frames(), 30_001);
+            let streamed = stream.finish().unwrap().into_inner();
+            assert_eq!(streamed, encoder.encode(&samples, 8000, 1).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_header_of_odd_sized_data() {
+        let u32_at = |bytes: &[u8], at: usize| {
+            u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize
+        };
+        let encoder = WavEncoder::new().with_sample_format(WavSampleFormat::Int24);
+        let mut stream = encoder.stream(Cursor::new(Vec::new()), 8000, 1).unwrap();
+        stream.write(&[0.25; 8001]).unwrap();
+
+        // The checkpoint describes exactly the bytes on disk, without a pad
+        let written = stream.sink.get_ref().clone();
+        assert_eq!(written.len() % 2, 1);
+        assert_eq!(u32_at(&written, 4), written.len() - 8);
+        assert_eq!(u32_at(&written, stream.data_size_at as usize), 8001 * 3);
+
+        let finished = stream.finish().unwrap().into_inner();
+        assert_eq!(finished.len(), written.len() + 1);
+        assert_eq!(u32_at(&finished, 4), finished.len() - 8);
+    }
+
+    #[test]
+    fn test_oversized_formats_are_rejected() {
+        let float = WavEncoder::new().with_sample_format(WavSampleFormat::Float32);
+        assert!(float.encode(&[0.0; 16], 16000, u16::MAX).is_err());
+        assert!(float.encode(&[0.0; 16], u32::MAX, 2).is_err());
+        assert!(WavEncoder::new().encode(&[0.0; 16], 192_000, 8).is_ok());
+    }
+
+    #[test]
+    fn test_encode_only_encoder() {
+        struct Raw;
+
+        impl AudioEncoder for Raw {
+            fn encode(&self, samples: &[f32], _: u32, _: u16) -> Result<Vec<u8>> {
+                Ok(samples.iter().flat_map(|s| s.to_le_bytes()).collect())
+            }
+        }
+
+        assert_eq!(Raw.mime_type(), "application/octet-stream");
+        assert_eq!(Raw.file_extension(), "bin");
+        assert!(Raw.open_stream(Box::new(Cursor::new(Vec::new())), 8000, 1).is_err());
+    }
+
+    #[test]
+    fn test_metadata_chunks() {
+        let metadata = WavMetadata {
+            title: Some("Weekly sync".to_string()),
+            originator: Some("Voice PA".to_string()),
+            // 2023-11-14 22:13:20 UTC
+            started_at: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            comment: None,
+        };
+        let wav = WavEncoder::new()
+            .with_metadata(metadata)
+            .encode(&[0.0; 100], 16000, 1)
+            .unwrap();
+        assert_eq!(hound::WavReader::new(Cursor::new(wav.clone())).unwrap().duration(), 100);
+
+        let mut chunks = std::collections::HashMap::new();
+        let mut pos = 12;
+        while pos < wav.len() {
+            let len = u32::from_le_bytes(wav[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            chunks.insert(wav[pos..pos + 4].to_vec(), wav[pos + 8..pos + 8 + len].to_vec());
+            pos += 8 + len + len % 2;
+        }
+
+        let bext = &chunks[&b"bext".to_vec()];
+        assert_eq!(bext.len(), 602);
+        assert!(bext.starts_with(b"Weekly sync\0"));
+        assert!(bext[256..].starts_with(b"Voice PA\0"));
+        assert_eq!(&bext[320..338], b"2023-11-1422:13:20");
+        let time_reference = u64::from_le_bytes(bext[338..346].try_into().unwrap());
+        assert_eq!(time_reference, (22 * 3600 + 13 * 60 + 20) * 16000);
+
+        let list = &chunks[&b"LIST".to_vec()];
+        assert!(list.starts_with(b"INFO"));
+        assert!(list.windows(20).any(|w| w == b"INAM\x0c\0\0\0Weekly sync\0"));
+        assert!(list.windows(18).any(|w| w == b"ICRD\x0b\0\0\x002023-11-14"));
+    }
 }
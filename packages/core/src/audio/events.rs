@@ -0,0 +1,36 @@
+use std::time::Duration;
+use crate::audio::autostop::StopReason;
+
+/// Something that happened to the input stream during a recording
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecorderEvent {
+    /// The audio backend reported an error
+    StreamError { message: String },
+    /// The input stream stopped delivering audio; a gap starts here
+    StreamLost { device_name: String, reason: String },
+    /// Capture resumed on `device_name` after being down for `gap`
+    StreamRecovered { device_name: String, gap: Duration },
+    /// Recovery gave up; the recording keeps the audio captured so far
+    RecoveryFailed { reason: String },
+    /// A finite source, e.g. a file, delivered all of its audio
+    SourceEnded,
+    /// A stop policy ended the recording; finish it to collect the audio
+    AutoStopped { reason: StopReason },
+}
+
+/// What the recorder does when its input stream fails
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RecoveryPolicy {
+    /// Report the failure and stop capturing; the session can still be
+    /// finished to collect the audio recorded before the failure
+    #[default]
+    Disabled,
+    /// Keep trying to reopen capture; device sources move to the current
+    /// default input device
+    ReopenDefault {
+        /// Time between reopen attempts
+        retry_interval: Duration,
+        /// Give up once the stream has been down this long
+        give_up_after: Duration,
+    },
+}
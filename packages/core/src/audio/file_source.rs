@@ -0,0 +1,135 @@
+use cpal::SampleFormat;
+use hound::WavReader;
+use std::path::{Path, PathBuf};
+use crate::audio::source::{spawn_playback, AudioSource, Pacing, PlaybackHandle, SourceFormat, SourceOutput};
+use crate::utils::error::{Result, VoicePAError};
+
+/// Plays a WAV file as if it were being captured from a device
+#[derive(Debug, Clone)]
+pub struct WavFileSource {
+    path: PathBuf,
+    spec: hound::WavSpec,
+    pacing: Pacing,
+}
+
+impl WavFileSource {
+    /// Open `path` for playback in real time
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let spec = WavReader::open(&path)?.spec();
+
+        Ok(Self {
+            path,
+            spec,
+            pacing: Pacing::RealTime,
+        })
+    }
+
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AudioSource for WavFileSource {
+    type Stream = PlaybackHandle;
+
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn format(&mut self) -> Result<SourceFormat> {
+        let sample_format = match (self.spec.sample_format, self.spec.bits_per_sample) {
+            (hound::SampleFormat::Float, 32) => SampleFormat::F32,
+            (hound::SampleFormat::Int, 8) => SampleFormat::I8,
+            (hound::SampleFormat::Int, 16) => SampleFormat::I16,
+            (hound::SampleFormat::Int, 24 | 32) => SampleFormat::I32,
+            (format, bits) => {
+                return Err(VoicePAError::AudioDevice(format!(
+                    "Unsupported WAV sample format: {:?} {} bit",
+                    format, bits
+                )))
+            }
+        };
+
+        Ok(SourceFormat {
+            sample_rate: self.spec.sample_rate,
+            channels: self.spec.channels,
+            sample_format,
+        })
+    }
+
+    fn start(&mut self, output: SourceOutput) -> Result<PlaybackHandle> {
+        let format = self.format()?;
+        let mut reader = WavReader::open(&self.path)?;
+        let float = self.spec.sample_format == hound::SampleFormat::Float;
+        let scale = 1.0 / (1u64 << (self.spec.bits_per_sample.max(1) - 1)) as f32;
+        let notifier = output.notifier();
+
+        spawn_playback("voice-pa-file", output, format, self.pacing, move |buffer| {
+            let samples: std::result::Result<Vec<f32>, hound::Error> = if float {
+                reader.samples::<f32>().take(buffer.len()).collect()
+            } else {
+                reader
+                    .samples::<i32>()
+                    .take(buffer.len())
+                    .map(|s| s.map(|s| s as f32 * scale))
+                    .collect()
+            };
+
+            match samples {
+                Ok(samples) => {
+                    buffer[..samples.len()].copy_from_slice(&samples);
+                    samples.len()
+                }
+                Err(e) => {
+                    notifier.lost(format!("Failed to read WAV file: {}", e));
+                    0
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioConfig, AudioRecorder, RecorderEvent};
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_file_plays_through_recorder() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..44100 / 2 {
+            writer.write_sample(i16::MAX / 4).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let source = WavFileSource::open(&path).unwrap().with_pacing(Pacing::Unthrottled);
+        let mut recorder = AudioRecorder::with_source(source, AudioConfig::default());
+        let mut events = recorder.subscribe_events();
+
+        recorder.start_recording().await.unwrap();
+        let ended = tokio::time::timeout(Duration::from_secs(10), events.recv()).await;
+        assert_eq!(ended.unwrap().unwrap(), RecorderEvent::SourceEnded);
+        let recording = recorder.finish_recording().await.unwrap();
+
+        assert_eq!(recording.frames, 8000);
+        assert_eq!(recorder.diagnostics().native_sample_format, Some(SampleFormat::I16));
+        let middle = recording.samples[4000];
+        assert!((middle - 0.25).abs() < 0.01, "{}", middle);
+    }
+}
@@ -0,0 +1,332 @@
+// Biquad IIR filters: high-pass, low-pass, band-pass, notch and shelves
+
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+/// Q of a second-order Butterworth response, flat up to the corner
+pub const BUTTERWORTH_Q: f64 = FRAC_1_SQRT_2;
+
+/// Q of the hum notch, about 5 Hz wide at 50 Hz
+const NOTCH_Q: f64 = 10.0;
+
+/// Passband of narrowband telephony (ITU-T G.712)
+const TELEPHONE_BAND: (f64, f64) = (300.0, 3400.0);
+
+/// Shape of a [`Biquad`], with coefficients from the Audio EQ Cookbook
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+    /// Peaks at 0 dB at the centre frequency
+    BandPass,
+    Notch,
+    /// Boost (or cut, if negative) in dB below the corner frequency
+    LowShelf(f64),
+    /// Boost (or cut, if negative) in dB above the corner frequency
+    HighShelf(f64),
+}
+
+/// Second-order IIR section in direct form I, keeping its state between
+/// calls so audio can be filtered in chunks of any size
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// Design a filter; the frequency is kept just below Nyquist
+    pub fn new(kind: FilterKind, frequency: f64, q: f64, sample_rate: u32) -> Self {
+        let rate = sample_rate.max(1) as f64;
+        let frequency = frequency.clamp(1e-3 * rate, 0.49 * rate);
+        let w0 = 2.0 * PI * frequency / rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q.max(1e-3));
+
+        let (b, a) = match kind {
+            FilterKind::HighPass => (
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::LowPass => (
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::BandPass => (
+                [alpha, 0.0, -alpha],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::Notch => (
+                [1.0, -2.0 * cos, 1.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::LowShelf(gain_db) => {
+                let g = 10f64.powf(gain_db / 40.0);
+                let beta = 2.0 * g.sqrt() * alpha;
+                (
+                    [
+                        g * ((g + 1.0) - (g - 1.0) * cos + beta),
+                        2.0 * g * ((g - 1.0) - (g + 1.0) * cos),
+                        g * ((g + 1.0) - (g - 1.0) * cos - beta),
+                    ],
+                    [
+                        (g + 1.0) + (g - 1.0) * cos + beta,
+                        -2.0 * ((g - 1.0) + (g + 1.0) * cos),
+                        (g + 1.0) + (g - 1.0) * cos - beta,
+                    ],
+                )
+            }
+            FilterKind::HighShelf(gain_db) => {
+                let g = 10f64.powf(gain_db / 40.0);
+                let beta = 2.0 * g.sqrt() * alpha;
+                (
+                    [
+                        g * ((g + 1.0) + (g - 1.0) * cos + beta),
+                        -2.0 * g * ((g - 1.0) + (g + 1.0) * cos),
+                        g * ((g + 1.0) + (g - 1.0) * cos - beta),
+                    ],
+                    [
+                        (g + 1.0) - (g - 1.0) * cos + beta,
+                        2.0 * ((g - 1.0) - (g + 1.0) * cos),
+                        (g + 1.0) - (g - 1.0) * cos - beta,
+                    ],
+                )
+            }
+        };
+
+        Self::from_coefficients(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+
+    /// A section with coefficients normalized so that a0 is 1
+    pub(crate) fn from_coefficients(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.filter(sample as f64) as f32
+    }
+
+    pub(crate) fn filter(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+
+    /// Forget previous samples, e.g. before an unrelated recording
+    pub fn reset(&mut self) {
+        self.x = [0.0; 2];
+        self.y = [0.0; 2];
+    }
+
+    /// Gain of the filter at a frequency, in dB
+    pub fn response_db(&self, frequency: f64, sample_rate: u32) -> f64 {
+        let w = 2.0 * PI * frequency / sample_rate.max(1) as f64;
+        // Evaluate both polynomials at z^-1 = e^(-jw)
+        let evaluate = |c: [f64; 3]| {
+            let re = c[0] + c[1] * w.cos() + c[2] * (2.0 * w).cos();
+            let im = -c[1] * w.sin() - c[2] * (2.0 * w).sin();
+            re * re + im * im
+        };
+        let numerator = evaluate(self.b);
+        let denominator = evaluate([1.0, self.a[0], self.a[1]]);
+        10.0 * (numerator / denominator).max(1e-30).log10()
+    }
+}
+
+/// A cascade of biquads applied to each channel of interleaved audio
+///
+/// State is kept per channel between calls to [`process`](Self::process),
+/// so a live stream can be filtered chunk by chunk, e.g. to take out rumble
+/// and a drifting DC offset with a high-pass before voice detection.
+#[derive(Debug, Clone)]
+pub struct FilterChain {
+    sample_rate: u32,
+    channels: usize,
+    /// The sections of each channel, in order
+    sections: Vec<Vec<Biquad>>,
+    /// Channel of the next sample, if the last chunk ended mid-frame
+    next_channel: usize,
+}
+
+impl FilterChain {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            sample_rate,
+            channels,
+            sections: vec![Vec::new(); channels],
+            next_channel: 0,
+        }
+    }
+
+    /// Band limits of a telephone line, 300-3400 Hz
+    pub fn telephone(sample_rate: u32, channels: u16) -> Self {
+        Self::new(sample_rate, channels)
+            .with_band_pass(TELEPHONE_BAND.0, TELEPHONE_BAND.1)
+    }
+
+    /// Add a section of any shape
+    pub fn with_filter(mut self, kind: FilterKind, frequency: f64, q: f64) -> Self {
+        let section = Biquad::new(kind, frequency, q, self.sample_rate);
+        for channel in &mut self.sections {
+            channel.push(section.clone());
+        }
+        self
+    }
+
+    /// Butterworth high-pass, e.g. at 80 Hz against rumble and DC
+    pub fn with_high_pass(self, frequency: f64) -> Self {
+        self.with_filter(FilterKind::HighPass, frequency, BUTTERWORTH_Q)
+    }
+
+    pub fn with_low_pass(self, frequency: f64) -> Self {
+        self.with_filter(FilterKind::LowPass, frequency, BUTTERWORTH_Q)
+    }
+
+    /// Flat passband between two corners, as a high-pass and a low-pass
+    pub fn with_band_pass(self, low: f64, high: f64) -> Self {
+        self.with_high_pass(low).with_low_pass(high)
+    }
+
+    /// Narrow notch, e.g. at 50 or 60 Hz against mains hum
+    pub fn with_notch(self, frequency: f64) -> Self {
+        self.with_filter(FilterKind::Notch, frequency, NOTCH_Q)
+    }
+
+    pub fn with_low_shelf(self, frequency: f64, gain_db: f64) -> Self {
+        self.with_filter(FilterKind::LowShelf(gain_db), frequency, BUTTERWORTH_Q)
+    }
+
+    pub fn with_high_shelf(self, frequency: f64, gain_db: f64) -> Self {
+        self.with_filter(FilterKind::HighShelf(gain_db), frequency, BUTTERWORTH_Q)
+    }
+
+    /// Filter interleaved samples in place; a chunk may end mid-frame
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            let output = self.sections[self.next_channel]
+                .iter_mut()
+                .fold(*sample as f64, |value, section| section.filter(value));
+            *sample = output as f32;
+            self.next_channel = (self.next_channel + 1) % self.channels;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.sections.iter_mut().flatten().for_each(Biquad::reset);
+        self.next_channel = 0;
+    }
+
+    /// Gain of the whole chain at a frequency, in dB
+    pub fn response_db(&self, frequency: f64) -> f64 {
+        self.sections[0]
+            .iter()
+            .map(|section| section.response_db(frequency, self.sample_rate))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    fn sine(frequency: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * PI * frequency * i as f64 / RATE as f64).sin() as f32)
+            .collect()
+    }
+
+    fn rms_db(samples: &[f32]) -> f64 {
+        let power = samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64;
+        10.0 * power.log10()
+    }
+
+    #[test]
+    fn test_frequency_responses() {
+        let near = |actual: f64, expected: f64| (actual - expected).abs() < 0.1;
+
+        let high_pass = FilterChain::new(RATE, 1).with_high_pass(80.0);
+        assert!(near(high_pass.response_db(80.0), -3.01));
+        assert!(near(high_pass.response_db(2000.0), 0.0));
+        // 12 dB per octave, two octaves down
+        assert!(high_pass.response_db(20.0) < -23.0);
+
+        let telephone = FilterChain::telephone(RATE, 1);
+        assert!(near(telephone.response_db(1000.0), 0.0));
+        assert!(telephone.response_db(100.0) < -15.0);
+        assert!(telephone.response_db(12000.0) < -20.0);
+
+        let band_pass = Biquad::new(FilterKind::BandPass, 1000.0, 2.0, RATE);
+        assert!(near(band_pass.response_db(1000.0, RATE), 0.0));
+        assert!(band_pass.response_db(250.0, RATE) < -15.0);
+
+        let notch = FilterChain::new(RATE, 1).with_notch(50.0);
+        assert!(notch.response_db(50.0) < -60.0);
+        assert!(notch.response_db(200.0).abs() < 0.1);
+
+        let shelves = FilterChain::new(RATE, 1)
+            .with_low_shelf(100.0, 6.0)
+            .with_high_shelf(8000.0, -6.0);
+        assert!(near(shelves.response_db(100.0), 3.0));
+        assert!(near(shelves.response_db(20.0), 6.0));
+        assert!(near(shelves.response_db(1000.0), 0.0));
+        assert!(shelves.response_db(20000.0) < -5.5);
+
+        // The filters do what their responses say
+        for (frequency, expected) in [(30.0, high_pass.response_db(30.0)), (1000.0, 0.0)] {
+            let input = sine(frequency, RATE as usize);
+            let mut output = input.clone();
+            high_pass.clone().process(&mut output);
+            let measured = rms_db(&output[RATE as usize / 2..]) - rms_db(&input);
+            assert!((measured - expected).abs() < 0.1, "{} Hz: {}", frequency, measured);
+        }
+    }
+
+    #[test]
+    fn test_streaming_high_pass_removes_drift_and_hum() {
+        // Stereo 50 Hz hum over a wandering DC offset, under a voice band tone
+        let frames = 2 * RATE as usize;
+        let hum = sine(50.0, frames);
+        let interference: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let drift = 0.2 + 0.3 * (i as f32 / RATE as f32 * 0.5).sin();
+                [drift + 0.3 * hum[i], 0.3 * hum[i] - drift]
+            })
+            .collect();
+        let tone: Vec<f32> = sine(500.0, frames).iter().flat_map(|&s| [0.5 * s; 2]).collect();
+        let mixed: Vec<f32> = interference.iter().zip(&tone).map(|(a, b)| a + b).collect();
+
+        let mut chain = FilterChain::new(RATE, 2).with_high_pass(80.0).with_notch(50.0);
+        let mut whole = mixed.clone();
+        chain.process(&mut whole);
+
+        chain.reset();
+        let mut chunked = mixed;
+        for chunk in chunked.chunks_mut(333) {
+            chain.process(chunk);
+        }
+        assert_eq!(whole, chunked);
+
+        chain.reset();
+        let mut residue = interference.clone();
+        chain.process(&mut residue);
+        // Half a second in, as interleaved stereo samples
+        let settled = RATE as usize;
+        assert!(rms_db(&residue[settled..]) - rms_db(&interference) < -50.0);
+        assert!((rms_db(&whole[settled..]) - rms_db(&tone)).abs() < 0.1);
+    }
+}
@@ -0,0 +1,1213 @@
+// Lossless FLAC encoding: fixed and LPC prediction with Rice-coded residuals
+
+use std::f64::consts::PI;
+use std::fs::OpenOptions;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::path::Path;
+use crate::audio::encoding::{AudioEncoder, EncoderSink, StreamingEncoder};
+use crate::utils::error::{Result, VoicePAError};
+
+/// Compression level used by [`FlacEncoder::new`]
+const DEFAULT_LEVEL: u8 = 5;
+
+/// Highest LPC coefficient precision the format can signal
+const MAX_QLP_PRECISION: u32 = 15;
+
+/// Encoder settings for one compression level, after the reference encoder
+struct LevelParams {
+    block_size: usize,
+    mid_side: bool,
+    max_lpc_order: usize,
+    /// Try every LPC order instead of the one the model predicts is best
+    exhaustive: bool,
+    max_partition_order: u32,
+}
+
+fn level_params(level: u8) -> LevelParams {
+    let (block_size, mid_side, max_lpc_order, exhaustive, max_partition_order) = match level {
+        0 => (1152, false, 0, false, 3),
+        1 => (1152, true, 0, false, 3),
+        2 => (1152, true, 0, false, 4),
+        3 => (4096, false, 6, false, 4),
+        4 => (4096, true, 8, false, 4),
+        5 => (4096, true, 8, false, 5),
+        6 => (4096, true, 8, false, 6),
+        7 => (4096, true, 8, true, 6),
+        _ => (4096, true, 12, true, 6),
+    };
+    LevelParams {
+        block_size,
+        mid_side,
+        max_lpc_order,
+        exhaustive,
+        max_partition_order,
+    }
+}
+
+/// Lossless FLAC encoder, typically about half the size of a WAV file for
+/// speech
+pub struct FlacEncoder {
+    level: u8,
+    bits_per_sample: u16,
+}
+
+impl Default for FlacEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlacEncoder {
+    /// Compression level 5 at 16 bits per sample
+    pub fn new() -> Self {
+        Self {
+            level: DEFAULT_LEVEL,
+            bits_per_sample: 16,
+        }
+    }
+
+    /// Trade encoding speed for size, from 0 (fastest) to 8 (smallest)
+    pub fn with_compression_level(mut self, level: u8) -> Self {
+        self.level = level.min(8);
+        self
+    }
+
+    /// Bit depth of the encoded samples, from 4 to 24
+    pub fn with_bits_per_sample(mut self, bits: u16) -> Self {
+        self.bits_per_sample = bits;
+        self
+    }
+}
+
+impl AudioEncoder for FlacEncoder {
+    fn encode(&self, samples: &[f32], sample_rate: u32, channels: u16) -> Result<Vec<u8>> {
+        let mut stream = FlacStream::new(Cursor::new(Vec::new()), self, sample_rate, channels)?;
+        stream.write(samples)?;
+        Ok(stream.finish()?.into_inner())
+    }
+
+    fn open_stream(
+        &self,
+        sink: Box<dyn EncoderSink>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Box<dyn StreamingEncoder>> {
+        Ok(Box::new(FlacStream::new(sink, self, sample_rate, channels)?))
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "audio/flac"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "flac"
+    }
+}
+
+/// Streaming FLAC writer
+///
+/// Frames are written as each block fills up. STREAMINFO is brought up to
+/// date about once per second, and [`repair_flac`] completes a file that
+/// was cut short.
+struct FlacStream<W: Write + Seek> {
+    sink: W,
+    params: LevelParams,
+    sample_rate: u32,
+    bps: u32,
+    scale: f32,
+    block: Vec<Vec<i64>>,
+    /// Channel of the next incoming sample
+    next_channel: usize,
+    frame_number: u64,
+    frames: u64,
+    min_frame: usize,
+    max_frame: usize,
+    frames_since_checkpoint: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write + Seek> FlacStream<W> {
+    fn new(mut sink: W, encoder: &FlacEncoder, sample_rate: u32, channels: u16) -> Result<Self> {
+        let bps = encoder.bits_per_sample as u32;
+        if !(4..=24).contains(&bps) {
+            return Err(VoicePAError::Encoding(format!(
+                "FLAC bit depth must be 4 to 24 bits, got {}",
+                bps
+            )));
+        }
+        if !(1..=8).contains(&channels) {
+            return Err(VoicePAError::Encoding(format!(
+                "FLAC supports 1 to 8 channels, got {}",
+                channels
+            )));
+        }
+        if sample_rate == 0 || sample_rate >= 1 << 20 {
+            return Err(VoicePAError::Encoding(format!(
+                "Unsupported FLAC sample rate: {}",
+                sample_rate
+            )));
+        }
+
+        sink.write_all(b"fLaC")?;
+        let params = level_params(encoder.level);
+        let mut stream = Self {
+            sink,
+            block: vec![Vec::with_capacity(params.block_size); channels as usize],
+            params,
+            sample_rate,
+            bps,
+            scale: ((1i64 << (bps - 1)) - 1) as f32,
+            next_channel: 0,
+            frame_number: 0,
+            frames: 0,
+            min_frame: usize::MAX,
+            max_frame: 0,
+            frames_since_checkpoint: 0,
+            buffer: Vec::new(),
+        };
+        let mut streaminfo = Vec::new();
+        write_streaminfo(&mut streaminfo, &stream.stream_info());
+        stream.sink.write_all(&streaminfo)?;
+        Ok(stream)
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * self.scale).round() as i64;
+            self.block[self.next_channel].push(value);
+            self.next_channel = (self.next_channel + 1) % self.block.len();
+            if self.next_channel == 0 && self.block[0].len() == self.params.block_size {
+                self.write_frame()?;
+            }
+        }
+
+        if self.frames_since_checkpoint >= self.sample_rate as u64 {
+            self.update_streaminfo()?;
+            self.frames_since_checkpoint = 0;
+        }
+        Ok(())
+    }
+
+    /// Encode the last, shorter block, write the final STREAMINFO and hand
+    /// back the sink; a trailing partial sample frame is dropped
+    fn finish(mut self) -> Result<W> {
+        let complete = self.block[self.block.len() - 1].len();
+        for channel in &mut self.block {
+            channel.truncate(complete);
+        }
+        if complete > 0 {
+            self.write_frame()?;
+        }
+        self.update_streaminfo()?;
+        Ok(self.sink)
+    }
+
+    fn write_frame(&mut self) -> Result<()> {
+        self.buffer.clear();
+        encode_frame(
+            &mut self.buffer,
+            self.frame_number,
+            &self.block,
+            self.sample_rate,
+            self.bps,
+            &self.params,
+        );
+        self.sink.write_all(&self.buffer)?;
+
+        let block_size = self.block[0].len() as u64;
+        self.frame_number += 1;
+        self.frames += block_size;
+        self.frames_since_checkpoint += block_size;
+        self.min_frame = self.min_frame.min(self.buffer.len());
+        self.max_frame = self.max_frame.max(self.buffer.len());
+        for channel in &mut self.block {
+            channel.clear();
+        }
+        Ok(())
+    }
+
+    fn stream_info(&self) -> StreamInfo {
+        // Zero frame sizes mean unknown
+        let (min_frame, max_frame) = match self.max_frame {
+            0 => (0, 0),
+            max => (self.min_frame, max),
+        };
+        StreamInfo {
+            block_size: self.params.block_size,
+            min_frame,
+            max_frame,
+            sample_rate: self.sample_rate,
+            channels: self.block.len(),
+            bps: self.bps,
+            total_frames: self.frames,
+        }
+    }
+
+    /// Rewrite STREAMINFO with the frames written so far and flush
+    fn update_streaminfo(&mut self) -> Result<()> {
+        let mut streaminfo = Vec::new();
+        write_streaminfo(&mut streaminfo, &self.stream_info());
+        let end = self.sink.stream_position()?;
+        self.sink.seek(SeekFrom::Start(4))?;
+        self.sink.write_all(&streaminfo)?;
+        self.sink.seek(SeekFrom::Start(end))?;
+        self.sink.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek + Send> StreamingEncoder for FlacStream<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        FlacStream::write(self, samples)
+    }
+
+    fn frames(&self) -> u64 {
+        self.frames + self.block[self.block.len() - 1].len() as u64
+    }
+
+    fn finalize(self: Box<Self>) -> Result<()> {
+        self.finish().map(drop)
+    }
+}
+
+/// Truncate a FLAC file after its last intact frame and complete its
+/// STREAMINFO, e.g. after a crash while a [`FlacEncoder`] stream was open
+///
+/// Frames are checked against both their header and frame checksums.
+/// Returns the number of sample frames in the repaired file.
+pub fn repair_flac(path: impl AsRef<Path>) -> Result<u64> {
+    let path = path.as_ref();
+    let invalid = |reason: &str| {
+        VoicePAError::Storage(format!("Cannot repair {}: {}", path.display(), reason))
+    };
+
+    let data = std::fs::read(path)?;
+    if !data.starts_with(b"fLaC") {
+        return Err(invalid("not a FLAC file"));
+    }
+
+    // Metadata blocks, STREAMINFO first
+    let mut position = 4;
+    loop {
+        let header = data
+            .get(position..position + 4)
+            .ok_or_else(|| invalid("truncated metadata"))?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        if position == 4 && (header[0] & 0x7F != 0 || len != 34) {
+            return Err(invalid("missing STREAMINFO"));
+        }
+        position += 4 + len;
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    if position > data.len() {
+        return Err(invalid("truncated metadata"));
+    }
+
+    let mut frames = 0u64;
+    let (mut min_frame, mut max_frame) = (usize::MAX, 0);
+    while let Some(block_size) = frame_header(&data[position..]) {
+        // The frame runs to the next frame header or the end of the file,
+        // and its CRC-16 covers everything up to there including itself
+        let mut crc = 0u16;
+        let mut end = None;
+        for (i, &b) in data[position..].iter().enumerate() {
+            if i > 0 && crc == 0 && frame_header(&data[position + i..]).is_some() {
+                end = Some(i);
+                break;
+            }
+            crc = crc16_update(crc, b);
+        }
+        let len = match end {
+            Some(len) => len,
+            None if crc == 0 => data.len() - position,
+            None => break,
+        };
+        frames += block_size;
+        min_frame = min_frame.min(len);
+        max_frame = max_frame.max(len);
+        position += len;
+    }
+
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(position as u64)?;
+
+    // Minimum and maximum frame size, then the 36-bit total sample count
+    // after the sample rate, channel and bit depth fields
+    let mut info = data[8..8 + 34].to_vec();
+    if max_frame == 0 {
+        min_frame = 0;
+    }
+    info[4..7].copy_from_slice(&(min_frame as u32).to_be_bytes()[1..]);
+    info[7..10].copy_from_slice(&(max_frame as u32).to_be_bytes()[1..]);
+    info[13] = (info[13] & 0xF0) | ((frames >> 32) & 0x0F) as u8;
+    info[14..18].copy_from_slice(&(frames as u32).to_be_bytes());
+    file.seek(SeekFrom::Start(8))?;
+    file.write_all(&info)?;
+    file.sync_all()?;
+
+    Ok(frames)
+}
+
+/// Block size of the frame starting at `data`, if it starts with a frame
+/// header whose CRC-8 matches
+fn frame_header(data: &[u8]) -> Option<u64> {
+    if data.len() < 6 || data[0] != 0xFF || data[1] & 0xFE != 0xF8 || data[3] & 0x01 != 0 {
+        return None;
+    }
+    // UTF-8 style coded frame or sample number
+    let number_len = match data[4].leading_ones() {
+        0 => 1,
+        n @ 2..=7 => n as usize,
+        _ => return None,
+    };
+    let mut len = 4 + number_len;
+    let size_code = data[2] >> 4;
+    let block_size = match size_code {
+        0 => return None,
+        1 => 192,
+        2..=5 => 576 << (size_code - 2),
+        6 => *data.get(len)? as u64 + 1,
+        7 => u16::from_be_bytes([*data.get(len)?, *data.get(len + 1)?]) as u64 + 1,
+        _ => 256 << (size_code - 8),
+    };
+    len += match size_code {
+        6 => 1,
+        7 => 2,
+        _ => 0,
+    };
+    len += match data[2] & 0x0F {
+        12 => 1,
+        13 | 14 => 2,
+        15 => return None,
+        _ => 0,
+    };
+    (*data.get(len)? == crc8(&data[..len])).then_some(block_size)
+}
+
+struct StreamInfo {
+    block_size: usize,
+    min_frame: usize,
+    max_frame: usize,
+    sample_rate: u32,
+    channels: usize,
+    bps: u32,
+    total_frames: u64,
+}
+
+/// The mandatory STREAMINFO metadata block, marked as the last one
+fn write_streaminfo(out: &mut Vec<u8>, info: &StreamInfo) {
+    let mut w = BitWriter::new();
+    w.write(1, 1);
+    w.write(0, 7);
+    w.write(34, 24);
+    w.write(info.block_size as u64, 16);
+    w.write(info.block_size as u64, 16);
+    w.write(info.min_frame as u64, 24);
+    w.write(info.max_frame as u64, 24);
+    w.write(info.sample_rate as u64, 20);
+    w.write(info.channels as u64 - 1, 3);
+    w.write(info.bps as u64 - 1, 5);
+    w.write(info.total_frames >> 32, 4);
+    w.write(info.total_frames & 0xFFFF_FFFF, 32);
+    // An all-zero MD5 signature means it was not computed
+    w.write(0, 32);
+    w.write(0, 32);
+    w.write(0, 32);
+    w.write(0, 32);
+    out.extend_from_slice(&w.finish());
+}
+
+/// How the two channels of a stereo frame are stored
+#[derive(Clone, Copy)]
+enum StereoMode {
+    Independent,
+    LeftSide,
+    RightSide,
+    MidSide,
+}
+
+fn encode_frame(
+    out: &mut Vec<u8>,
+    number: u64,
+    block: &[Vec<i64>],
+    sample_rate: u32,
+    bps: u32,
+    params: &LevelParams,
+) {
+    let block_size = block[0].len();
+    let mut w = BitWriter::new();
+
+    let plan = |samples: &[i64], bps| plan_subframe(samples, bps, params);
+    let (mode, subframes) = if block.len() == 2 && params.mid_side {
+        let (left, right) = (block[0].clone(), block[1].clone());
+        let side: Vec<i64> = left.iter().zip(&right).map(|(l, r)| l - r).collect();
+        let mid: Vec<i64> = left.iter().zip(&right).map(|(l, r)| (l + r) >> 1).collect();
+
+        let (l, r) = (plan(&left, bps), plan(&right, bps));
+        let (m, s) = (plan(&mid, bps), plan(&side, bps + 1));
+        let costs = [l.1 + r.1, l.1 + s.1, s.1 + r.1, m.1 + s.1];
+        let cheapest = (0..4).min_by_key(|&i| costs[i]).unwrap_or(0);
+        match cheapest {
+            0 => (
+                StereoMode::Independent,
+                vec![(left, bps, l.0), (right, bps, r.0)],
+            ),
+            1 => (
+                StereoMode::LeftSide,
+                vec![(left, bps, l.0), (side, bps + 1, s.0)],
+            ),
+            2 => (
+                StereoMode::RightSide,
+                vec![(side, bps + 1, s.0), (right, bps, r.0)],
+            ),
+            _ => (
+                StereoMode::MidSide,
+                vec![(mid, bps, m.0), (side, bps + 1, s.0)],
+            ),
+        }
+    } else {
+        let subframes = block
+            .iter()
+            .map(|channel| (channel.clone(), bps, plan(channel, bps).0))
+            .collect();
+        (StereoMode::Independent, subframes)
+    };
+
+    // Frame header
+    w.write(0xFFF8, 16);
+    w.write(0b0111, 4);
+    let (rate_code, rate_extra) = sample_rate_code(sample_rate);
+    w.write(rate_code, 4);
+    let assignment = match mode {
+        StereoMode::Independent => block.len() as u64 - 1,
+        StereoMode::LeftSide => 8,
+        StereoMode::RightSide => 9,
+        StereoMode::MidSide => 10,
+    };
+    w.write(assignment, 4);
+    w.write(sample_size_code(bps), 3);
+    w.write(0, 1);
+    w.write_utf8(number);
+    w.write(block_size as u64 - 1, 16);
+    if let Some((value, bits)) = rate_extra {
+        w.write(value, bits);
+    }
+    let crc = crc8(w.bytes());
+    w.write(crc as u64, 8);
+
+    for (samples, bps, plan) in &subframes {
+        write_subframe(&mut w, samples, *bps, plan);
+    }
+
+    w.align();
+    let crc = crc16(w.bytes());
+    w.write(crc as u64, 16);
+    out.extend_from_slice(&w.finish());
+}
+
+/// Header code for a sample rate, plus the bits to write after the header
+/// when the rate has no code of its own
+fn sample_rate_code(rate: u32) -> (u64, Option<(u64, u32)>) {
+    match rate {
+        88200 => (1, None),
+        176400 => (2, None),
+        192000 => (3, None),
+        8000 => (4, None),
+        16000 => (5, None),
+        22050 => (6, None),
+        24000 => (7, None),
+        32000 => (8, None),
+        44100 => (9, None),
+        48000 => (10, None),
+        96000 => (11, None),
+        rate if rate % 1000 == 0 && rate / 1000 <= 255 => (12, Some((rate as u64 / 1000, 8))),
+        rate if rate <= 0xFFFF => (13, Some((rate as u64, 16))),
+        rate if rate % 10 == 0 && rate / 10 <= 0xFFFF => (14, Some((rate as u64 / 10, 16))),
+        // Taken from STREAMINFO
+        _ => (0, None),
+    }
+}
+
+fn sample_size_code(bps: u32) -> u64 {
+    match bps {
+        8 => 1,
+        12 => 2,
+        16 => 4,
+        20 => 5,
+        24 => 6,
+        // Taken from STREAMINFO
+        _ => 0,
+    }
+}
+
+enum Subframe {
+    Constant,
+    Verbatim,
+    Fixed {
+        order: usize,
+        residual: Vec<i64>,
+        rice: RicePlan,
+    },
+    Lpc {
+        order: usize,
+        precision: u32,
+        shift: u32,
+        coefficients: Vec<i64>,
+        residual: Vec<i64>,
+        rice: RicePlan,
+    },
+}
+
+/// Cheapest way to store `samples`, with its size in bits
+fn plan_subframe(samples: &[i64], bps: u32, params: &LevelParams) -> (Subframe, u64) {
+    let n = samples.len();
+    if samples.iter().all(|&s| s == samples[0]) {
+        return (Subframe::Constant, 8 + bps as u64);
+    }
+
+    let mut best = (Subframe::Verbatim, 8 + n as u64 * bps as u64);
+    for order in 0..=4.min(n - 1) {
+        let residual = fixed_residual(samples, order);
+        let rice = plan_rice(&residual, order, n, params.max_partition_order);
+        let bits = 8 + (order as u64) * bps as u64 + rice.bits;
+        if bits < best.1 {
+            best = (
+                Subframe::Fixed {
+                    order,
+                    residual,
+                    rice,
+                },
+                bits,
+            );
+        }
+    }
+
+    if params.max_lpc_order > 0 && n > params.max_lpc_order {
+        if let Some(lpc) = plan_lpc(samples, bps, params) {
+            if lpc.1 < best.1 {
+                best = lpc;
+            }
+        }
+    }
+    best
+}
+
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    samples
+        .windows(order + 1)
+        .map(|w| match order {
+            0 => w[0],
+            1 => w[1] - w[0],
+            2 => w[2] - 2 * w[1] + w[0],
+            3 => w[3] - 3 * w[2] + 3 * w[1] - w[0],
+            _ => w[4] - 4 * w[3] + 6 * w[2] - 4 * w[1] + w[0],
+        })
+        .collect()
+}
+
+fn plan_lpc(samples: &[i64], bps: u32, params: &LevelParams) -> Option<(Subframe, u64)> {
+    let n = samples.len();
+    let max_order = params.max_lpc_order;
+
+    // Autocorrelation of the Tukey-windowed signal
+    let windowed: Vec<f64> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| s as f64 * tukey(i, n))
+        .collect();
+    let autoc: Vec<f64> = (0..=max_order)
+        .map(|lag| {
+            windowed[lag..]
+                .iter()
+                .zip(&windowed)
+                .map(|(a, b)| a * b)
+                .sum()
+        })
+        .collect();
+    if autoc[0] <= 0.0 {
+        return None;
+    }
+
+    let models = levinson(&autoc, max_order);
+    let orders: Vec<usize> = if params.exhaustive {
+        (1..=models.len()).collect()
+    } else {
+        // Pick the order whose expected residual plus coefficients is smallest
+        let precision = qlp_precision(n, bps) as f64;
+        let estimate = |order: usize, error: f64| {
+            let per_sample = (0.5 * (error.max(f64::MIN_POSITIVE) / n as f64).log2()).max(0.0);
+            per_sample * (n - order) as f64 + order as f64 * (precision + bps as f64)
+        };
+        models
+            .iter()
+            .enumerate()
+            .map(|(i, (_, error))| (i + 1, estimate(i + 1, *error)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(order, _)| vec![order])
+            .unwrap_or_default()
+    };
+
+    let precision = qlp_precision(n, bps);
+    let mut best: Option<(Subframe, u64)> = None;
+    for order in orders {
+        let Some((coefficients, shift)) = quantize(&models[order - 1].0, precision) else {
+            continue;
+        };
+        let Some(residual) = lpc_residual(samples, &coefficients, shift) else {
+            continue;
+        };
+        let rice = plan_rice(&residual, order, n, params.max_partition_order);
+        let bits =
+            8 + order as u64 * bps as u64 + 4 + 5 + order as u64 * precision as u64 + rice.bits;
+        if best.as_ref().is_none_or(|(_, b)| bits < *b) {
+            best = Some((
+                Subframe::Lpc {
+                    order,
+                    precision,
+                    shift,
+                    coefficients,
+                    residual,
+                    rice,
+                },
+                bits,
+            ));
+        }
+    }
+    best
+}
+
+/// Tukey window with half its length tapered
+fn tukey(i: usize, n: usize) -> f64 {
+    const ALPHA: f64 = 0.5;
+    let edge = ALPHA * (n - 1) as f64 / 2.0;
+    let x = i as f64;
+    if x < edge {
+        0.5 * (1.0 - (PI * x / edge).cos())
+    } else if x > (n - 1) as f64 - edge {
+        0.5 * (1.0 - (PI * ((n - 1) as f64 - x) / edge).cos())
+    } else {
+        1.0
+    }
+}
+
+/// Predictor coefficients and prediction error for every order up to
+/// `max_order`
+fn levinson(autoc: &[f64], max_order: usize) -> Vec<(Vec<f64>, f64)> {
+    let mut models = Vec::with_capacity(max_order);
+    let mut a = vec![0.0; max_order + 1];
+    let mut error = autoc[0];
+
+    for i in 1..=max_order {
+        let mut acc = autoc[i];
+        for j in 1..i {
+            acc -= a[j] * autoc[i - j];
+        }
+        let k = acc / error;
+        let previous = a.clone();
+        a[i] = k;
+        for j in 1..i {
+            a[j] = previous[j] - k * previous[i - j];
+        }
+        error *= 1.0 - k * k;
+        models.push((a[1..=i].to_vec(), error));
+        if error <= 0.0 {
+            break;
+        }
+    }
+    models
+}
+
+/// Coefficient precision for a block size, as the reference encoder picks it
+fn qlp_precision(block_size: usize, bps: u32) -> u32 {
+    let base = match block_size {
+        0..=192 => 7,
+        193..=384 => 8,
+        385..=576 => 9,
+        577..=1152 => 10,
+        1153..=2304 => 11,
+        2305..=4608 => 12,
+        _ => 13,
+    };
+    let extra = if bps > 16 { 2 } else { 0 };
+    (base + extra).min(MAX_QLP_PRECISION)
+}
+
+/// Quantize coefficients to `precision` bits; returns them with the shift
+/// that scales them back
+fn quantize(coefficients: &[f64], precision: u32) -> Option<(Vec<i64>, u32)> {
+    let max = coefficients.iter().fold(0.0f64, |max, c| max.max(c.abs()));
+    if max <= 0.0 || !max.is_finite() {
+        return None;
+    }
+
+    let exponent = max.log2().floor() as i32 + 1;
+    let shift = (precision as i32 - 1 - exponent).min(15);
+    if shift < 0 {
+        return None;
+    }
+    let limit = (1i64 << (precision - 1)) - 1;
+
+    // Carry each rounding error into the next coefficient
+    let mut error = 0.0;
+    let quantized = coefficients
+        .iter()
+        .map(|c| {
+            let scaled = c * (1i64 << shift) as f64 + error;
+            let q = (scaled.round() as i64).clamp(-limit - 1, limit);
+            error = scaled - q as f64;
+            q
+        })
+        .collect();
+    Some((quantized, shift as u32))
+}
+
+/// Residual of the LPC prediction, or `None` if it does not fit the 32 bits
+/// the format allows
+fn lpc_residual(samples: &[i64], coefficients: &[i64], shift: u32) -> Option<Vec<i64>> {
+    let order = coefficients.len();
+    let mut residual = Vec::with_capacity(samples.len() - order);
+    for i in order..samples.len() {
+        let prediction: i64 = coefficients
+            .iter()
+            .enumerate()
+            .map(|(j, c)| c * samples[i - j - 1])
+            .sum();
+        let r = samples[i] - (prediction >> shift);
+        if r < i32::MIN as i64 || r > i32::MAX as i64 {
+            return None;
+        }
+        residual.push(r);
+    }
+    Some(residual)
+}
+
+/// Partitioning and Rice parameters for a residual, with the size in bits
+/// of the whole residual section
+struct RicePlan {
+    partition_order: u32,
+    parameters: Vec<u32>,
+    /// 5-bit instead of 4-bit parameters
+    wide: bool,
+    bits: u64,
+}
+
+fn fold(residual: i64) -> u64 {
+    if residual >= 0 {
+        (residual as u64) << 1
+    } else {
+        ((-residual as u64) << 1) - 1
+    }
+}
+
+fn plan_rice(
+    residual: &[i64],
+    predictor_order: usize,
+    block_size: usize,
+    max_order: u32,
+) -> RicePlan {
+    let folded: Vec<u64> = residual.iter().map(|&r| fold(r)).collect();
+    let mut best: Option<RicePlan> = None;
+
+    for order in 0..=max_order {
+        let partitions = 1usize << order;
+        let length = block_size >> order;
+        if !block_size.is_multiple_of(partitions) || length <= predictor_order {
+            break;
+        }
+
+        let mut parameters = Vec::with_capacity(partitions);
+        let mut bits = 0;
+        let mut start = 0;
+        for p in 0..partitions {
+            let len = if p == 0 {
+                length - predictor_order
+            } else {
+                length
+            };
+            let (k, partition_bits) = best_parameter(&folded[start..start + len]);
+            parameters.push(k);
+            bits += partition_bits;
+            start += len;
+        }
+
+        let wide = parameters.iter().any(|&k| k > 14);
+        let bits = 2 + 4 + bits + partitions as u64 * if wide { 5 } else { 4 };
+        if best.as_ref().is_none_or(|b| bits < b.bits) {
+            best = Some(RicePlan {
+                partition_order: order,
+                parameters,
+                wide,
+                bits,
+            });
+        }
+    }
+    best.expect("partition order 0 always fits")
+}
+
+/// Rice parameter coding `values` in the fewest bits, and that many bits
+fn best_parameter(values: &[u64]) -> (u32, u64) {
+    if values.is_empty() {
+        return (0, 0);
+    }
+    let cost = |k: u32| values.iter().map(|v| (v >> k) + 1 + k as u64).sum::<u64>();
+
+    let mean = values.iter().sum::<u64>() / values.len() as u64;
+    let estimate = if mean > 0 {
+        63 - mean.leading_zeros()
+    } else {
+        0
+    };
+    let mut best = (estimate.min(30), cost(estimate.min(30)));
+    for k in [estimate.saturating_sub(1), estimate + 1] {
+        let k = k.min(30);
+        let bits = cost(k);
+        if bits < best.1 {
+            best = (k, bits);
+        }
+    }
+    best
+}
+
+fn write_subframe(w: &mut BitWriter, samples: &[i64], bps: u32, plan: &Subframe) {
+    match plan {
+        Subframe::Constant => {
+            w.write(0, 8);
+            w.write_signed(samples[0], bps);
+        }
+        Subframe::Verbatim => {
+            w.write(0b0000_0010, 8);
+            for &s in samples {
+                w.write_signed(s, bps);
+            }
+        }
+        Subframe::Fixed {
+            order,
+            residual,
+            rice,
+        } => {
+            w.write((0b00_1000 | *order as u64) << 1, 8);
+            for &s in &samples[..*order] {
+                w.write_signed(s, bps);
+            }
+            write_residual(w, residual, rice, *order, samples.len());
+        }
+        Subframe::Lpc {
+            order,
+            precision,
+            shift,
+            coefficients,
+            residual,
+            rice,
+        } => {
+            w.write((0b10_0000 | (*order as u64 - 1)) << 1, 8);
+            for &s in &samples[..*order] {
+                w.write_signed(s, bps);
+            }
+            w.write(*precision as u64 - 1, 4);
+            w.write(*shift as u64, 5);
+            for &c in coefficients {
+                w.write_signed(c, *precision);
+            }
+            write_residual(w, residual, rice, *order, samples.len());
+        }
+    }
+}
+
+fn write_residual(
+    w: &mut BitWriter,
+    residual: &[i64],
+    rice: &RicePlan,
+    predictor_order: usize,
+    block_size: usize,
+) {
+    w.write(rice.wide as u64, 2);
+    w.write(rice.partition_order as u64, 4);
+
+    let length = block_size >> rice.partition_order;
+    let mut start = 0;
+    for (p, &k) in rice.parameters.iter().enumerate() {
+        w.write(k as u64, if rice.wide { 5 } else { 4 });
+        let len = if p == 0 {
+            length - predictor_order
+        } else {
+            length
+        };
+        for &r in &residual[start..start + len] {
+            let value = fold(r);
+            w.write_unary(value >> k);
+            w.write(value & ((1 << k) - 1), k);
+        }
+        start += len;
+    }
+}
+
+/// MSB-first bit packer
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    /// Write the low `bits` bits of `value`, at most 32 at a time
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        debug_assert!(bits <= 32);
+        self.acc = (self.acc << bits) | (value & ((1u64 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1u64 << self.bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// `zeros` zero bits followed by a one
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    /// Frame numbers are coded like UTF-8 characters
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let continuation = match value {
+            0..=0x7FF => 1,
+            0x800..=0xFFFF => 2,
+            0x1_0000..=0x1F_FFFF => 3,
+            0x20_0000..=0x3FF_FFFF => 4,
+            0x400_0000..=0x7FFF_FFFF => 5,
+            _ => 6,
+        };
+        let prefix = (0xFF00u64 >> (continuation + 1)) & 0xFF;
+        self.write(prefix | (value >> (6 * continuation)), 8);
+        for i in (0..continuation).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    /// Pad with zeros to a byte boundary
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+
+    /// Complete bytes written so far
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+const CRC8_TABLE: [u8; 256] = crc8_table();
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+/// Lookup table for the frame header CRC, polynomial x^8 + x^2 + x + 1
+const fn crc8_table() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Lookup table for the frame CRC, polynomial x^16 + x^15 + x^2 + 1
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0, |crc, &b| CRC8_TABLE[(crc ^ b) as usize])
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &b| crc16_update(crc, b))
+}
+
+fn crc16_update(crc: u16, b: u8) -> u16 {
+    (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ b) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::encoding::WavEncoder;
+    use std::io::Cursor;
+
+    /// A voice-like test signal: two harmonics under a slow envelope plus a
+    /// little noise
+    fn speech_like(frames: usize, channels: usize) -> Vec<f32> {
+        let mut seed = 0x2545_F491u32;
+        let mut samples = Vec::with_capacity(frames * channels);
+        for i in 0..frames {
+            let t = i as f32 / 16000.0;
+            let envelope = 0.5 + 0.5 * (2.0 * std::f32::consts::PI * 3.0 * t).sin();
+            let voice = envelope
+                * (0.3 * (2.0 * std::f32::consts::PI * 180.0 * t).sin()
+                    + 0.1 * (2.0 * std::f32::consts::PI * 360.0 * t).sin());
+            for channel in 0..channels {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let noise = (seed as f32 / u32::MAX as f32 - 0.5) * 0.002;
+                samples.push(voice * (1.0 - 0.2 * channel as f32) + noise);
+            }
+        }
+        samples
+    }
+
+    fn decode(data: &[u8]) -> (claxon::metadata::StreamInfo, Vec<i32>) {
+        let mut reader = claxon::FlacReader::new(Cursor::new(data)).unwrap();
+        let info = reader.streaminfo();
+        let samples = reader.samples().map(|s| s.unwrap()).collect();
+        (info, samples)
+    }
+
+    #[test]
+    fn test_round_trip_is_lossless() {
+        for level in [0, 3, 5, 8] {
+            for (channels, bits) in [(1u16, 16u16), (2, 16), (2, 24), (3, 8)] {
+                let samples = speech_like(10_000, channels as usize);
+                let encoder = FlacEncoder::new()
+                    .with_compression_level(level)
+                    .with_bits_per_sample(bits);
+                let data = encoder.encode(&samples, 16000, channels).unwrap();
+
+                let (info, decoded) = decode(&data);
+                assert_eq!(info.sample_rate, 16000);
+                assert_eq!(info.channels, channels as u32);
+                assert_eq!(info.bits_per_sample, bits as u32);
+                assert_eq!(info.samples, Some(10_000));
+
+                let scale = ((1i64 << (bits - 1)) - 1) as f32;
+                let expected: Vec<i32> = samples
+                    .iter()
+                    .map(|s| (s.clamp(-1.0, 1.0) * scale).round() as i32)
+                    .collect();
+                assert!(
+                    decoded == expected,
+                    "level {} {}ch {} bit",
+                    level,
+                    channels,
+                    bits
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_compresses_speech_below_wav() {
+        let samples = speech_like(16000 * 5, 1);
+        let wav = WavEncoder::new().encode(&samples, 16000, 1).unwrap();
+        let fast = FlacEncoder::new()
+            .with_compression_level(0)
+            .encode(&samples, 16000, 1)
+            .unwrap();
+        let best = FlacEncoder::new()
+            .with_compression_level(8)
+            .encode(&samples, 16000, 1)
+            .unwrap();
+
+        assert!(
+            fast.len() < wav.len() * 3 / 4,
+            "{} vs {}",
+            fast.len(),
+            wav.len()
+        );
+        assert!(best.len() <= fast.len(), "{} vs {}", best.len(), fast.len());
+    }
+
+    #[test]
+    fn test_repair_truncated_stream() {
+        let samples = speech_like(20_000, 2);
+        let encoder = FlacEncoder::new();
+        let mut stream = FlacStream::new(Cursor::new(Vec::new()), &encoder, 16000, 2).unwrap();
+        for chunk in samples.chunks(999) {
+            stream.write(chunk).unwrap();
+        }
+        let data = stream.finish().unwrap().into_inner();
+        assert_eq!(data, encoder.encode(&samples, 16000, 2).unwrap());
+
+        // Cut the file in the middle of its fourth frame
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cut.flac");
+        std::fs::write(&path, &data[..data.len() * 7 / 10]).unwrap();
+        assert_eq!(repair_flac(&path).unwrap(), 3 * 4096);
+
+        let (info, decoded) = decode(&std::fs::read(&path).unwrap());
+        assert_eq!(info.samples, Some(3 * 4096));
+        assert!(info.max_frame_size.is_some());
+        let (_, full) = decode(&data);
+        assert_eq!(decoded, full[..decoded.len()]);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_formats() {
+        let encoder = FlacEncoder::new().with_bits_per_sample(32);
+        assert!(encoder.encode(&[0.0; 4], 16000, 1).is_err());
+        assert!(FlacEncoder::new().encode(&[0.0; 18], 16000, 9).is_err());
+
+        // Silence and odd sample rates still decode
+        let data = FlacEncoder::new().encode(&[0.0; 1000], 11025, 1).unwrap();
+        let (info, decoded) = decode(&data);
+        assert_eq!(info.sample_rate, 11025);
+        assert_eq!(decoded, vec![0; 1000]);
+    }
+}
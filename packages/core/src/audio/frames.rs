@@ -0,0 +1,222 @@
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// A fixed-size block of interleaved audio delivered while recording
+#[derive(Debug, Clone)]
+pub struct AudioFrame {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Position of the first sample relative to the start of the recording
+    pub timestamp: Duration,
+    /// Index of this frame within the subscription; gaps mean frames were dropped
+    pub sequence: u64,
+}
+
+impl AudioFrame {
+    /// Number of sample frames (samples per channel) in this block
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+    }
+}
+
+/// Receiving end of a frame subscription
+///
+/// Frames are queued in a bounded channel. The capture path never waits for a
+/// slow consumer: when the queue is full the frame is dropped and counted in
+/// [`FrameSubscription::dropped_frames`]. The stream ends when recording stops.
+pub struct FrameSubscription {
+    receiver: mpsc::Receiver<AudioFrame>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl FrameSubscription {
+    /// Wait for the next frame
+    pub async fn recv(&mut self) -> Option<AudioFrame> {
+        self.receiver.recv().await
+    }
+
+    /// Number of frames dropped because the queue was full
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for FrameSubscription {
+    type Item = AudioFrame;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+struct Subscriber {
+    sender: mpsc::Sender<AudioFrame>,
+    dropped: Arc<AtomicU64>,
+    frame_size: usize,
+    pending: Vec<f32>,
+    position: u64,
+    sequence: u64,
+}
+
+/// Slices output audio into frames for every subscriber
+pub(crate) struct FrameDispatcher {
+    subscribers: Vec<Subscriber>,
+    sample_rate: u32,
+    channels: u16,
+    position: u64,
+}
+
+impl FrameDispatcher {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Vec::new(),
+            sample_rate: 0,
+            channels: 1,
+            position: 0,
+        }
+    }
+
+    pub fn subscribe(&mut self, frame_size: usize, capacity: usize) -> FrameSubscription {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        self.subscribers.push(Subscriber {
+            sender,
+            dropped: Arc::clone(&dropped),
+            frame_size: frame_size.max(1),
+            pending: Vec::new(),
+            position: self.position,
+            sequence: 0,
+        });
+
+        FrameSubscription { receiver, dropped }
+    }
+
+    /// Prepare for a new recording in the given output format
+    ///
+    /// Subscriptions taken out since the last recording start counting from
+    /// the beginning of this one.
+    pub fn start(&mut self, sample_rate: u32, channels: u16) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self.position = 0;
+        for subscriber in &mut self.subscribers {
+            subscriber.pending.clear();
+            subscriber.position = 0;
+            subscriber.sequence = 0;
+        }
+    }
+
+    /// Feed samples in the output format
+    pub fn push(&mut self, samples: &[f32]) {
+        self.dispatch(samples, false);
+    }
+
+    /// Send the remaining audio as a final, possibly shorter, frame and
+    /// close every subscription
+    pub fn finish(&mut self) {
+        self.dispatch(&[], true);
+        self.subscribers.clear();
+        self.position = 0;
+    }
+
+    fn dispatch(&mut self, samples: &[f32], last: bool) {
+        let rate = self.sample_rate;
+        let channels = self.channels;
+        self.position += (samples.len() / channels as usize) as u64;
+
+        self.subscribers.retain_mut(|subscriber| {
+            subscriber.pending.extend_from_slice(samples);
+            let frame_len = subscriber.frame_size * channels as usize;
+
+            while subscriber.pending.len() >= frame_len
+                || (last && !subscriber.pending.is_empty())
+            {
+                let take = frame_len.min(subscriber.pending.len());
+                let frame = AudioFrame {
+                    samples: subscriber.pending.drain(..take).collect(),
+                    sample_rate: rate,
+                    channels,
+                    timestamp: Duration::from_secs_f64(subscriber.position as f64 / rate as f64),
+                    sequence: subscriber.sequence,
+                };
+                subscriber.position += (take / channels as usize) as u64;
+                subscriber.sequence += 1;
+
+                match subscriber.sender.try_send(frame) {
+                    Ok(()) => {}
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => return false,
+                }
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_frames_are_fixed_size_and_timestamped() {
+        let mut dispatcher = FrameDispatcher::new();
+        let mut frames = dispatcher.subscribe(160, 64);
+        dispatcher.start(16000, 1);
+
+        dispatcher.push(&vec![0.25; 500]);
+        dispatcher.finish();
+
+        let received: Vec<AudioFrame> = frames.by_ref().collect().await;
+        assert_eq!(received.len(), 4);
+        assert_eq!(received[0].frames(), 160);
+        assert_eq!(received[3].frames(), 20);
+        assert_eq!(received[2].timestamp, Duration::from_millis(20));
+        assert_eq!(frames.dropped_frames(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_subscription_between_recordings_starts_at_zero() {
+        let mut dispatcher = FrameDispatcher::new();
+        dispatcher.start(16000, 1);
+        dispatcher.push(&vec![0.0; 1600]);
+        dispatcher.finish();
+
+        let mut frames = dispatcher.subscribe(160, 64);
+        dispatcher.start(16000, 1);
+        dispatcher.push(&vec![0.0; 320]);
+        dispatcher.finish();
+
+        let received: Vec<AudioFrame> = frames.by_ref().collect().await;
+        assert_eq!(received.len(), 2);
+        assert_eq!((received[0].timestamp, received[0].sequence), (Duration::ZERO, 0));
+        assert_eq!(received[1].timestamp, Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_reports_overflow() {
+        let mut dispatcher = FrameDispatcher::new();
+        let mut frames = dispatcher.subscribe(100, 2);
+        dispatcher.start(16000, 1);
+
+        dispatcher.push(&vec![0.0; 500]);
+        assert_eq!(frames.dropped_frames(), 3);
+
+        let first = frames.recv().await.unwrap();
+        let second = frames.recv().await.unwrap();
+        assert_eq!((first.sequence, second.sequence), (0, 1));
+    }
+}
@@ -0,0 +1,630 @@
+// Loudness measurement (ITU-R BS.1770 / EBU R128), normalization and AGC
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use crate::audio::filter::Biquad;
+
+/// Loudness of a block below which it is ignored entirely, in LUFS
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Blocks this far below the ungated loudness are ignored, in LU
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Gating blocks are 400 ms long and start every 100 ms
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+/// Sub-blocks in the 3 s short-term window
+const SUB_BLOCKS_SHORT_TERM: usize = 30;
+
+/// Oversampling used to find peaks between samples
+const OVERSAMPLING: usize = 4;
+
+/// Input samples on each side of an interpolated point
+const INTERPOLATION_HALF_WIDTH: usize = 6;
+
+/// Lookahead of the limiter, so it can turn down ahead of a peak
+const LIMITER_LOOKAHEAD: f64 = 0.005;
+
+/// Time constant with which the limiter lets go after a peak
+const LIMITER_RELEASE: f64 = 0.1;
+
+/// Most rounds of gain and limiting [`LoudnessNormalizer`] makes, since
+/// limiting loud peaks lowers the loudness a little each time
+const NORMALIZE_PASSES: usize = 4;
+
+/// Loudness error, in LU, at which normalization stops
+const NORMALIZE_TOLERANCE: f32 = 0.1;
+
+/// Short-term loudness below which the AGC holds its gain, so pauses and
+/// background noise are not turned up
+const AGC_GATE: f64 = -50.0;
+
+/// Measures loudness in LUFS as ITU-R BS.1770-4 specifies: K-weighted,
+/// mean-square energy over 400 ms blocks, and gated for the integrated value
+pub struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<KWeighting>,
+    sub_block_len: usize,
+    /// Weighted energy summed over the current sub-block
+    accumulated: f64,
+    accumulated_frames: usize,
+    /// Mean weighted energy of every finished 100 ms sub-block
+    sub_blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            channels,
+            weights: channel_weights(channels),
+            filters: (0..channels).map(|_| KWeighting::new(sample_rate)).collect(),
+            sub_block_len: (sample_rate as usize / 10).max(1),
+            accumulated: 0.0,
+            accumulated_frames: 0,
+            sub_blocks: Vec::new(),
+        }
+    }
+
+    /// Measure interleaved samples
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for ((&sample, filter), weight) in frame.iter().zip(&mut self.filters).zip(&self.weights)
+            {
+                let filtered = filter.process(sample as f64);
+                self.accumulated += weight * filtered * filtered;
+            }
+            self.accumulated_frames += 1;
+            if self.accumulated_frames == self.sub_block_len {
+                self.sub_blocks.push(self.accumulated / self.sub_block_len as f64);
+                self.accumulated = 0.0;
+                self.accumulated_frames = 0;
+            }
+        }
+    }
+
+    /// Gated loudness of everything measured, or `None` for silence
+    pub fn integrated_lufs(&self) -> Option<f32> {
+        let blocks: Vec<f64> = self
+            .sub_blocks
+            .windows(SUB_BLOCKS_PER_BLOCK)
+            .map(|window| window.iter().sum::<f64>() / SUB_BLOCKS_PER_BLOCK as f64)
+            .filter(|&energy| to_lufs(energy) > ABSOLUTE_GATE)
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+
+        let relative_gate = to_lufs(mean(&blocks)) + RELATIVE_GATE;
+        let gated: Vec<f64> = blocks
+            .into_iter()
+            .filter(|&energy| to_lufs(energy) > relative_gate)
+            .collect();
+        Some(to_lufs(mean(&gated)) as f32)
+    }
+
+    /// Loudness of the last 400 ms
+    pub fn momentary_lufs(&self) -> Option<f32> {
+        self.recent(SUB_BLOCKS_PER_BLOCK)
+    }
+
+    /// Loudness of the last 3 s
+    pub fn short_term_lufs(&self) -> Option<f32> {
+        self.recent(SUB_BLOCKS_SHORT_TERM)
+    }
+
+    fn recent(&self, sub_blocks: usize) -> Option<f32> {
+        let start = self.sub_blocks.len().checked_sub(sub_blocks)?;
+        Some(to_lufs(mean(&self.sub_blocks[start..])) as f32)
+    }
+}
+
+/// Loudness of interleaved samples, or `None` for silence
+pub fn integrated_loudness(samples: &[f32], sample_rate: u32, channels: u16) -> Option<f32> {
+    let mut meter = LoudnessMeter::new(sample_rate, channels);
+    meter.process(samples);
+    meter.integrated_lufs()
+}
+
+/// Highest true peak of interleaved samples in dBTP, found by 4x
+/// oversampling
+pub fn true_peak_db(samples: &[f32], channels: u16) -> f32 {
+    let detector = PeakDetector::new();
+    let channels = channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    let peak = (0..frames)
+        .map(|frame| detector.peak(samples, channels, frame))
+        .fold(0.0f32, f32::max);
+    20.0 * peak.max(1e-10).log10()
+}
+
+/// Brings recordings to a target integrated loudness, with a true-peak
+/// limiter catching whatever the gain pushes over the ceiling
+///
+/// Unlike peak normalization, a single cough does not set the gain for the
+/// whole recording.
+#[derive(Debug, Clone)]
+pub struct LoudnessNormalizer {
+    target_lufs: f32,
+    true_peak_db: f32,
+}
+
+impl Default for LoudnessNormalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoudnessNormalizer {
+    /// -23 LUFS with a -1 dBTP ceiling, as EBU R128 recommends
+    pub fn new() -> Self {
+        Self {
+            target_lufs: -23.0,
+            true_peak_db: -1.0,
+        }
+    }
+
+    pub fn with_target_lufs(mut self, target_lufs: f32) -> Self {
+        self.target_lufs = target_lufs;
+        self
+    }
+
+    /// Highest true peak allowed in the output, in dBTP
+    pub fn with_true_peak_limit(mut self, true_peak_db: f32) -> Self {
+        self.true_peak_db = true_peak_db;
+        self
+    }
+
+    /// Normalize interleaved samples in place; returns the gain applied in
+    /// dB before limiting, 0 for silence
+    pub fn normalize(&self, samples: &mut [f32], sample_rate: u32, channels: u16) -> f32 {
+        let mut total_gain_db = 0.0;
+        for _ in 0..NORMALIZE_PASSES {
+            let Some(loudness) = integrated_loudness(samples, sample_rate, channels) else {
+                break;
+            };
+            let gain_db = self.target_lufs - loudness;
+            if gain_db.abs() < NORMALIZE_TOLERANCE {
+                break;
+            }
+
+            let gain = 10f32.powf(gain_db / 20.0);
+            for sample in samples.iter_mut() {
+                *sample *= gain;
+            }
+            let mut limiter = TruePeakLimiter::new(sample_rate, channels, self.true_peak_db);
+            let mut output = limiter.process(samples);
+            output.extend(limiter.flush());
+            samples.copy_from_slice(&output);
+            total_gain_db += gain_db;
+        }
+        total_gain_db
+    }
+}
+
+/// Lookahead limiter holding the true peak under a ceiling
+///
+/// The gain needed for each peak is spread over the lookahead window before
+/// it, so the limiter never clips and adds no distortion on peaks below the
+/// ceiling. Output lags input by the lookahead until
+/// [`flush`](Self::flush).
+pub struct TruePeakLimiter {
+    channels: usize,
+    ceiling: f32,
+    lookahead: usize,
+    release: f32,
+    detector: PeakDetector,
+    /// Interleaved input from `buffer_start` on
+    buffer: Vec<f32>,
+    buffer_start: u64,
+    /// Gain each frame needs to stay under the ceiling, from `frames_out`
+    /// on, kept as a monotonic queue of frame and gain for a sliding minimum
+    required: VecDeque<(u64, f32)>,
+    /// The last `lookahead` windowed minimums of `required` and their sum
+    minimums: VecDeque<f32>,
+    minimum_sum: f64,
+    gain: f32,
+    frames_in: u64,
+    frames_out: u64,
+    /// Frames whose peak has been measured
+    frames_peaked: u64,
+}
+
+impl TruePeakLimiter {
+    pub fn new(sample_rate: u32, channels: u16, ceiling_db: f32) -> Self {
+        let lookahead = ((sample_rate as f64 * LIMITER_LOOKAHEAD) as usize).max(1);
+        Self {
+            channels: channels.max(1) as usize,
+            ceiling: 10f32.powf(ceiling_db / 20.0),
+            lookahead,
+            release: (1.0 - (-1.0 / (LIMITER_RELEASE * sample_rate as f64)).exp()) as f32,
+            detector: PeakDetector::new(),
+            buffer: Vec::new(),
+            buffer_start: 0,
+            required: VecDeque::new(),
+            minimums: std::iter::repeat_n(1.0, lookahead).collect(),
+            minimum_sum: lookahead as f64,
+            gain: 1.0,
+            frames_in: 0,
+            frames_out: 0,
+            frames_peaked: 0,
+        }
+    }
+
+    /// Limit interleaved samples, returning what is ready
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.buffer.extend_from_slice(samples);
+        self.frames_in += (samples.len() / self.channels) as u64;
+        self.run(self.frames_in)
+    }
+
+    /// Push the buffered tail out, so that all input has come out
+    pub fn flush(&mut self) -> Vec<f32> {
+        let remaining = (self.frames_in - self.frames_out) as usize;
+        let padding = self.lookahead + INTERPOLATION_HALF_WIDTH + 1;
+        self.buffer
+            .extend(std::iter::repeat_n(0.0, padding * self.channels));
+        let mut output = self.run(self.frames_in + padding as u64);
+        output.truncate(remaining * self.channels);
+        output
+    }
+
+    fn run(&mut self, available: u64) -> Vec<f32> {
+        let lookahead = self.lookahead as u64;
+        let mut output = Vec::new();
+        loop {
+            // Measure peaks up to the end of the lookahead window; peaks
+            // between samples need the samples after them
+            while self.frames_peaked < self.frames_out + lookahead
+                && self.frames_peaked + (INTERPOLATION_HALF_WIDTH as u64) < available
+            {
+                let frame = (self.frames_peaked - self.buffer_start) as usize;
+                let peak = self.detector.peak(&self.buffer, self.channels, frame);
+                let gain = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+                while self.required.back().is_some_and(|&(_, g)| g >= gain) {
+                    self.required.pop_back();
+                }
+                self.required.push_back((self.frames_peaked, gain));
+                self.frames_peaked += 1;
+            }
+            if self.frames_peaked < self.frames_out + lookahead {
+                break;
+            }
+
+            while self.required.front().is_some_and(|&(frame, _)| frame < self.frames_out) {
+                self.required.pop_front();
+            }
+            let minimum = self.required.front().map_or(1.0, |&(_, gain)| gain);
+            self.minimums.push_back(minimum);
+            self.minimum_sum += minimum as f64;
+            self.minimum_sum -= self.minimums.pop_front().unwrap_or(1.0) as f64;
+
+            // Averaging the minimums ramps the gain down over the lookahead
+            // and reaches the gain each peak needs by the time it arrives
+            let target = (self.minimum_sum / self.lookahead as f64) as f32;
+            self.gain = target.min(self.gain + (1.0 - self.gain) * self.release);
+
+            let frame = (self.frames_out - self.buffer_start) as usize * self.channels;
+            output.extend(self.buffer[frame..frame + self.channels].iter().map(|s| s * self.gain));
+            self.frames_out += 1;
+        }
+
+        // Keep the samples interpolation around the next peak needs
+        let keep_from = self
+            .frames_out
+            .saturating_sub(INTERPOLATION_HALF_WIDTH as u64)
+            .max(self.buffer_start);
+        self.buffer
+            .drain(..(keep_from - self.buffer_start) as usize * self.channels);
+        self.buffer_start = keep_from;
+        output
+    }
+}
+
+/// Slow automatic gain control for live capture
+///
+/// The gain follows the short-term loudness of what is being said towards
+/// the target, changing by at most a few dB per second, and holds through
+/// pauses. A [`TruePeakLimiter`] catches the peaks, so output lags input by
+/// its lookahead until [`flush`](Self::flush).
+pub struct AutomaticGainControl {
+    meter: LoudnessMeter,
+    limiter: TruePeakLimiter,
+    sample_rate: u32,
+    channels: usize,
+    step_len: usize,
+    target_lufs: f32,
+    max_gain_db: f32,
+    max_change_db: f32,
+    gain_db: f32,
+    /// Interleaved input waiting for a whole step
+    pending: Vec<f32>,
+}
+
+impl AutomaticGainControl {
+    /// -23 LUFS, up to 24 dB of gain changing by at most 3 dB/s
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let step_len = (sample_rate as usize / 10).max(1);
+        Self {
+            meter: LoudnessMeter::new(sample_rate, channels),
+            limiter: TruePeakLimiter::new(sample_rate, channels, -1.0),
+            sample_rate,
+            channels: channels.max(1) as usize,
+            step_len,
+            target_lufs: -23.0,
+            max_gain_db: 24.0,
+            max_change_db: 3.0 * step_len as f32 / sample_rate as f32,
+            gain_db: 0.0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn with_target_lufs(mut self, target_lufs: f32) -> Self {
+        self.target_lufs = target_lufs;
+        self
+    }
+
+    /// Most the gain may boost or cut, in dB
+    pub fn with_max_gain_db(mut self, max_gain_db: f32) -> Self {
+        self.max_gain_db = max_gain_db.max(0.0);
+        self
+    }
+
+    /// Fastest the gain may change, in dB per second
+    pub fn with_max_rate(mut self, db_per_second: f32) -> Self {
+        self.max_change_db =
+            db_per_second.max(0.0) * self.step_len as f32 / self.sample_rate as f32;
+        self
+    }
+
+    /// Gain currently applied, in dB
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    /// Adjust interleaved samples, returning what is ready
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.pending.extend_from_slice(samples);
+        let step = self.step_len * self.channels;
+        let mut output = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= step {
+            let mut chunk = self.pending[offset..offset + step].to_vec();
+            offset += step;
+            self.meter.process(&chunk);
+
+            // Ramp from the previous gain to the new one across the step
+            let previous = self.gain_db;
+            if let Some(loudness) = self.meter.short_term_lufs() {
+                if loudness as f64 > AGC_GATE {
+                    let wanted = (self.target_lufs - loudness)
+                        .clamp(-self.max_gain_db, self.max_gain_db);
+                    let change = (wanted - previous).clamp(-self.max_change_db, self.max_change_db);
+                    self.gain_db = previous + change;
+                }
+            }
+            let slope = (self.gain_db - previous) / self.step_len as f32;
+            for (i, frame) in chunk.chunks_exact_mut(self.channels).enumerate() {
+                let gain_db = previous + slope * (i + 1) as f32;
+                let gain = 10f32.powf(gain_db / 20.0);
+                frame.iter_mut().for_each(|sample| *sample *= gain);
+            }
+            output.extend(self.limiter.process(&chunk));
+        }
+        self.pending.drain(..offset);
+        output
+    }
+
+    /// Push out buffered audio at the current gain
+    pub fn flush(&mut self) -> Vec<f32> {
+        let gain = 10f32.powf(self.gain_db / 20.0);
+        let tail: Vec<f32> = self.pending.drain(..).map(|sample| sample * gain).collect();
+        let mut output = self.limiter.process(&tail);
+        output.extend(self.limiter.flush());
+        output
+    }
+}
+
+/// BS.1770 channel weights: surrounds count 1.41 times, the LFE not at all
+fn channel_weights(channels: usize) -> Vec<f64> {
+    (0..channels)
+        .map(|channel| match (channels, channel) {
+            (6, 3) => 0.0,
+            (6, 4) | (6, 5) => 1.41,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+fn to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.max(1e-20).log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
+
+/// The K-weighting pre-filter: a high shelf modelling the head, then a
+/// high-pass (RLB weighting), designed for any sample rate
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        let k = (PI * 1_681.974_450_955_533 / rate).tan();
+        let q = 0.707_175_236_955_419_6;
+        let vh = 10f64.powf(3.999_843_853_973_347 / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::from_coefficients(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let k = (PI * 38.135_470_876_024_44 / rate).tan();
+        let q = 0.500_327_037_323_877_3;
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::from_coefficients(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        Self { shelf, high_pass }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.high_pass.filter(self.shelf.filter(sample))
+    }
+}
+
+/// Finds the peak around a sample by interpolating three points between it
+/// and the next with a windowed sinc
+struct PeakDetector {
+    phases: [[f32; 2 * INTERPOLATION_HALF_WIDTH]; OVERSAMPLING - 1],
+}
+
+impl PeakDetector {
+    fn new() -> Self {
+        let mut phases = [[0.0; 2 * INTERPOLATION_HALF_WIDTH]; OVERSAMPLING - 1];
+        let half_width = INTERPOLATION_HALF_WIDTH as f64;
+        for (phase, taps) in phases.iter_mut().enumerate() {
+            let fraction = (phase + 1) as f64 / OVERSAMPLING as f64;
+            for (tap, coefficient) in taps.iter_mut().enumerate() {
+                // Distance from the interpolated point to input sample
+                // `frame - half_width + 1 + tap`
+                let distance = fraction - (tap as f64 - half_width + 1.0);
+                let sinc = if distance == 0.0 {
+                    1.0
+                } else {
+                    (PI * distance).sin() / (PI * distance)
+                };
+                let window = 0.5 * (1.0 + (PI * distance / half_width).cos());
+                *coefficient = (sinc * window) as f32;
+            }
+        }
+        Self { phases }
+    }
+
+    /// Largest absolute value at and just after `frame` across channels;
+    /// samples outside `samples` count as silence
+    fn peak(&self, samples: &[f32], channels: usize, frame: usize) -> f32 {
+        let frames = samples.len() / channels;
+        let mut peak = 0.0f32;
+        for channel in 0..channels {
+            let at = |index: isize| {
+                if index < 0 || index as usize >= frames {
+                    0.0
+                } else {
+                    samples[index as usize * channels + channel]
+                }
+            };
+            peak = peak.max(at(frame as isize).abs());
+            for taps in &self.phases {
+                let value: f32 = taps
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, &c)| {
+                        c * at(frame as isize - INTERPOLATION_HALF_WIDTH as isize + 1 + tap as isize)
+                    })
+                    .sum();
+                peak = peak.max(value.abs());
+            }
+        }
+        peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, channels: u16, freq: f64, amplitude: f32, seconds: f64) -> Vec<f32> {
+        let frames = (rate as f64 * seconds) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let value = (2.0 * PI * freq * i as f64 / rate as f64).sin() as f32 * amplitude;
+                std::iter::repeat_n(value, channels as usize)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_measures_reference_tone() {
+        // EBU Tech 3341: a stereo 1 kHz sine at -23 dBFS reads -23 LUFS
+        let amplitude = 10f32.powf(-23.0 / 20.0);
+        for rate in [44100, 48000] {
+            let mut tone = sine(rate, 2, 1000.0, amplitude, 10.0);
+            let loudness = integrated_loudness(&tone, rate, 2).unwrap();
+            assert!((loudness + 23.0).abs() < 0.1, "{} Hz: {} LUFS", rate, loudness);
+
+            // Silence is gated out
+            tone.extend(vec![0.0; rate as usize * 20]);
+            let gated = integrated_loudness(&tone, rate, 2).unwrap();
+            assert!((gated - loudness).abs() < 0.1, "{} vs {}", gated, loudness);
+        }
+        assert_eq!(integrated_loudness(&[0.0; 48000], 48000, 1), None);
+
+        // A sine at a quarter of the sample rate peaks between samples
+        let tone: Vec<f32> = (0..4800)
+            .map(|i| (PI / 2.0 * i as f64 + PI / 4.0).sin() as f32 * 0.5)
+            .collect();
+        let sample_peak = 20.0 * tone.iter().fold(0.0f32, |a, b| a.max(b.abs())).log10();
+        assert!((sample_peak + 9.03).abs() < 0.05);
+        let true_peak = true_peak_db(&tone, 1);
+        assert!((true_peak + 6.02).abs() < 0.3, "{} dBTP", true_peak);
+    }
+
+    #[test]
+    fn test_normalizes_quiet_speaker_despite_cough() {
+        let rate = 16000;
+        let mut samples = sine(rate, 1, 220.0, 0.02, 30.0);
+        // A 20 ms cough near full scale
+        for (i, sample) in samples[160_000..160_320].iter_mut().enumerate() {
+            *sample = if i % 16 < 8 { 0.99 } else { -0.99 };
+        }
+
+        let gain = LoudnessNormalizer::new().normalize(&mut samples, rate, 1);
+        assert!(gain > 10.0, "gain {} dB", gain);
+        let loudness = integrated_loudness(&samples, rate, 1).unwrap();
+        assert!((loudness + 23.0).abs() < 0.5, "{} LUFS", loudness);
+        let peak = true_peak_db(&samples, 1);
+        assert!(peak <= -0.9, "{} dBTP", peak);
+    }
+
+    #[test]
+    fn test_agc_evens_out_speakers() {
+        let rate = 16000;
+        let mut samples = sine(rate, 1, 220.0, 0.01, 20.0);
+        samples.extend(sine(rate, 1, 330.0, 0.3, 20.0));
+
+        let run = |chunk: usize| {
+            let mut agc = AutomaticGainControl::new(rate, 1);
+            let mut output = Vec::new();
+            for part in samples.chunks(chunk) {
+                output.extend(agc.process(part));
+            }
+            output.extend(agc.flush());
+            output
+        };
+        let output = run(1000);
+        assert_eq!(output.len(), samples.len());
+        assert_eq!(output, run(777));
+
+        // Both speakers end up near the target once the gain has settled
+        let seconds = |from: usize, to: usize| &output[from * 16000..to * 16000];
+        for (from, to) in [(15, 20), (35, 40)] {
+            let loudness = integrated_loudness(seconds(from, to), rate, 1).unwrap();
+            assert!((loudness + 23.0).abs() < 1.0, "{}-{} s: {} LUFS", from, to, loudness);
+        }
+        assert!(true_peak_db(&output, 1) <= -0.9);
+    }
+}
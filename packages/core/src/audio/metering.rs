@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+/// Level reported for digital silence, instead of negative infinity
+pub const SILENCE_DBFS: f32 = -100.0;
+
+/// Samples at or above this magnitude count as clipped
+const CLIP_THRESHOLD: f32 = 0.999;
+
+/// Input level over one metering window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputLevel {
+    /// Root mean square of the window, 0.0 to 1.0
+    pub rms: f32,
+    /// Largest absolute sample in the window, 0.0 to 1.0
+    pub peak: f32,
+    pub rms_dbfs: f32,
+    pub peak_dbfs: f32,
+    /// Samples at full scale in the window
+    pub clipped_samples: u32,
+    /// Samples at full scale since recording started
+    pub total_clipped: u64,
+}
+
+impl Default for InputLevel {
+    fn default() -> Self {
+        Self {
+            rms: 0.0,
+            peak: 0.0,
+            rms_dbfs: SILENCE_DBFS,
+            peak_dbfs: SILENCE_DBFS,
+            clipped_samples: 0,
+            total_clipped: 0,
+        }
+    }
+}
+
+/// Convert a linear amplitude to dBFS, floored at [`SILENCE_DBFS`]
+pub fn to_dbfs(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return SILENCE_DBFS;
+    }
+    (20.0 * amplitude.log10()).max(SILENCE_DBFS)
+}
+
+/// Measures RMS, peak and clipping over fixed windows of interleaved audio
+pub struct LevelMeter {
+    window: Duration,
+    window_samples: usize,
+    sum_squares: f64,
+    peak: f32,
+    clipped: u32,
+    samples: usize,
+    total_clipped: u64,
+}
+
+impl LevelMeter {
+    pub fn new(sample_rate: u32, channels: u16, window: Duration) -> Self {
+        let mut meter = Self {
+            window,
+            window_samples: 1,
+            sum_squares: 0.0,
+            peak: 0.0,
+            clipped: 0,
+            samples: 0,
+            total_clipped: 0,
+        };
+        meter.set_format(sample_rate, channels);
+        meter
+    }
+
+    /// Switch to audio in another format, keeping the clipping total
+    pub fn set_format(&mut self, sample_rate: u32, channels: u16) {
+        let frames = (self.window.as_secs_f64() * sample_rate as f64).round() as usize;
+        self.window_samples = frames.max(1) * channels.max(1) as usize;
+        self.sum_squares = 0.0;
+        self.peak = 0.0;
+        self.clipped = 0;
+        self.samples = 0;
+    }
+
+    /// Feed interleaved samples; returns the level of the last window they
+    /// completed, if any
+    pub fn process(&mut self, samples: &[f32]) -> Option<InputLevel> {
+        let mut level = None;
+
+        for &sample in samples {
+            let magnitude = sample.abs();
+            self.sum_squares += (sample as f64) * (sample as f64);
+            self.peak = self.peak.max(magnitude);
+            if magnitude >= CLIP_THRESHOLD {
+                self.clipped += 1;
+                self.total_clipped += 1;
+            }
+
+            self.samples += 1;
+            if self.samples == self.window_samples {
+                level = Some(self.take_level());
+            }
+        }
+        level
+    }
+
+    fn take_level(&mut self) -> InputLevel {
+        let rms = (self.sum_squares / self.samples as f64).sqrt() as f32;
+        let level = InputLevel {
+            rms,
+            peak: self.peak,
+            rms_dbfs: to_dbfs(rms),
+            peak_dbfs: to_dbfs(self.peak),
+            clipped_samples: self.clipped,
+            total_clipped: self.total_clipped,
+        };
+
+        self.sum_squares = 0.0;
+        self.peak = 0.0;
+        self.clipped = 0;
+        self.samples = 0;
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windowed_levels() {
+        let mut meter = LevelMeter::new(1000, 2, Duration::from_millis(10));
+
+        // Half a window does not report yet
+        assert_eq!(meter.process(&[0.5; 10]), None);
+        let level = meter.process(&[0.5; 10]).unwrap();
+        assert!((level.rms - 0.5).abs() < 1e-6);
+        assert!((level.rms_dbfs + 6.02).abs() < 0.01);
+        assert_eq!(level.clipped_samples, 0);
+
+        let mut loud = vec![0.0; 20];
+        loud[3] = 1.0;
+        loud[4] = -1.0;
+        let level = meter.process(&loud).unwrap();
+        assert_eq!(level.peak, 1.0);
+        assert_eq!(level.peak_dbfs, 0.0);
+        assert_eq!(level.clipped_samples, 2);
+
+        let level = meter.process(&[0.0; 20]).unwrap();
+        assert_eq!(level.rms_dbfs, SILENCE_DBFS);
+        assert_eq!(level.clipped_samples, 0);
+        assert_eq!(level.total_clipped, 2);
+    }
+}
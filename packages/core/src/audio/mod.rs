@@ -1,7 +1,59 @@
+pub mod autostop;
 pub mod capture;
+pub mod channels;
+pub mod decoding;
+pub mod devices;
 pub mod encoding;
+pub mod events;
+pub mod file_source;
+pub mod filter;
+pub mod flac;
+pub mod frames;
+pub mod loudness;
+pub mod metering;
+pub mod multi_source;
+pub(crate) mod ogg;
+#[cfg(feature = "opus")]
+pub mod opus;
+pub(crate) mod pipeline;
 pub mod preprocessing;
+pub mod preroll;
+pub mod recording;
+pub mod resampler;
+pub mod ring;
+pub mod signal;
+pub mod source;
+pub mod spill;
+pub(crate) mod stream;
+pub mod vad;
+pub(crate) mod webm;
 
-pub use capture::{AudioRecorder, AudioConfig, AudioFormat};
-pub use encoding::{WavEncoder, AudioEncoder};
-pub use preprocessing::{VoiceActivityDetector, AudioPreprocessor};
+pub use autostop::{StopPolicy, StopReason};
+pub use capture::{AudioRecorder, AudioConfig, AudioFormat, RecorderDiagnostics};
+pub use channels::ChannelMapping;
+pub use decoding::{AudioDecoder, DecodedAudio, FileDecoder};
+pub use devices::{list_hosts, list_input_devices, DeviceSelector, HostInfo, InputDeviceInfo};
+pub use encoding::{
+    AudioEncoder, EncoderSink, StreamingEncoder, WavEncoder, WavMetadata, WavSampleFormat,
+};
+pub use events::{RecorderEvent, RecoveryPolicy};
+pub use file_source::WavFileSource;
+pub use filter::{Biquad, FilterChain, FilterKind};
+pub use flac::{repair_flac, FlacEncoder};
+pub use frames::{AudioFrame, FrameSubscription};
+pub use loudness::{
+    AutomaticGainControl, LoudnessMeter, LoudnessNormalizer, TruePeakLimiter,
+};
+pub use metering::{InputLevel, LevelMeter};
+pub use multi_source::{MultiRecording, MultiSourceRecorder, Track};
+pub use ogg::repair_ogg;
+#[cfg(feature = "opus")]
+pub use opus::{OpusApplication, OpusEncoder};
+pub use preprocessing::{AudioPreprocessor, NoiseSuppressor, VoiceActivityDetector};
+pub use preroll::PreRollBuffer;
+pub use recording::{Dropout, DropoutCause, PauseInterval, Recording, StreamGap};
+pub use resampler::{AudioResampler, ResampleQuality};
+pub use signal::{Signal, SignalSource, SyntheticSpeaker};
+pub use source::{AudioSource, CpalSource, Pacing, SourceFormat, SourceNotifier, SourceOutput};
+pub use spill::{repair_audio_file, repair_wav, SpillWriter};
+pub use vad::{SpeechDetector, SpeechSegment, VadConfig, VadMode};
@@ -0,0 +1,384 @@
+use std::time::{Duration, SystemTime};
+use crate::audio::capture::{AudioConfig, AudioRecorder};
+use crate::audio::channels::ChannelMapping;
+use crate::audio::devices::DeviceSelector;
+use crate::audio::recording::{Dropout, PauseInterval, Recording, StreamGap};
+use crate::audio::source::{AudioSource, CpalSource};
+use crate::utils::error::{Result, VoicePAError};
+
+/// Records several sources at once, e.g. the microphone and the system audio
+/// of a call, as separate tracks on a common timeline
+///
+/// Every track is converted to the configured sample rate and channel count,
+/// so tracks can be compared sample for sample and mixed down. Tracks are
+/// lined up on the wall clock at their start and across gaps and dropouts
+/// only; drift between the clocks of different devices is not compensated,
+/// so long recordings from separate devices may slowly fall out of step.
+pub struct MultiSourceRecorder<S: AudioSource = CpalSource> {
+    config: AudioConfig,
+    tracks: Vec<(String, AudioRecorder<S>)>,
+}
+
+/// One source's audio on the common timeline
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub label: String,
+    pub device_name: String,
+    /// Interleaved samples, with silence where the source started late, lost
+    /// its stream or dropped audio
+    pub samples: Vec<f32>,
+    /// How long after the earliest track this one's first sample was
+    /// captured
+    pub offset: Duration,
+    pub gaps: Vec<StreamGap>,
+    pub dropouts: Vec<Dropout>,
+}
+
+/// Sample-aligned tracks returned when a multi-source recording ends
+#[derive(Debug, Clone)]
+pub struct MultiRecording {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Number of sample frames in every track
+    pub frames: u64,
+    /// Wall-clock time the earliest track's first sample was captured
+    pub started_at: SystemTime,
+    pub pauses: Vec<PauseInterval>,
+    pub tracks: Vec<Track>,
+}
+
+impl MultiRecording {
+    pub fn track(&self, label: &str) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.label == label)
+    }
+
+    /// Duration of the recorded audio, excluding pauses
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.sample_rate as f64)
+    }
+
+    /// Sum of all tracks, clamped to full scale
+    pub fn mixdown(&self) -> Vec<f32> {
+        let mut mix = vec![0.0f32; self.frames as usize * self.channels as usize];
+        for track in &self.tracks {
+            for (mixed, sample) in mix.iter_mut().zip(&track.samples) {
+                *mixed += sample;
+            }
+        }
+        for sample in &mut mix {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+        mix
+    }
+}
+
+impl MultiSourceRecorder {
+    /// Record the microphone selected by `config.device` as track `"mic"`
+    /// and the system audio monitor as track `"system"`
+    pub fn mic_and_monitor(config: AudioConfig) -> Result<Self> {
+        let mic = CpalSource::new(&config.device)?;
+        let monitor = CpalSource::new(&DeviceSelector::Monitor)?;
+        Ok(Self::new(config)?
+            .with_track("mic", mic)
+            .with_track("system", monitor))
+    }
+}
+
+impl<S: AudioSource> MultiSourceRecorder<S> {
+    /// Create a recorder without tracks
+    ///
+    /// Tracks are aligned in memory, so `config` must not ask for native
+    /// formats, separate channels or spilling to disk. It must not set a
+    /// stop policy either, as each track would stop and be trimmed on its
+    /// own.
+    pub fn new(config: AudioConfig) -> Result<Self> {
+        if config.native_format || config.channel_mapping == ChannelMapping::Separate {
+            return Err(VoicePAError::Config(
+                "Multi-source recordings need a common output format".to_string(),
+            ));
+        }
+        if config.spill_path.is_some() {
+            return Err(VoicePAError::Config(
+                "Multi-source recordings cannot spill to disk".to_string(),
+            ));
+        }
+        if config.stop_policy.is_enabled() {
+            return Err(VoicePAError::Config(
+                "Multi-source recordings do not support a stop policy".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            config,
+            tracks: Vec::new(),
+        })
+    }
+
+    /// Add a track recorded from `source`
+    pub fn with_track(mut self, label: impl Into<String>, source: S) -> Self {
+        let recorder = AudioRecorder::with_source(source, self.config.clone());
+        self.tracks.push((label.into(), recorder));
+        self
+    }
+
+    /// The recorder behind a track, for its events and levels
+    pub fn recorder(&self, label: &str) -> Option<&AudioRecorder<S>> {
+        self.tracks
+            .iter()
+            .find(|(name, _)| name == label)
+            .map(|(_, recorder)| recorder)
+    }
+
+    pub fn track_labels(&self) -> Vec<String> {
+        self.tracks.iter().map(|(label, _)| label.clone()).collect()
+    }
+
+    /// Start recording every track; if one fails to start, the others are
+    /// stopped again
+    pub async fn start_recording(&mut self) -> Result<()> {
+        if self.tracks.is_empty() {
+            return Err(VoicePAError::Config("No tracks to record".to_string()));
+        }
+
+        for index in 0..self.tracks.len() {
+            if let Err(e) = self.tracks[index].1.start_recording().await {
+                for (_, recorder) in &mut self.tracks[..index] {
+                    let _ = recorder.finish_recording().await;
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn pause_recording(&mut self) -> Result<()> {
+        for (_, recorder) in &mut self.tracks {
+            recorder.pause_recording().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn resume_recording(&mut self) -> Result<()> {
+        for (_, recorder) in &mut self.tracks {
+            recorder.resume_recording().await?;
+        }
+        Ok(())
+    }
+
+    /// Check if any track is still recording
+    pub fn is_recording(&self) -> bool {
+        self.tracks.iter().any(|(_, recorder)| recorder.is_recording())
+    }
+
+    /// Stop every track and align them on a common timeline
+    pub async fn finish_recording(&mut self) -> Result<MultiRecording> {
+        let mut recordings = Vec::with_capacity(self.tracks.len());
+        let mut failure = None;
+        for (label, recorder) in &mut self.tracks {
+            match recorder.finish_recording().await {
+                Ok(recording) => {
+                    recordings.push((label.clone(), recorder.device_name(), recording))
+                }
+                Err(e) => failure = failure.or(Some(e)),
+            }
+        }
+        if let Some(e) = failure {
+            return Err(e);
+        }
+
+        Ok(align_tracks(
+            recordings,
+            self.config.sample_rate,
+            self.config.channels,
+        ))
+    }
+}
+
+/// Line recordings up by the wall-clock time of their first sample and pad
+/// them to the same length
+fn align_tracks(
+    recordings: Vec<(String, String, Recording)>,
+    sample_rate: u32,
+    channels: u16,
+) -> MultiRecording {
+    let started_at = recordings
+        .iter()
+        .map(|(_, _, recording)| recording.started_at)
+        .min()
+        .unwrap_or_else(SystemTime::now);
+    let pauses = recordings
+        .first()
+        .map(|(_, _, recording)| recording.pauses.clone())
+        .unwrap_or_default();
+
+    let mut tracks: Vec<Track> = recordings
+        .into_iter()
+        .map(|(label, device_name, recording)| {
+            let offset = recording
+                .started_at
+                .duration_since(started_at)
+                .unwrap_or_default();
+            Track {
+                label,
+                device_name,
+                samples: align(&recording, offset),
+                offset,
+                gaps: recording.gaps,
+                dropouts: recording.dropouts,
+            }
+        })
+        .collect();
+
+    let len = tracks.iter().map(|track| track.samples.len()).max().unwrap_or(0);
+    for track in &mut tracks {
+        track.samples.resize(len, 0.0);
+    }
+
+    MultiRecording {
+        sample_rate,
+        channels,
+        frames: (len / channels.max(1) as usize) as u64,
+        started_at,
+        pauses,
+        tracks,
+    }
+}
+
+/// Samples of `recording` delayed by `offset`, with silence in place of its
+/// stream gaps and dropouts
+fn align(recording: &Recording, offset: Duration) -> Vec<f32> {
+    let channels = recording.channels.max(1) as usize;
+    let to_frames = |duration: Duration| {
+        (duration.as_secs_f64() * recording.sample_rate as f64).round() as usize
+    };
+    let total = recording.samples.len() / channels;
+
+    let mut holes: Vec<(Duration, Duration)> = recording
+        .gaps
+        .iter()
+        .map(|gap| (gap.position, gap.duration))
+        .chain(recording.dropouts.iter().map(|d| (d.position, d.duration)))
+        .collect();
+    holes.sort_by_key(|&(position, _)| position);
+
+    let mut samples = vec![0.0; to_frames(offset) * channels];
+    let mut cursor = 0;
+    for (position, duration) in holes {
+        let at = to_frames(position).clamp(cursor, total);
+        samples.extend_from_slice(&recording.samples[cursor * channels..at * channels]);
+        samples.resize(samples.len() + to_frames(duration) * channels, 0.0);
+        cursor = at;
+    }
+    samples.extend_from_slice(&recording.samples[cursor * channels..total * channels]);
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::autostop::StopPolicy;
+    use crate::audio::recording::DropoutCause;
+    use crate::audio::signal::{Signal, SignalSource};
+
+    fn recording(samples: Vec<f32>, started_at: SystemTime) -> Recording {
+        Recording {
+            frames: samples.len() as u64,
+            samples,
+            sample_rate: 1000,
+            channels: 1,
+            file: None,
+            started_at,
+            pauses: Vec::new(),
+            gaps: Vec::new(),
+            dropouts: Vec::new(),
+            stop_reason: None,
+        }
+    }
+
+    #[test]
+    fn test_tracks_line_up_on_wall_clock() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mic = recording(vec![0.5; 100], start);
+
+        // The monitor started 20 ms later and dropped 10 ms of audio after
+        // its first 30 ms
+        let mut system = recording(vec![0.25; 60], start + Duration::from_millis(20));
+        system.dropouts.push(Dropout {
+            position: Duration::from_millis(30),
+            duration: Duration::from_millis(10),
+            cause: DropoutCause::Skipped,
+        });
+
+        let multi = align_tracks(
+            vec![
+                ("mic".to_string(), "Built-in".to_string(), mic),
+                ("system".to_string(), "Monitor".to_string(), system),
+            ],
+            1000,
+            1,
+        );
+        assert_eq!(multi.started_at, start);
+        assert_eq!(multi.frames, 100);
+
+        let system = multi.track("system").unwrap();
+        assert_eq!(system.offset, Duration::from_millis(20));
+        let expected = [
+            vec![0.0; 20],
+            vec![0.25; 30],
+            vec![0.0; 10],
+            vec![0.25; 30],
+            vec![0.0; 10],
+        ]
+        .concat();
+        assert_eq!(system.samples, expected);
+
+        let mix = multi.mixdown();
+        assert_eq!(mix[10], 0.5);
+        assert_eq!(mix[25], 0.75);
+        assert_eq!(mix[55], 0.5);
+    }
+
+    #[test]
+    fn test_stop_policy_is_rejected() {
+        let config = AudioConfig {
+            stop_policy: StopPolicy {
+                max_duration: Some(Duration::from_secs(60)),
+                ..StopPolicy::default()
+            },
+            ..AudioConfig::default()
+        };
+        assert!(MultiSourceRecorder::<SignalSource>::new(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_records_two_sources_together() {
+        let sine = |frequency| {
+            SignalSource::new(
+                Signal::Sine {
+                    frequency,
+                    amplitude: 0.4,
+                },
+                48000,
+                2,
+            )
+        };
+        let mut recorder = MultiSourceRecorder::new(AudioConfig::default())
+            .unwrap()
+            .with_track("mic", sine(440.0))
+            .with_track("system", sine(660.0));
+        assert_eq!(recorder.track_labels(), vec!["mic", "system"]);
+
+        recorder.start_recording().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let multi = recorder.finish_recording().await.unwrap();
+
+        assert_eq!(multi.tracks.len(), 2);
+        assert!((0.3..0.6).contains(&multi.duration().as_secs_f64()), "{:?}", multi.duration());
+        for track in &multi.tracks {
+            assert_eq!(track.samples.len() as u64, multi.frames);
+            assert!(track.offset < Duration::from_millis(50), "{:?}", track.offset);
+        }
+        let peak = multi.mixdown().iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak > 0.5, "{}", peak);
+    }
+}
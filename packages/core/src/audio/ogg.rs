@@ -0,0 +1,163 @@
+// Ogg container pages (RFC 3533) for the Opus encoder and file repair
+
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use crate::utils::error::{Result, VoicePAError};
+
+/// Serial number of the single logical stream in each file
+#[cfg(feature = "opus")]
+const STREAM_SERIAL: u32 = 0x7670_6121;
+
+#[cfg(feature = "opus")]
+pub(crate) const PAGE_FIRST: u8 = 0x02;
+pub(crate) const PAGE_LAST: u8 = 0x04;
+
+/// Writes pages of a single Ogg logical stream
+#[cfg(feature = "opus")]
+pub(crate) struct OggWriter<W: Write> {
+    sink: W,
+    sequence: u32,
+    page: Vec<u8>,
+}
+
+#[cfg(feature = "opus")]
+impl<W: Write> OggWriter<W> {
+    pub fn new(sink: W) -> Self {
+        Self {
+            sink,
+            sequence: 0,
+            page: Vec::new(),
+        }
+    }
+
+    /// Write a page of whole packets; they must fit in 255 lacing values
+    pub fn write_page(&mut self, packets: &[&[u8]], granule: u64, flags: u8) -> Result<()> {
+        let page = &mut self.page;
+        page.clear();
+        page.extend_from_slice(b"OggS");
+        page.push(0);
+        page.push(flags);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&STREAM_SERIAL.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+
+        // A packet is laced as 255s followed by a value below 255, so one
+        // that is a multiple of 255 bytes long ends with a 0
+        let lacing: Vec<u8> = packets
+            .iter()
+            .flat_map(|packet| {
+                std::iter::repeat_n(255, packet.len() / 255).chain([(packet.len() % 255) as u8])
+            })
+            .collect();
+        debug_assert!(lacing.len() <= 255);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        for packet in packets {
+            page.extend_from_slice(packet);
+        }
+
+        let crc = crc32(page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.sink.write_all(page)?;
+        self.sequence += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.sink.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+}
+
+/// Truncate an Ogg/Opus file after its last intact page and mark that page
+/// as the end of the stream, e.g. after a crash while an Opus stream was
+/// open
+///
+/// Returns the number of 48 kHz sample frames in the repaired file, after
+/// the pre-skip.
+pub fn repair_ogg(path: impl AsRef<Path>) -> Result<u64> {
+    let path = path.as_ref();
+    let invalid = |reason: &str| {
+        VoicePAError::Storage(format!("Cannot repair {}: {}", path.display(), reason))
+    };
+
+    let data = std::fs::read(path)?;
+    let mut position = 0;
+    let mut last_page = None;
+    while let Some(len) = page_len(&data[position..]) {
+        last_page = Some(position);
+        position += len;
+    }
+    let last_page = last_page.ok_or_else(|| invalid("no intact Ogg page"))?;
+
+    // The first page holds the identification header
+    let pre_skip = match data.get(28..40) {
+        Some(head) if head.starts_with(b"OpusHead") => u16::from_le_bytes([head[10], head[11]]),
+        _ => return Err(invalid("not an Ogg/Opus file")),
+    };
+
+    let mut page = data[last_page..position].to_vec();
+    page[5] |= PAGE_LAST;
+    page[22..26].fill(0);
+    let crc = crc32(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(position as u64)?;
+    file.seek(SeekFrom::Start(last_page as u64))?;
+    file.write_all(&page)?;
+    file.sync_all()?;
+
+    let granule = u64::from_le_bytes(page[6..14].try_into().unwrap_or_default());
+    Ok(granule.saturating_sub(pre_skip as u64))
+}
+
+/// Length of the page at the start of `data`, if it is complete and its
+/// checksum matches
+fn page_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 27 || !data.starts_with(b"OggS") {
+        return None;
+    }
+    let segments = data[26] as usize;
+    let lacing = data.get(27..27 + segments)?;
+    let len = 27 + segments + lacing.iter().map(|&l| l as usize).sum::<usize>();
+
+    let mut page = data.get(..len)?.to_vec();
+    let stored = u32::from_le_bytes([page[22], page[23], page[24], page[25]]);
+    page[22..26].fill(0);
+    (crc32(&page) == stored).then_some(len)
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Ogg page checksum: CRC-32 without reflection or final inversion
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |crc, &b| {
+        (crc << 8) ^ CRC32_TABLE[((crc >> 24) as u8 ^ b) as usize]
+    })
+}
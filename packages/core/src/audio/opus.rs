@@ -0,0 +1,467 @@
+use std::io::Write;
+use audiopus::coder::{Decoder, Encoder};
+use audiopus::{Application, Bitrate, Channels, SampleRate, Signal};
+use crate::audio::encoding::{AudioEncoder, EncoderSink, StreamingEncoder};
+use crate::audio::ogg::{OggWriter, PAGE_FIRST, PAGE_LAST};
+use crate::audio::resampler::{AudioResampler, ResampleQuality};
+use crate::utils::error::{Result, VoicePAError};
+
+/// Opus always codes 48 kHz audio; other rates are converted first
+const OPUS_RATE: u32 = 48000;
+
+/// Samples per channel in each 20 ms packet
+const FRAME_SAMPLES: usize = 960;
+
+/// Largest packet libopus is allowed to produce
+const MAX_PACKET: usize = 4000;
+
+/// Longest Opus packet, 120 ms, in samples per channel
+const MAX_PACKET_SAMPLES: usize = 5760;
+
+/// Bitrate used by [`OpusEncoder::new`]
+const DEFAULT_BITRATE: u32 = 24_000;
+
+/// Audio packets per Ogg page, one second of audio
+const PACKETS_PER_PAGE: usize = 50;
+
+/// What libopus tunes its encoding for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpusApplication {
+    /// Voice signal, favouring intelligibility at low bitrates
+    #[default]
+    Speech,
+    /// General audio such as music
+    Audio,
+}
+
+/// Lossy Ogg/Opus encoder, a fraction of the size of a WAV file for uploads
+///
+/// Input is converted to 48 kHz, and more than two channels are mixed down
+/// to stereo.
+pub struct OpusEncoder {
+    bitrate: u32,
+    application: OpusApplication,
+}
+
+impl Default for OpusEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpusEncoder {
+    /// 24 kbit/s tuned for speech
+    pub fn new() -> Self {
+        Self {
+            bitrate: DEFAULT_BITRATE,
+            application: OpusApplication::Speech,
+        }
+    }
+
+    /// Target bitrate in bits per second, from 6 to 510 kbit/s
+    pub fn with_bitrate(mut self, bitrate: u32) -> Self {
+        self.bitrate = bitrate.clamp(6_000, 510_000);
+        self
+    }
+
+    pub fn with_application(mut self, application: OpusApplication) -> Self {
+        self.application = application;
+        self
+    }
+
+    fn build(&self, channels: Channels) -> Result<Encoder> {
+        let (application, signal) = match self.application {
+            OpusApplication::Speech => (Application::Voip, Signal::Voice),
+            OpusApplication::Audio => (Application::Audio, Signal::Auto),
+        };
+        let mut encoder =
+            Encoder::new(SampleRate::Hz48000, channels, application).map_err(opus_error)?;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(self.bitrate as i32))
+            .map_err(opus_error)?;
+        encoder.set_signal(signal).map_err(opus_error)?;
+        Ok(encoder)
+    }
+}
+
+impl AudioEncoder for OpusEncoder {
+    fn encode(&self, samples: &[f32], sample_rate: u32, channels: u16) -> Result<Vec<u8>> {
+        let mut stream = OpusStream::new(Vec::new(), self, sample_rate, channels)?;
+        stream.write(samples)?;
+        stream.finish()
+    }
+
+    fn open_stream(
+        &self,
+        sink: Box<dyn EncoderSink>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Box<dyn StreamingEncoder>> {
+        Ok(Box::new(OpusStream::new(sink, self, sample_rate, channels)?))
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "audio/ogg"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "ogg"
+    }
+}
+
+/// Streaming Ogg/Opus writer
+///
+/// Each page holds a second of packets and is flushed once complete, so a
+/// file cut short loses at most about a second; [`repair_ogg`]
+/// marks its last intact page as the end of the stream.
+///
+/// [`repair_ogg`]: crate::audio::ogg::repair_ogg
+struct OpusStream<W: Write> {
+    ogg: OggWriter<W>,
+    encoder: Encoder,
+    resampler: AudioResampler,
+    channels: usize,
+    pre_skip: usize,
+    /// 48 kHz samples waiting for a whole packet
+    pcm: Vec<f32>,
+    /// Input sample frames, at the input rate
+    frames: u64,
+    /// Sample frames at 48 kHz
+    output_frames: u64,
+    packets: u64,
+    page: Vec<Vec<u8>>,
+    segments: usize,
+    packet: Vec<u8>,
+}
+
+impl<W: Write> OpusStream<W> {
+    fn new(sink: W, encoder: &OpusEncoder, sample_rate: u32, channels: u16) -> Result<Self> {
+        if sample_rate == 0 || channels == 0 {
+            return Err(VoicePAError::Encoding(format!(
+                "Unsupported Opus input: {} Hz, {} channels",
+                sample_rate, channels
+            )));
+        }
+
+        let opus_channels = channels.min(2);
+        let resampler = AudioResampler::new(
+            sample_rate,
+            channels,
+            OPUS_RATE,
+            opus_channels,
+            ResampleQuality::Balanced,
+        )?;
+        let opus = encoder.build(match opus_channels {
+            1 => Channels::Mono,
+            _ => Channels::Stereo,
+        })?;
+        let pre_skip = opus.lookahead().map_err(opus_error)? as usize;
+
+        let mut ogg = OggWriter::new(sink);
+        ogg.write_page(
+            &[&opus_head(opus_channels, pre_skip as u16, sample_rate)],
+            0,
+            PAGE_FIRST,
+        )?;
+        ogg.write_page(&[&opus_tags()], 0, 0)?;
+        ogg.flush()?;
+
+        Ok(Self {
+            ogg,
+            encoder: opus,
+            resampler,
+            channels: opus_channels as usize,
+            pre_skip,
+            pcm: Vec::new(),
+            frames: 0,
+            output_frames: 0,
+            packets: 0,
+            page: Vec::with_capacity(PACKETS_PER_PAGE),
+            segments: 0,
+            packet: vec![0; MAX_PACKET],
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        self.frames += (samples.len() / self.resampler.input_channels() as usize) as u64;
+        let pcm = self.resampler.process(samples)?;
+        self.push(&pcm)
+    }
+
+    /// Encode what is left and write the last page, whose granule position
+    /// tells decoders where the audio ends
+    fn finish(mut self) -> Result<W> {
+        let tail = self.resampler.flush()?;
+        self.push(&tail)?;
+
+        // The encoder lags by `pre_skip` samples, so pad with at least that
+        // much silence to get the end out, up to a whole number of packets
+        let packets = (self.output_frames as usize + self.pre_skip)
+            .div_ceil(FRAME_SAMPLES)
+            .max(self.packets as usize + 1);
+        let padding = (packets - self.packets as usize) * FRAME_SAMPLES * self.channels;
+        let granule = self.pre_skip as u64 + self.output_frames;
+        self.push(&vec![0.0; padding - self.pcm.len()])?;
+
+        let packets: Vec<&[u8]> = self.page.iter().map(Vec::as_slice).collect();
+        self.ogg.write_page(&packets, granule, PAGE_LAST)?;
+        self.ogg.flush()?;
+        Ok(self.ogg.into_inner())
+    }
+
+    /// Queue 48 kHz samples and encode every whole packet
+    fn push(&mut self, pcm: &[f32]) -> Result<()> {
+        self.output_frames += (pcm.len() / self.channels) as u64;
+        self.pcm.extend_from_slice(pcm);
+
+        let frame_len = FRAME_SAMPLES * self.channels;
+        let mut offset = 0;
+        while self.pcm.len() - offset >= frame_len {
+            let frame = &self.pcm[offset..offset + frame_len];
+            offset += frame_len;
+            let len = self
+                .encoder
+                .encode_float(frame, &mut self.packet)
+                .map_err(opus_error)?;
+            let lacing = len / 255 + 1;
+            if self.page.len() == PACKETS_PER_PAGE || self.segments + lacing > 255 {
+                let packets: Vec<&[u8]> = self.page.iter().map(Vec::as_slice).collect();
+                self.ogg
+                    .write_page(&packets, self.packets * FRAME_SAMPLES as u64, 0)?;
+                self.ogg.flush()?;
+                self.page.clear();
+                self.segments = 0;
+            }
+            self.page.push(self.packet[..len].to_vec());
+            self.segments += lacing;
+            self.packets += 1;
+        }
+        self.pcm.drain(..offset);
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> StreamingEncoder for OpusStream<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        OpusStream::write(self, samples)
+    }
+
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn finalize(self: Box<Self>) -> Result<()> {
+        self.finish().map(drop)
+    }
+}
+
+/// Decodes the Opus packets of a WebM or MP4 track to 48 kHz
+pub(crate) struct OpusPacketDecoder {
+    decoder: Decoder,
+    channels: usize,
+    skip: usize,
+    buffer: Vec<f32>,
+}
+
+impl OpusPacketDecoder {
+    /// `head` is the track's identification header, which gives the channel
+    /// count and how many leading samples to drop
+    pub fn new(head: Option<&[u8]>, channels: u16) -> Result<Self> {
+        let (channels, pre_skip) = match head {
+            Some(head) if head.len() >= 12 && head.starts_with(b"OpusHead") => {
+                (head[9] as u16, u16::from_le_bytes([head[10], head[11]]) as usize)
+            }
+            _ => (channels, 0),
+        };
+        let opus_channels = match channels {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            _ => {
+                return Err(VoicePAError::Decoding(format!(
+                    "Unsupported Opus channel count: {}",
+                    channels
+                )))
+            }
+        };
+
+        Ok(Self {
+            decoder: Decoder::new(SampleRate::Hz48000, opus_channels).map_err(opus_decoding_error)?,
+            channels: channels as usize,
+            skip: pre_skip,
+            buffer: vec![0.0; MAX_PACKET_SAMPLES * channels as usize],
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    /// Decode one packet onto `out`
+    pub fn decode(&mut self, packet: &[u8], out: &mut Vec<f32>) -> Result<()> {
+        let frames = self
+            .decoder
+            .decode_float(
+                Some(packet.try_into().map_err(opus_decoding_error)?),
+                (&mut self.buffer).try_into().map_err(opus_decoding_error)?,
+                false,
+            )
+            .map_err(opus_decoding_error)?;
+        let skip = self.skip.min(frames);
+        self.skip -= skip;
+        out.extend_from_slice(&self.buffer[skip * self.channels..frames * self.channels]);
+        Ok(())
+    }
+}
+
+fn opus_error(e: audiopus::Error) -> VoicePAError {
+    VoicePAError::Encoding(format!("Opus: {}", e))
+}
+
+fn opus_decoding_error(e: audiopus::Error) -> VoicePAError {
+    VoicePAError::Decoding(format!("Opus: {}", e))
+}
+
+/// Identification header (RFC 7845, section 5.1)
+fn opus_head(channels: u16, pre_skip: u16, input_rate: u32) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1);
+    head.push(channels as u8);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&input_rate.to_le_bytes());
+    // Output gain, then channel mapping family 0 (mono or stereo)
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+/// Comment header with the vendor string and no user comments
+fn opus_tags() -> Vec<u8> {
+    let vendor = concat!("voice-pa-core ", env!("CARGO_PKG_VERSION"));
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor.as_bytes());
+    tags.extend_from_slice(&0u32.to_le_bytes());
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::encoding::WavEncoder;
+    use crate::audio::ogg::crc32;
+
+    struct Page {
+        flags: u8,
+        granule: u64,
+        packets: Vec<Vec<u8>>,
+    }
+
+    /// Split an Ogg stream into pages, checking their checksums
+    fn read_pages(mut data: &[u8]) -> Vec<Page> {
+        let mut pages = Vec::new();
+        while !data.is_empty() {
+            assert_eq!(&data[..4], b"OggS");
+            let segments = data[26] as usize;
+            let lacing = &data[27..27 + segments];
+            let len = 27 + segments + lacing.iter().map(|&l| l as usize).sum::<usize>();
+
+            let mut page = data[..len].to_vec();
+            page[22..26].fill(0);
+            assert_eq!(crc32(&page), u32::from_le_bytes(data[22..26].try_into().unwrap()));
+
+            let mut packets = Vec::new();
+            let mut packet = Vec::new();
+            let mut body = &data[27 + segments..len];
+            for &l in lacing {
+                packet.extend_from_slice(&body[..l as usize]);
+                body = &body[l as usize..];
+                if l < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                }
+            }
+            pages.push(Page {
+                flags: data[5],
+                granule: u64::from_le_bytes(data[6..14].try_into().unwrap()),
+                packets,
+            });
+            data = &data[len..];
+        }
+        pages
+    }
+
+    #[test]
+    fn test_ogg_opus_round_trip() {
+        let samples: Vec<f32> = (0..16000)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin() * 0.5)
+            .collect();
+        let encoder = OpusEncoder::new();
+        let data = encoder.encode(&samples, 16000, 1).unwrap();
+        let wav = WavEncoder::new().encode(&samples, 16000, 1).unwrap();
+        assert!(data.len() * 8 < wav.len(), "{} vs {} bytes", data.len(), wav.len());
+        assert_eq!(encoder.mime_type(), "audio/ogg");
+
+        let pages = read_pages(&data);
+        let head = &pages[0].packets[0];
+        assert_eq!(pages[0].flags, PAGE_FIRST);
+        assert_eq!(&head[..8], b"OpusHead");
+        assert_eq!(head[9], 1);
+        assert_eq!(u32::from_le_bytes(head[12..16].try_into().unwrap()), 16000);
+        assert_eq!(&pages[1].packets[0][..8], b"OpusTags");
+
+        // One second at 48 kHz after the pre-skip
+        let pre_skip = u16::from_le_bytes([head[10], head[11]]) as u64;
+        let last = pages.last().unwrap();
+        assert_eq!(last.flags, PAGE_LAST);
+        assert_eq!(last.granule, pre_skip + 48000);
+
+        let mut decoder = Decoder::new(SampleRate::Hz48000, Channels::Mono).unwrap();
+        let mut decoded = Vec::new();
+        let mut buffer = vec![0.0f32; 5760];
+        for packet in pages[2..].iter().flat_map(|page| &page.packets) {
+            let n = decoder
+                .decode_float(
+                    Some(packet.as_slice().try_into().unwrap()),
+                    (&mut buffer).try_into().unwrap(),
+                    false,
+                )
+                .unwrap();
+            decoded.extend_from_slice(&buffer[..n]);
+        }
+        let decoded = &decoded[pre_skip as usize..(last.granule as usize)];
+        assert_eq!(decoded.len(), 48000);
+
+        let rms = (decoded[4800..43200].iter().map(|s| s * s).sum::<f32>() / 38400.0).sqrt();
+        assert!((rms - 0.5 / 2f32.sqrt()).abs() < 0.05, "rms {}", rms);
+    }
+
+    #[test]
+    fn test_streaming_and_repair() {
+        let samples: Vec<f32> = (0..32000)
+            .map(|i| (2.0 * std::f32::consts::PI * 300.0 * i as f32 / 16000.0).sin() * 0.5)
+            .collect();
+        let encoder = OpusEncoder::new();
+        let mut stream = OpusStream::new(Vec::new(), &encoder, 16000, 1).unwrap();
+        for chunk in samples.chunks(700) {
+            stream.write(chunk).unwrap();
+        }
+        let data = stream.finish().unwrap();
+        assert_eq!(data, encoder.encode(&samples, 16000, 1).unwrap());
+
+        // Drop the last page as if the process died before writing it
+        let pages = read_pages(&data);
+        let pre_skip = u16::from_le_bytes([pages[0].packets[0][10], pages[0].packets[0][11]]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cut.ogg");
+        std::fs::write(&path, &data[..data.len() - 10]).unwrap();
+
+        let frames = crate::audio::ogg::repair_ogg(&path).unwrap();
+        let repaired = read_pages(&std::fs::read(&path).unwrap());
+        let last = repaired.last().unwrap();
+        assert_eq!(repaired.len(), pages.len() - 1);
+        assert_eq!(last.flags, PAGE_LAST);
+        assert_eq!(frames, last.granule - pre_skip as u64);
+        assert_eq!(last.granule, (2 * PACKETS_PER_PAGE * FRAME_SAMPLES) as u64);
+    }
+}
@@ -0,0 +1,516 @@
+// Consumer side of the capture path: everything that happens to captured
+// audio after it leaves the real-time callback
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::sync::watch;
+use crate::audio::autostop::{StopMonitor, StopReason};
+use crate::audio::frames::FrameDispatcher;
+use crate::audio::metering::{InputLevel, LevelMeter};
+use crate::audio::preroll::PreRollBuffer;
+use crate::audio::recording::{Dropout, DropoutCause};
+use crate::audio::resampler::AudioResampler;
+use crate::audio::ring::{CaptureConsumer, Discontinuity};
+use crate::audio::spill::SpillWriter;
+use crate::audio::stream::Segment;
+use crate::utils::error::{Result, VoicePAError};
+
+/// How often the consumer thread drains the capture ring
+const DRAIN_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Window over which input levels are measured
+const METER_WINDOW: Duration = Duration::from_millis(50);
+
+/// Where converted audio ends up
+pub(crate) enum SampleSink {
+    Memory(Vec<f32>),
+    File(SpillWriter),
+    /// Standby: keep only the most recent audio until a real sink arrives
+    PreRoll(PreRollBuffer),
+}
+
+/// What a finished pipeline produced
+pub(crate) struct CaptureOutput {
+    pub samples: Vec<f32>,
+    pub file: Option<PathBuf>,
+    pub stop_reason: Option<StopReason>,
+    pub dropouts: Vec<Dropout>,
+}
+
+/// Converts native audio to the output format and hands it to the sink and
+/// frame subscribers
+pub(crate) struct CapturePipeline {
+    resampler: AudioResampler,
+    sink: SampleSink,
+    frames: Arc<Mutex<FrameDispatcher>>,
+    recorded_frames: Arc<AtomicU64>,
+    meter: LevelMeter,
+    levels: Arc<watch::Sender<InputLevel>>,
+    monitor: Option<StopMonitor>,
+    stop_reason: Option<StopReason>,
+    dropouts: Vec<Dropout>,
+}
+
+impl CapturePipeline {
+    pub fn new(
+        resampler: AudioResampler,
+        sink: SampleSink,
+        frames: Arc<Mutex<FrameDispatcher>>,
+        recorded_frames: Arc<AtomicU64>,
+        levels: Arc<watch::Sender<InputLevel>>,
+    ) -> Self {
+        frames
+            .lock()
+            .unwrap()
+            .start(resampler.output_rate(), resampler.output_channels());
+        recorded_frames.store(0, Ordering::Relaxed);
+        levels.send_replace(InputLevel::default());
+        let meter = LevelMeter::new(
+            resampler.input_rate(),
+            resampler.input_channels(),
+            METER_WINDOW,
+        );
+
+        Self {
+            resampler,
+            sink,
+            frames,
+            recorded_frames,
+            meter,
+            levels,
+            monitor: None,
+            stop_reason: None,
+            dropouts: Vec::new(),
+        }
+    }
+
+    /// Move from standby to recording: the buffered pre-roll is written to
+    /// `sink` first, as if it had just been captured
+    pub fn begin(&mut self, sink: SampleSink) -> Result<()> {
+        if let SampleSink::PreRoll(buffer) = std::mem::replace(&mut self.sink, sink) {
+            let (older, newer) = buffer.as_slices();
+            self.write(older)?;
+            self.write(newer)?;
+        }
+        Ok(())
+    }
+
+    /// Stop taking audio once `monitor` says so
+    pub fn with_stop_monitor(mut self, monitor: StopMonitor) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    /// Why the stop monitor ended the recording, if it did
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Measure the level of native audio, including audio that is discarded
+    /// while paused
+    pub fn meter(&mut self, native: &[f32]) {
+        if let Some(level) = self.meter.process(native) {
+            self.levels.send_replace(level);
+        }
+    }
+
+    /// Process samples in the native capture format
+    pub fn process(&mut self, native: &[f32]) -> Result<()> {
+        let output = self.resampler.process(native)?;
+        self.write(&output)
+    }
+
+    /// Process the batch just drained from `consumer`, cut at each break in
+    /// it so every dropout is placed where its break happened
+    pub fn process_drained(&mut self, native: &[f32], consumer: &mut CaptureConsumer) -> Result<()> {
+        let start = consumer.drained() - native.len() as u64;
+        let channels = self.resampler.input_channels().max(1) as usize;
+        let mut done = 0;
+        while let Some(discontinuity) = consumer.next_discontinuity() {
+            let at = discontinuity.at().saturating_sub(start) as usize;
+            let end = (at - at % channels).clamp(done, native.len());
+            self.process(&native[done..end])?;
+            self.note_discontinuity(discontinuity);
+            done = end;
+        }
+        self.process(&native[done..])
+    }
+
+    /// Record a break in the native audio processed so far as a dropout at
+    /// the current position; breaks in pre-roll or after a stop are ignored
+    pub fn note_discontinuity(&mut self, discontinuity: Discontinuity) {
+        if matches!(self.sink, SampleSink::PreRoll(_)) || self.stop_reason.is_some() {
+            return;
+        }
+
+        let (duration, cause) = match discontinuity {
+            Discontinuity::Skipped { missing, .. } => (missing, DropoutCause::Skipped),
+            Discontinuity::Overflow { samples, .. } => {
+                let frames = samples / self.resampler.input_channels().max(1) as u64;
+                let duration = frames as f64 / self.resampler.input_rate() as f64;
+                (Duration::from_secs_f64(duration), DropoutCause::Overflow)
+            }
+        };
+        // Audio still inside the resampler was captured before the break
+        let frames =
+            self.recorded_frames.load(Ordering::Relaxed) + self.resampler.buffered_frames();
+        let position = Duration::from_secs_f64(frames as f64 / self.resampler.output_rate() as f64);
+
+        // A sustained overflow reports every callback; keep it as one span
+        match self.dropouts.last_mut() {
+            Some(last) if last.position == position && last.cause == cause => {
+                last.duration += duration;
+            }
+            _ => self.dropouts.push(Dropout {
+                position,
+                duration,
+                cause,
+            }),
+        }
+    }
+
+    /// Switch to audio from a reopened stream, whose native format may differ
+    pub fn switch_resampler(&mut self, resampler: AudioResampler) -> Result<()> {
+        let tail = self.resampler.flush()?;
+        self.write(&tail)?;
+        self.meter
+            .set_format(resampler.input_rate(), resampler.input_channels());
+        self.resampler = resampler;
+        Ok(())
+    }
+
+    /// Flush the resampler, close subscriptions and finalize the sink
+    pub fn finish(mut self) -> Result<CaptureOutput> {
+        let tail = self.resampler.flush()?;
+        self.write(&tail)?;
+        self.frames.lock().unwrap().finish();
+        self.levels.send_replace(InputLevel::default());
+
+        // An automatic stop drops the silence after the last speech
+        let keep = match (&self.monitor, self.stop_reason) {
+            (Some(monitor), Some(_)) => {
+                let keep = monitor.keep_frames();
+                self.recorded_frames.store(keep, Ordering::Relaxed);
+                Some(keep)
+            }
+            _ => None,
+        };
+        let channels = self.resampler.output_channels() as usize;
+
+        let (samples, file) = match self.sink {
+            SampleSink::Memory(mut samples) => {
+                if let Some(keep) = keep {
+                    samples.truncate(keep as usize * channels);
+                }
+                (samples, None)
+            }
+            SampleSink::File(writer) => {
+                let path = match keep {
+                    Some(keep) => writer.finalize_at(keep)?,
+                    None => writer.finalize()?,
+                };
+                (Vec::new(), Some(path))
+            }
+            SampleSink::PreRoll(_) => (Vec::new(), None),
+        };
+        if let Some(keep) = keep {
+            let end = Duration::from_secs_f64(keep as f64 / self.resampler.output_rate() as f64);
+            self.dropouts.retain(|dropout| dropout.position <= end);
+        }
+        Ok(CaptureOutput {
+            samples,
+            file,
+            stop_reason: self.stop_reason,
+            dropouts: self.dropouts,
+        })
+    }
+
+    fn write(&mut self, output: &[f32]) -> Result<()> {
+        if output.is_empty() || self.stop_reason.is_some() {
+            return Ok(());
+        }
+        if let SampleSink::PreRoll(buffer) = &mut self.sink {
+            buffer.push(output);
+            return Ok(());
+        }
+
+        let output = match &mut self.monitor {
+            Some(monitor) => {
+                let (keep, reason) = monitor.admit(output);
+                self.stop_reason = reason;
+                &output[..keep]
+            }
+            None => output,
+        };
+
+        match &mut self.sink {
+            SampleSink::Memory(samples) => samples.extend_from_slice(output),
+            SampleSink::File(writer) => writer.write(output)?,
+            SampleSink::PreRoll(_) => unreachable!("pre-roll audio is buffered above"),
+        }
+        self.frames.lock().unwrap().push(output);
+
+        let frames = output.len() / self.resampler.output_channels() as usize;
+        self.recorded_frames.fetch_add(frames as u64, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Called once from the consumer thread when a stop policy fires
+pub(crate) type StopHandler = Box<dyn FnOnce(StopReason) + Send>;
+
+/// Handle to the thread draining the capture ring into a pipeline
+///
+/// When the stream is reopened the new ring arrives as a [`Segment`]; the
+/// thread moves on to it once the old ring is drained. Dropping the handle
+/// stops the thread like [`stop`](Self::stop) and discards the output.
+pub(crate) struct ConsumerThread {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Result<CaptureOutput>>>,
+}
+
+impl ConsumerThread {
+    pub fn spawn(
+        mut consumer: CaptureConsumer,
+        mut pipeline: CapturePipeline,
+        segments: Receiver<Segment>,
+        sinks: Receiver<SampleSink>,
+        paused: Arc<AtomicBool>,
+        mut on_stop: Option<StopHandler>,
+    ) -> Result<Self> {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+
+        let handle = thread::Builder::new()
+            .name("voice-pa-capture".to_string())
+            .spawn(move || {
+                let mut scratch = Vec::new();
+                let mut failure = None;
+                loop {
+                    let stopping = !thread_running.load(Ordering::Acquire);
+
+                    if let Ok(sink) = sinks.try_recv() {
+                        if let Err(e) = pipeline.begin(sink) {
+                            log::error!("Capture pipeline failed: {}", e);
+                            failure = Some(e);
+                        }
+                    }
+
+                    scratch.clear();
+                    if consumer.drain_into(&mut scratch) > 0 {
+                        pipeline.meter(&scratch);
+                        // Keep draining while paused or failed so the ring
+                        // never overflows
+                        let skip = paused.load(Ordering::Acquire) || failure.is_some();
+                        if !skip {
+                            if let Err(e) = pipeline.process_drained(&scratch, &mut consumer) {
+                                log::error!("Capture pipeline failed: {}", e);
+                                failure = Some(e);
+                            }
+                        }
+                        // Breaks in discarded audio are not dropouts
+                        while consumer.next_discontinuity().is_some() {}
+                        if let Some(reason) = pipeline.stop_reason() {
+                            if let Some(on_stop) = on_stop.take() {
+                                on_stop(reason);
+                            }
+                        }
+                    } else if consumer.is_abandoned() {
+                        match segments.try_recv() {
+                            Ok(segment) => {
+                                consumer = segment.consumer;
+                                if failure.is_none() {
+                                    if let Err(e) = pipeline.switch_resampler(segment.resampler) {
+                                        log::error!("Capture pipeline failed: {}", e);
+                                        failure = Some(e);
+                                    }
+                                }
+                            }
+                            Err(_) if stopping => break,
+                            Err(_) => thread::sleep(DRAIN_INTERVAL),
+                        }
+                    } else if stopping {
+                        break;
+                    } else {
+                        thread::sleep(DRAIN_INTERVAL);
+                    }
+                }
+
+                let output = pipeline.finish();
+                match failure {
+                    Some(e) => Err(e),
+                    None => output,
+                }
+            })?;
+
+        Ok(Self {
+            running,
+            handle: Some(handle),
+        })
+    }
+
+    /// Signal the thread and wait for it to drain the ring
+    pub fn stop(mut self) -> Result<CaptureOutput> {
+        self.shutdown().unwrap_or_else(|| {
+            Err(VoicePAError::AudioStream("Capture thread already stopped".to_string()))
+        })
+    }
+
+    fn shutdown(&mut self) -> Option<Result<CaptureOutput>> {
+        let handle = self.handle.take()?;
+        self.running.store(false, Ordering::Release);
+        Some(handle.join().unwrap_or_else(|_| {
+            Err(VoicePAError::AudioStream("Capture consumer thread panicked".to_string()))
+        }))
+    }
+}
+
+impl Drop for ConsumerThread {
+    fn drop(&mut self) {
+        if let Some(Err(e)) = self.shutdown() {
+            log::error!("Capture ended with an error: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::encoding::WavEncoder;
+    use crate::audio::resampler::ResampleQuality;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_file_sink_keeps_nothing_in_memory() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("meeting.wav");
+        let recorded = Arc::new(AtomicU64::new(0));
+
+        let mut pipeline = CapturePipeline::new(
+            AudioResampler::new(48000, 2, 16000, 1, ResampleQuality::Fast).unwrap(),
+            SampleSink::File(SpillWriter::create(&path, &WavEncoder::new(), 16000, 1).unwrap()),
+            Arc::new(Mutex::new(FrameDispatcher::new())),
+            Arc::clone(&recorded),
+            Arc::new(watch::channel(InputLevel::default()).0),
+        );
+        for _ in 0..100 {
+            pipeline.process(&[0.1; 960]).unwrap();
+        }
+        let output = pipeline.finish().unwrap();
+
+        assert!(output.samples.is_empty());
+        assert_eq!(output.file.as_deref(), Some(path.as_path()));
+        assert_eq!(recorded.load(Ordering::Relaxed), 16000);
+        assert_eq!(hound::WavReader::open(&path).unwrap().duration(), 16000);
+    }
+
+    #[test]
+    fn test_consumer_moves_to_reopened_stream() {
+        let recorded = Arc::new(AtomicU64::new(0));
+        let pipeline = CapturePipeline::new(
+            AudioResampler::new(48000, 2, 16000, 1, ResampleQuality::Fast).unwrap(),
+            SampleSink::Memory(Vec::new()),
+            Arc::new(Mutex::new(FrameDispatcher::new())),
+            Arc::clone(&recorded),
+            Arc::new(watch::channel(InputLevel::default()).0),
+        );
+        let (segments, receiver) = std::sync::mpsc::channel();
+        let (mut first, consumer) = crate::audio::ring::capture_ring(48000 * 2);
+        let thread = ConsumerThread::spawn(
+            consumer,
+            pipeline,
+            receiver,
+            std::sync::mpsc::channel().1,
+            Arc::new(AtomicBool::new(false)),
+            None,
+        )
+        .unwrap();
+
+        // Half a second from a 48 kHz stereo device, then half a second from
+        // a 16 kHz mono one after the first disappears
+        first.push(&[0.1; 48000]);
+        drop(first);
+        let (mut second, consumer) = crate::audio::ring::capture_ring(16000);
+        segments
+            .send(Segment {
+                consumer,
+                resampler: AudioResampler::new(16000, 1, 16000, 1, ResampleQuality::Fast).unwrap(),
+            })
+            .unwrap();
+        second.push(&[0.1; 8000]);
+        drop(second);
+
+        let output = thread.stop().unwrap();
+        assert_eq!(output.samples.len(), 16000);
+        assert_eq!(recorded.load(Ordering::Relaxed), 16000);
+    }
+
+    #[test]
+    fn test_ring_overflow_becomes_one_dropout() {
+        let mut pipeline = CapturePipeline::new(
+            AudioResampler::new(16000, 1, 16000, 1, ResampleQuality::Fast).unwrap(),
+            SampleSink::Memory(Vec::new()),
+            Arc::new(Mutex::new(FrameDispatcher::new())),
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(watch::channel(InputLevel::default()).0),
+        );
+        let (mut producer, mut consumer) = crate::audio::ring::capture_ring(8000);
+
+        // Half a second fits; the next two 50 ms callbacks are dropped
+        producer.push(&[0.1; 8000]);
+        producer.push(&[0.1; 800]);
+        producer.push(&[0.1; 800]);
+        let mut scratch = Vec::new();
+        consumer.drain_into(&mut scratch);
+        pipeline.process(&scratch).unwrap();
+        while let Some(discontinuity) = consumer.next_discontinuity() {
+            pipeline.note_discontinuity(discontinuity);
+        }
+
+        let output = pipeline.finish().unwrap();
+        assert_eq!(
+            output.dropouts,
+            vec![Dropout {
+                position: Duration::from_millis(500),
+                duration: Duration::from_millis(100),
+                cause: DropoutCause::Overflow,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_dropout_is_placed_within_drained_batch() {
+        let mut pipeline = CapturePipeline::new(
+            AudioResampler::new(48000, 2, 16000, 1, ResampleQuality::Balanced).unwrap(),
+            SampleSink::Memory(Vec::new()),
+            Arc::new(Mutex::new(FrameDispatcher::new())),
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(watch::channel(InputLevel::default()).0),
+        );
+        let (mut producer, mut consumer) = crate::audio::ring::capture_ring(48000 * 2 * 2);
+
+        // A skip 0.3 s into a second of audio that is drained all at once,
+        // as when the consumer falls behind
+        producer.push(&[0.1; 28800]);
+        producer.mark_skipped(Duration::from_millis(40));
+        producer.push(&[0.1; 67200]);
+        let mut scratch = Vec::new();
+        consumer.drain_into(&mut scratch);
+        pipeline.process_drained(&scratch, &mut consumer).unwrap();
+
+        let output = pipeline.finish().unwrap();
+        assert_eq!(output.samples.len(), 16000);
+        assert_eq!(
+            output.dropouts,
+            vec![Dropout {
+                position: Duration::from_millis(300),
+                duration: Duration::from_millis(40),
+                cause: DropoutCause::Skipped,
+            }]
+        );
+    }
+}
@@ -0,0 +1,88 @@
+/// Fixed-size circular buffer holding the most recent interleaved audio
+///
+/// Memory is allocated once up front; older audio is overwritten as new
+/// audio arrives.
+pub struct PreRollBuffer {
+    samples: Vec<f32>,
+    channels: usize,
+    start: usize,
+    len: usize,
+}
+
+impl PreRollBuffer {
+    pub fn new(frames: usize, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            samples: vec![0.0; frames * channels],
+            channels,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Append interleaved samples, dropping the oldest when full
+    pub fn push(&mut self, samples: &[f32]) {
+        let capacity = self.samples.len();
+        if capacity == 0 {
+            return;
+        }
+        let samples = &samples[samples.len().saturating_sub(capacity)..];
+
+        // Write in up to two pieces, wrapping at the end of the storage
+        let write = (self.start + self.len) % capacity;
+        let first = samples.len().min(capacity - write);
+        self.samples[write..write + first].copy_from_slice(&samples[..first]);
+        self.samples[..samples.len() - first].copy_from_slice(&samples[first..]);
+
+        let len = self.len + samples.len();
+        if len > capacity {
+            self.start = (write + samples.len()) % capacity;
+            self.len = capacity;
+        } else {
+            self.len = len;
+        }
+    }
+
+    /// Buffered audio, oldest first, as two slices
+    pub fn as_slices(&self) -> (&[f32], &[f32]) {
+        let capacity = self.samples.len();
+        let end = self.start + self.len;
+        if end <= capacity {
+            (&self.samples[self.start..end], &[])
+        } else {
+            (&self.samples[self.start..], &self.samples[..end - capacity])
+        }
+    }
+
+    /// Number of sample frames buffered
+    pub fn frames(&self) -> usize {
+        self.len / self.channels
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_most_recent_audio() {
+        let mut buffer = PreRollBuffer::new(4, 2);
+        buffer.push(&[1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+        assert_eq!(buffer.frames(), 3);
+
+        buffer.push(&[4.0, 4.0, 5.0, 5.0]);
+        let (first, second) = buffer.as_slices();
+        assert_eq!([first, second].concat(), vec![2.0, 2.0, 3.0, 3.0, 4.0, 4.0, 5.0, 5.0]);
+
+        // More than fits at once keeps only the tail
+        buffer.push(&[6.0, 6.0, 7.0, 7.0, 8.0, 8.0, 9.0, 9.0, 10.0, 10.0]);
+        let (first, second) = buffer.as_slices();
+        assert_eq!([first, second].concat(), vec![7.0, 7.0, 8.0, 8.0, 9.0, 9.0, 10.0, 10.0]);
+        assert_eq!(buffer.frames(), 4);
+    }
+}
@@ -0,0 +1,172 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use crate::audio::autostop::StopReason;
+
+/// A span during which the recording was paused
+#[derive(Debug, Clone, PartialEq)]
+pub struct PauseInterval {
+    /// Position in the recorded audio where the pause happened
+    pub position: Duration,
+    /// Wall-clock time the pause started
+    pub started_at: SystemTime,
+    pub duration: Duration,
+}
+
+/// A span during which the input stream was down and nothing was captured
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamGap {
+    /// Position in the recorded audio where the gap happened
+    pub position: Duration,
+    /// Wall-clock time the stream was lost
+    pub started_at: SystemTime,
+    pub duration: Duration,
+}
+
+/// Why audio went missing without the stream stopping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropoutCause {
+    /// The source's capture timestamps jumped, e.g. the device overran its
+    /// buffer and discarded audio
+    Skipped,
+    /// The capture ring was full because processing fell behind
+    Overflow,
+}
+
+/// A span of audio lost mid-stream, e.g. to an xrun
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dropout {
+    /// Position in the recorded audio where the audio is missing
+    pub position: Duration,
+    pub duration: Duration,
+    pub cause: DropoutCause,
+}
+
+/// Audio and session bookkeeping returned when a recording ends
+#[derive(Debug, Clone)]
+pub struct Recording {
+    /// Interleaved samples in the recorder's output format; empty when the
+    /// audio was written to `file` instead
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Number of sample frames recorded
+    pub frames: u64,
+    /// WAV file holding the audio when recording spilled to disk
+    pub file: Option<PathBuf>,
+    /// Wall-clock time the first sample was captured, as reported by the
+    /// source where it has capture timestamps
+    pub started_at: SystemTime,
+    pub pauses: Vec<PauseInterval>,
+    /// Spans lost to stream failures, e.g. a disconnected headset
+    pub gaps: Vec<StreamGap>,
+    /// Audio lost while the stream kept running
+    pub dropouts: Vec<Dropout>,
+    /// Set when a stop policy ended the recording
+    pub stop_reason: Option<StopReason>,
+}
+
+impl Recording {
+    /// Duration of the recorded audio, excluding pauses
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.sample_rate as f64)
+    }
+
+    /// Deinterleave the audio into one track per channel
+    pub fn split_channels(&self) -> Vec<Vec<f32>> {
+        let channels = self.channels.max(1) as usize;
+        (0..channels)
+            .map(|channel| {
+                self.samples
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .copied()
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Total time spent paused
+    pub fn paused_duration(&self) -> Duration {
+        self.pauses.iter().map(|p| p.duration).sum()
+    }
+
+    /// Total time the input stream was down
+    pub fn gap_duration(&self) -> Duration {
+        self.gaps.iter().map(|g| g.duration).sum()
+    }
+
+    /// Total audio lost to dropouts
+    pub fn dropout_duration(&self) -> Duration {
+        self.dropouts.iter().map(|d| d.duration).sum()
+    }
+
+    /// Map a position in the recorded audio, e.g. a transcript timestamp,
+    /// to the wall-clock time it was spoken
+    pub fn wall_clock_at(&self, position: Duration) -> SystemTime {
+        let paused: Duration = self
+            .pauses
+            .iter()
+            .filter(|p| p.position <= position)
+            .map(|p| p.duration)
+            .sum();
+        let lost: Duration = self
+            .gaps
+            .iter()
+            .filter(|g| g.position <= position)
+            .map(|g| g.duration)
+            .sum();
+        let dropped: Duration = self
+            .dropouts
+            .iter()
+            .filter(|d| d.position <= position)
+            .map(|d| d.duration)
+            .sum();
+        self.started_at + position + paused + lost + dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wall_clock_skips_pauses() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let recording = Recording {
+            samples: vec![0.0; 16000 * 20],
+            sample_rate: 16000,
+            channels: 1,
+            frames: 16000 * 20,
+            file: None,
+            started_at: start,
+            pauses: vec![PauseInterval {
+                position: Duration::from_secs(10),
+                started_at: start + Duration::from_secs(10),
+                duration: Duration::from_secs(300),
+            }],
+            gaps: vec![StreamGap {
+                position: Duration::from_secs(18),
+                started_at: start + Duration::from_secs(318),
+                duration: Duration::from_secs(4),
+            }],
+            dropouts: vec![Dropout {
+                position: Duration::from_secs(12),
+                duration: Duration::from_millis(500),
+                cause: DropoutCause::Skipped,
+            }],
+            stop_reason: None,
+        };
+
+        assert_eq!(recording.duration(), Duration::from_secs(20));
+        assert_eq!(recording.wall_clock_at(Duration::from_secs(5)), start + Duration::from_secs(5));
+        assert_eq!(
+            recording.wall_clock_at(Duration::from_secs(15)),
+            start + Duration::from_millis(315_500)
+        );
+        assert_eq!(
+            recording.wall_clock_at(Duration::from_secs(19)),
+            start + Duration::from_millis(323_500)
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use rubato::{
+    FastFixedIn, PolynomialDegree, SincFixedIn, SincInterpolationParameters,
+    SincInterpolationType, VecResampler, WindowFunction,
+};
+use crate::audio::channels::{ChannelMapper, ChannelMapping};
+use crate::utils::error::Result;
+
+/// Number of input frames handed to rubato per processing call
+const CHUNK_FRAMES: usize = 1024;
+
+/// Trade-off between resampling speed and fidelity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Cubic polynomial interpolation without anti-aliasing
+    Fast,
+    /// Short windowed-sinc filter, good enough for speech
+    #[default]
+    Balanced,
+    /// Long windowed-sinc filter for archival quality
+    High,
+}
+
+/// Streaming sample-rate and channel-layout converter
+///
+/// Input and output are interleaved. Channels are mapped and mixed first so
+/// that a stereo-to-mono conversion only resamples a single channel.
+pub struct AudioResampler {
+    input_rate: u32,
+    input_channels: u16,
+    output_rate: u32,
+    output_channels: u16,
+    mapper: Option<ChannelMapper>,
+    mapped: Vec<f32>,
+    resampler: Option<Box<dyn VecResampler<f32>>>,
+    pending: Vec<Vec<f32>>,
+    cursor: OutputCursor,
+    frames_in: u64,
+}
+
+/// Output bookkeeping, kept apart from the resampler so both can be borrowed
+struct OutputCursor {
+    delay_remaining: usize,
+    frames_out: u64,
+}
+
+impl AudioResampler {
+    pub fn new(
+        input_rate: u32,
+        input_channels: u16,
+        output_rate: u32,
+        output_channels: u16,
+        quality: ResampleQuality,
+    ) -> Result<Self> {
+        let resampler = if input_rate == output_rate {
+            None
+        } else {
+            Some(build_resampler(
+                output_rate as f64 / input_rate as f64,
+                output_channels as usize,
+                quality,
+            )?)
+        };
+        let delay_remaining = resampler.as_ref().map(|r| r.output_delay()).unwrap_or(0);
+
+        Ok(Self {
+            input_rate,
+            input_channels,
+            output_rate,
+            output_channels,
+            mapper: None,
+            mapped: Vec::new(),
+            resampler,
+            pending: vec![Vec::new(); output_channels as usize],
+            cursor: OutputCursor {
+                delay_remaining,
+                frames_out: 0,
+            },
+            frames_in: 0,
+        })
+    }
+
+    /// Convert a whole buffer in one go
+    pub fn convert(
+        samples: &[f32],
+        input_rate: u32,
+        input_channels: u16,
+        output_rate: u32,
+        output_channels: u16,
+        quality: ResampleQuality,
+    ) -> Result<Vec<f32>> {
+        let mut resampler =
+            Self::new(input_rate, input_channels, output_rate, output_channels, quality)?;
+        let mut output = resampler.process(samples)?;
+        output.extend(resampler.flush()?);
+        Ok(output)
+    }
+
+    /// Map input channels with `mapping` before mixing them to the output
+    /// layout; the default averages them
+    pub fn with_channel_mapping(mut self, mapping: ChannelMapping) -> Self {
+        self.mapper = match mapping {
+            ChannelMapping::Average => None,
+            mapping => Some(ChannelMapper::new(mapping, self.input_channels, self.input_rate)),
+        };
+        self
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    pub fn input_channels(&self) -> u16 {
+        self.input_channels
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn output_channels(&self) -> u16 {
+        self.output_channels
+    }
+
+    /// Output frames owed for input fed so far but not yet returned, e.g.
+    /// while a chunk fills up
+    pub fn buffered_frames(&self) -> u64 {
+        let owed = self.frames_in as f64 * self.output_rate as f64 / self.input_rate as f64;
+        (owed.round() as u64).saturating_sub(self.cursor.frames_out)
+    }
+
+    /// Feed interleaved input samples and return whatever output is ready
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>> {
+        let frames = samples.len() / self.input_channels as usize;
+        self.frames_in += frames as u64;
+        match self.mapper.as_mut() {
+            Some(mapper) => {
+                self.mapped.clear();
+                mapper.process(samples, &mut self.mapped);
+                let channels = mapper.output_channels();
+                mix_channels(&self.mapped, channels, self.output_channels, &mut self.pending);
+            }
+            None => mix_channels(samples, self.input_channels, self.output_channels, &mut self.pending),
+        }
+
+        let mut output = Vec::new();
+        match self.resampler.as_mut() {
+            None => self.pass_through(&mut output),
+            Some(resampler) => loop {
+                let needed = resampler.input_frames_next();
+                if self.pending[0].len() < needed {
+                    break;
+                }
+                let chunk: Vec<Vec<f32>> = self
+                    .pending
+                    .iter_mut()
+                    .map(|channel| channel.drain(..needed).collect())
+                    .collect();
+                let resampled = resampler.process(&chunk, None)?;
+                self.cursor.emit(resampled, &mut output, u64::MAX);
+            },
+        }
+
+        Ok(output)
+    }
+
+    /// Drain buffered input and the filter tail
+    ///
+    /// After flushing, the total output length matches the input duration
+    /// at the output rate.
+    pub fn flush(&mut self) -> Result<Vec<f32>> {
+        let mut output = Vec::new();
+        let expected = (self.frames_in as f64 * self.output_rate as f64 / self.input_rate as f64)
+            .round() as u64;
+
+        if let Some(mapper) = self.mapper.as_mut() {
+            self.mapped.clear();
+            mapper.flush(&mut self.mapped);
+            let channels = mapper.output_channels();
+            mix_channels(&self.mapped, channels, self.output_channels, &mut self.pending);
+        }
+
+        match self.resampler.as_mut() {
+            None => self.pass_through(&mut output),
+            Some(resampler) => while self.cursor.frames_out < expected {
+                let resampled = if self.pending[0].is_empty() {
+                    resampler.process_partial(None, None)?
+                } else {
+                    let take = self.pending[0].len().min(resampler.input_frames_next());
+                    let chunk: Vec<Vec<f32>> = self
+                        .pending
+                        .iter_mut()
+                        .map(|channel| channel.drain(..take).collect())
+                        .collect();
+                    resampler.process_partial(Some(&chunk), None)?
+                };
+                self.cursor.emit(resampled, &mut output, expected);
+            },
+        }
+
+        Ok(output)
+    }
+
+    /// Hand mixed input straight to `output` when the rates match
+    fn pass_through(&mut self, output: &mut Vec<f32>) {
+        self.cursor.frames_out += self.pending[0].len() as u64;
+        interleave(&self.pending, output);
+        self.pending.iter_mut().for_each(Vec::clear);
+    }
+}
+
+impl OutputCursor {
+    /// Append resampled channels to `output`, skipping the filter delay and
+    /// never emitting more than `limit` frames in total
+    fn emit(&mut self, resampled: Vec<Vec<f32>>, output: &mut Vec<f32>, limit: u64) {
+        let available = resampled[0].len();
+        let skip = self.delay_remaining.min(available);
+        self.delay_remaining -= skip;
+
+        let remaining = limit.saturating_sub(self.frames_out) as usize;
+        let frames = (available - skip).min(remaining);
+        self.frames_out += frames as u64;
+
+        let channels: Vec<Vec<f32>> = resampled
+            .into_iter()
+            .map(|channel| channel[skip..skip + frames].to_vec())
+            .collect();
+        interleave(&channels, output);
+    }
+}
+
+fn build_resampler(
+    ratio: f64,
+    channels: usize,
+    quality: ResampleQuality,
+) -> Result<Box<dyn VecResampler<f32>>> {
+    let resampler: Box<dyn VecResampler<f32>> = match quality {
+        ResampleQuality::Fast => Box::new(FastFixedIn::<f32>::new(
+            ratio,
+            1.0,
+            PolynomialDegree::Cubic,
+            CHUNK_FRAMES,
+            channels,
+        )?),
+        ResampleQuality::Balanced => Box::new(SincFixedIn::<f32>::new(
+            ratio,
+            1.0,
+            SincInterpolationParameters {
+                sinc_len: 64,
+                f_cutoff: 0.915,
+                oversampling_factor: 128,
+                interpolation: SincInterpolationType::Linear,
+                window: WindowFunction::Blackman2,
+            },
+            CHUNK_FRAMES,
+            channels,
+        )?),
+        ResampleQuality::High => Box::new(SincFixedIn::<f32>::new(
+            ratio,
+            1.0,
+            SincInterpolationParameters {
+                sinc_len: 256,
+                f_cutoff: 0.95,
+                oversampling_factor: 256,
+                interpolation: SincInterpolationType::Cubic,
+                window: WindowFunction::BlackmanHarris2,
+            },
+            CHUNK_FRAMES,
+            channels,
+        )?),
+    };
+    Ok(resampler)
+}
+
+/// Deinterleave `samples` into `out`, mapping input channels onto the
+/// output layout: input channel `c` contributes to output `c % out_channels`
+/// (averaged), and missing outputs repeat input `o % in_channels`.
+fn mix_channels(samples: &[f32], in_channels: u16, out_channels: u16, out: &mut [Vec<f32>]) {
+    let in_channels = in_channels as usize;
+    let out_channels = out_channels as usize;
+
+    for frame in samples.chunks_exact(in_channels) {
+        for (o, channel) in out.iter_mut().enumerate().take(out_channels) {
+            let sample = if in_channels > out_channels {
+                let sources = (o..in_channels).step_by(out_channels);
+                let count = sources.clone().count() as f32;
+                sources.map(|c| frame[c]).sum::<f32>() / count
+            } else {
+                frame[o % in_channels]
+            };
+            channel.push(sample);
+        }
+    }
+}
+
+fn interleave(channels: &[Vec<f32>], out: &mut Vec<f32>) {
+    let frames = channels.first().map(|c| c.len()).unwrap_or(0);
+    out.reserve(frames * channels.len());
+    for i in 0..frames {
+        for channel in channels {
+            out.push(channel[i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, freq: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / rate as f32).sin() * 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn test_stereo_downmix_averages_channels() {
+        let stereo = vec![1.0, 0.0, 0.5, 0.5, -1.0, 1.0];
+        let mono = AudioResampler::convert(&stereo, 16000, 2, 16000, 1, ResampleQuality::Fast)
+            .unwrap();
+        assert_eq!(mono, vec![0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_mapping_applies_before_resampling() {
+        // A tone on the right channel only, picked and converted to 16 kHz
+        let stereo: Vec<f32> = sine(48000, 440.0, 4800)
+            .into_iter()
+            .flat_map(|s| [0.0, s])
+            .collect();
+        let mut resampler = AudioResampler::new(48000, 2, 16000, 1, ResampleQuality::Fast)
+            .unwrap()
+            .with_channel_mapping(ChannelMapping::Pick(1));
+        let mut output = resampler.process(&stereo).unwrap();
+        output.extend(resampler.flush().unwrap());
+
+        assert_eq!(output.len(), 1600);
+        let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 0.5).abs() < 0.02, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_resample_length_matches_duration() {
+        for quality in [ResampleQuality::Fast, ResampleQuality::Balanced, ResampleQuality::High] {
+            let input = sine(48000, 440.0, 48000);
+            let output =
+                AudioResampler::convert(&input, 48000, 1, 16000, 1, quality).unwrap();
+            assert_eq!(output.len(), 16000);
+
+            // The tone should survive with roughly the same level
+            let rms = (output[1000..15000].iter().map(|s| s * s).sum::<f32>() / 14000.0).sqrt();
+            assert!((rms - 0.5 / 2f32.sqrt()).abs() < 0.02, "rms {} for {:?}", rms, quality);
+        }
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot() {
+        let input = sine(44100, 300.0, 10000);
+        let one_shot =
+            AudioResampler::convert(&input, 44100, 1, 16000, 1, ResampleQuality::Balanced).unwrap();
+
+        let mut streaming =
+            AudioResampler::new(44100, 1, 16000, 1, ResampleQuality::Balanced).unwrap();
+        let mut output = Vec::new();
+        for chunk in input.chunks(333) {
+            output.extend(streaming.process(chunk).unwrap());
+        }
+        output.extend(streaming.flush().unwrap());
+
+        assert_eq!(output.len(), one_shot.len());
+        for (a, b) in output.iter().zip(&one_shot) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use rtrb::RingBuffer;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Breaks in the audio a ring can hold before more are discarded
+const DISCONTINUITY_CAPACITY: usize = 64;
+
+/// Create a preallocated single-producer/single-consumer sample ring
+///
+/// The producer side is safe to use on a real-time audio thread: pushing
+/// never locks or allocates, and samples that do not fit are counted as
+/// dropped instead of blocking.
+pub fn capture_ring(capacity: usize) -> (CaptureProducer, CaptureConsumer) {
+    shared_capture_ring(capacity, Arc::new(AtomicU64::new(0)))
+}
+
+/// Create a capture ring that adds to an existing dropped-sample counter, so
+/// the count survives the stream being reopened
+pub(crate) fn shared_capture_ring(
+    capacity: usize,
+    dropped: Arc<AtomicU64>,
+) -> (CaptureProducer, CaptureConsumer) {
+    let (producer, consumer) = RingBuffer::new(capacity);
+    let (marks, mark_consumer) = RingBuffer::new(DISCONTINUITY_CAPACITY);
+
+    (
+        CaptureProducer {
+            producer,
+            marks,
+            written: 0,
+            dropped: Arc::clone(&dropped),
+        },
+        CaptureConsumer {
+            consumer,
+            marks: mark_consumer,
+            drained: 0,
+            dropped,
+        },
+    )
+}
+
+/// A break in the audio that went through a capture ring
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Discontinuity {
+    /// The source skipped `missing` of audio before sample index `at`, e.g.
+    /// because the device overran its buffer
+    Skipped { at: u64, missing: Duration },
+    /// `samples` were dropped at sample index `at` because the ring was full
+    Overflow { at: u64, samples: u64 },
+}
+
+impl Discontinuity {
+    /// Number of samples that went through the ring before the break
+    pub fn at(&self) -> u64 {
+        match *self {
+            Discontinuity::Skipped { at, .. } | Discontinuity::Overflow { at, .. } => at,
+        }
+    }
+}
+
+/// Real-time side of the capture ring
+pub struct CaptureProducer {
+    producer: rtrb::Producer<f32>,
+    marks: rtrb::Producer<Discontinuity>,
+    written: u64,
+    dropped: Arc<AtomicU64>,
+}
+
+impl CaptureProducer {
+    /// Push as many samples as fit; returns how many were written
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        self.push_iter(samples.len(), samples.iter().copied())
+    }
+
+    /// Push `len` samples produced by `samples`; returns how many were written
+    pub fn push_iter(&mut self, len: usize, samples: impl Iterator<Item = f32>) -> usize {
+        let writable = len.min(self.producer.slots());
+        let written = match self.producer.write_chunk_uninit(writable) {
+            Ok(chunk) => chunk.fill_from_iter(samples),
+            Err(_) => 0,
+        };
+
+        self.written += written as u64;
+        if written < len {
+            let samples = (len - written) as u64;
+            self.dropped.fetch_add(samples, Ordering::Relaxed);
+            self.mark(Discontinuity::Overflow {
+                at: self.written,
+                samples,
+            });
+        }
+        written
+    }
+
+    /// Record that the source skipped `missing` of audio before the next
+    /// sample it pushes
+    pub fn mark_skipped(&mut self, missing: Duration) {
+        self.mark(Discontinuity::Skipped {
+            at: self.written,
+            missing,
+        });
+    }
+
+    fn mark(&mut self, discontinuity: Discontinuity) {
+        // Losing a mark when the consumer is far behind only loses the report
+        let _ = self.marks.push(discontinuity);
+    }
+
+    /// Number of samples that can be pushed without dropping any
+    pub fn slots(&self) -> usize {
+        self.producer.slots()
+    }
+
+    /// Samples discarded because the consumer fell behind
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Draining side of the capture ring
+pub struct CaptureConsumer {
+    consumer: rtrb::Consumer<f32>,
+    marks: rtrb::Consumer<Discontinuity>,
+    drained: u64,
+    dropped: Arc<AtomicU64>,
+}
+
+impl CaptureConsumer {
+    /// Move every queued sample into `out`; returns how many were moved
+    pub fn drain_into(&mut self, out: &mut Vec<f32>) -> usize {
+        let available = self.consumer.slots();
+        let Ok(chunk) = self.consumer.read_chunk(available) else {
+            return 0;
+        };
+
+        let (first, second) = chunk.as_slices();
+        out.extend_from_slice(first);
+        out.extend_from_slice(second);
+        chunk.commit_all();
+        self.drained += available as u64;
+        available
+    }
+
+    /// Number of samples drained since the ring was created
+    pub fn drained(&self) -> u64 {
+        self.drained
+    }
+
+    /// Next break in the audio drained so far, if any
+    pub fn next_discontinuity(&mut self) -> Option<Discontinuity> {
+        match self.marks.peek() {
+            Ok(mark) if mark.at() <= self.drained => self.marks.pop().ok(),
+            _ => None,
+        }
+    }
+
+    /// Whether the producer has been dropped, i.e. the stream is gone
+    pub fn is_abandoned(&self) -> bool {
+        self.consumer.is_abandoned()
+    }
+
+    /// Samples discarded because the consumer fell behind
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overflow_counts_dropped_samples() {
+        let (mut producer, mut consumer) = capture_ring(8);
+
+        assert_eq!(producer.push(&[0.1; 5]), 5);
+        assert_eq!(producer.push(&[0.2; 5]), 3);
+        assert_eq!(consumer.dropped_samples(), 2);
+
+        // The break is reported once the audio before it has been drained
+        assert_eq!(consumer.next_discontinuity(), None);
+        let mut out = Vec::new();
+        assert_eq!(consumer.drain_into(&mut out), 8);
+        assert_eq!(&out[..5], &[0.1; 5]);
+        assert_eq!(
+            consumer.next_discontinuity(),
+            Some(Discontinuity::Overflow { at: 8, samples: 2 })
+        );
+
+        // Space is reclaimed once drained, including across the wrap point
+        assert_eq!(producer.push(&[0.3; 6]), 6);
+        out.clear();
+        consumer.drain_into(&mut out);
+        assert_eq!(out, vec![0.3; 6]);
+    }
+}
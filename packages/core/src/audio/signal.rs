@@ -0,0 +1,292 @@
+use cpal::SampleFormat;
+use std::f64::consts::TAU;
+use std::time::Duration;
+use crate::audio::source::{spawn_playback, AudioSource, Pacing, PlaybackHandle, SourceFormat, SourceOutput};
+use crate::utils::error::Result;
+
+/// Rate at which synthetic speech rises and falls, roughly syllables per second
+const SYLLABLE_RATE: f64 = 4.0;
+
+/// Harmonics of the pitch mixed into synthetic speech
+const HARMONICS: usize = 5;
+
+/// A generated waveform
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signal {
+    Silence,
+    Sine { frequency: f32, amplitude: f32 },
+    /// Uniform white noise from a seeded generator, so runs are repeatable
+    WhiteNoise { amplitude: f32, seed: u64 },
+    /// Speakers taking turns: each talks for `turn`, then everyone is silent
+    /// for `pause` before the next speaker, cycling through `speakers`
+    Conversation {
+        speakers: Vec<SyntheticSpeaker>,
+        turn: Duration,
+        pause: Duration,
+    },
+}
+
+/// A voice in a synthetic conversation: a harmonic tone at `pitch` with
+/// speech-like amplitude modulation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyntheticSpeaker {
+    pub pitch: f32,
+    pub amplitude: f32,
+}
+
+/// Generates a [`Signal`] as if it were captured from a device
+#[derive(Debug, Clone)]
+pub struct SignalSource {
+    signal: Signal,
+    sample_rate: u32,
+    channels: u16,
+    length: Option<Duration>,
+    pacing: Pacing,
+}
+
+impl SignalSource {
+    /// An endless signal delivered in real time
+    pub fn new(signal: Signal, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            signal,
+            sample_rate,
+            channels,
+            length: None,
+            pacing: Pacing::RealTime,
+        }
+    }
+
+    /// End the signal after `length`
+    pub fn with_length(mut self, length: Duration) -> Self {
+        self.length = Some(length);
+        self
+    }
+
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Generate `frames` interleaved sample frames from the start of the
+    /// signal without playing it
+    pub fn render(&self, frames: usize) -> Vec<f32> {
+        let mut generator = Generator::new(self);
+        let mut samples = vec![0.0; frames * self.channels as usize];
+        let written = generator.fill(&mut samples);
+        samples.truncate(written);
+        samples
+    }
+
+    /// Which speaker of a conversation is talking at `position`
+    pub fn speaker_at(&self, position: Duration) -> Option<usize> {
+        match &self.signal {
+            Signal::Conversation {
+                speakers,
+                turn,
+                pause,
+            } if !speakers.is_empty() => {
+                let cycle = (*turn + *pause).as_secs_f64();
+                let index = (position.as_secs_f64() / cycle) as usize;
+                let within = position.as_secs_f64() - index as f64 * cycle;
+                (within < turn.as_secs_f64()).then_some(index % speakers.len())
+            }
+            _ => None,
+        }
+    }
+
+    fn total_frames(&self) -> Option<u64> {
+        self.length
+            .map(|length| (length.as_secs_f64() * self.sample_rate as f64).round() as u64)
+    }
+}
+
+impl AudioSource for SignalSource {
+    type Stream = PlaybackHandle;
+
+    fn name(&self) -> String {
+        match &self.signal {
+            Signal::Silence => "Silence".to_string(),
+            Signal::Sine { frequency, .. } => format!("Sine {} Hz", frequency),
+            Signal::WhiteNoise { .. } => "White noise".to_string(),
+            Signal::Conversation { speakers, .. } => {
+                format!("Synthetic conversation ({} speakers)", speakers.len())
+            }
+        }
+    }
+
+    fn format(&mut self) -> Result<SourceFormat> {
+        Ok(SourceFormat {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            sample_format: SampleFormat::F32,
+        })
+    }
+
+    fn start(&mut self, output: SourceOutput) -> Result<PlaybackHandle> {
+        let mut generator = Generator::new(self);
+        let format = self.format()?;
+        spawn_playback("voice-pa-signal", output, format, self.pacing, move |buffer| {
+            generator.fill(buffer)
+        })
+    }
+}
+
+struct Generator {
+    signal: Signal,
+    sample_rate: f64,
+    channels: usize,
+    position: u64,
+    end: Option<u64>,
+    rng: u64,
+    /// Phase of the tone or of the speaker's pitch, in cycles wrapped to
+    /// 0..1, so endless signals stay exact however long they run
+    phase: f64,
+}
+
+impl Generator {
+    fn new(source: &SignalSource) -> Self {
+        let seed = match source.signal {
+            Signal::WhiteNoise { seed, .. } => seed,
+            _ => 0,
+        };
+        Self {
+            signal: source.signal.clone(),
+            sample_rate: source.sample_rate.max(1) as f64,
+            channels: source.channels.max(1) as usize,
+            position: 0,
+            end: source.total_frames(),
+            // xorshift must not start at zero
+            rng: seed ^ 0x9E37_79B9_7F4A_7C15,
+            phase: 0.0,
+        }
+    }
+
+    /// Fill whole frames of `buffer`; returns the number of samples written
+    fn fill(&mut self, buffer: &mut [f32]) -> usize {
+        let mut frames = (buffer.len() / self.channels) as u64;
+        if let Some(end) = self.end {
+            frames = frames.min(end.saturating_sub(self.position));
+        }
+
+        for frame in buffer.chunks_exact_mut(self.channels).take(frames as usize) {
+            let value = self.next_value();
+            frame.fill(value);
+        }
+        (frames as usize) * self.channels
+    }
+
+    /// Advance the phase by one sample of `frequency`; returns the phase
+    /// before the step
+    fn step_phase(&mut self, frequency: f32) -> f64 {
+        let phase = self.phase;
+        self.phase = (phase + frequency as f64 / self.sample_rate).rem_euclid(1.0);
+        phase
+    }
+
+    fn next_value(&mut self) -> f32 {
+        let t = self.position as f64 / self.sample_rate;
+        self.position += 1;
+
+        match self.signal {
+            Signal::Silence => 0.0,
+            Signal::Sine {
+                frequency,
+                amplitude,
+            } => amplitude * (TAU * self.step_phase(frequency)).sin() as f32,
+            Signal::WhiteNoise { amplitude, .. } => {
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                let unit = (self.rng >> 40) as f32 / (1u64 << 24) as f32;
+                amplitude * (unit * 2.0 - 1.0)
+            }
+            Signal::Conversation {
+                ref speakers,
+                turn,
+                pause,
+            } => {
+                if speakers.is_empty() {
+                    return 0.0;
+                }
+                let cycle = (turn + pause).as_secs_f64();
+                let index = (t / cycle) as usize;
+                let within = t - index as f64 * cycle;
+                if within >= turn.as_secs_f64() {
+                    return 0.0;
+                }
+
+                let speaker = speakers[index % speakers.len()];
+                let envelope = 0.5 * (1.0 - (TAU * SYLLABLE_RATE * within).cos());
+                let phase = self.step_phase(speaker.pitch);
+                let voiced: f64 = (1..=HARMONICS)
+                    .map(|h| (TAU * h as f64 * phase).sin() / h as f64)
+                    .sum();
+                // The harmonic series peaks a little above 1.5
+                speaker.amplitude * (envelope * voiced / 1.6) as f32
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversation_alternates_speakers() {
+        let source = SignalSource::new(
+            Signal::Conversation {
+                speakers: vec![
+                    SyntheticSpeaker { pitch: 110.0, amplitude: 0.5 },
+                    SyntheticSpeaker { pitch: 220.0, amplitude: 0.5 },
+                ],
+                turn: Duration::from_secs(1),
+                pause: Duration::from_millis(500),
+            },
+            16000,
+            1,
+        )
+        .with_length(Duration::from_secs(3));
+
+        let samples = source.render(16000 * 10);
+        assert_eq!(samples.len(), 16000 * 3);
+        assert!(samples.iter().all(|s| s.abs() <= 0.5));
+
+        let energy = |from: f32, to: f32| {
+            let range = &samples[(from * 16000.0) as usize..(to * 16000.0) as usize];
+            range.iter().map(|s| s * s).sum::<f32>() / range.len() as f32
+        };
+        assert!(energy(0.0, 1.0) > 0.01);
+        assert_eq!(energy(1.0, 1.5), 0.0);
+        assert!(energy(1.5, 2.5) > 0.01);
+
+        assert_eq!(source.speaker_at(Duration::from_millis(200)), Some(0));
+        assert_eq!(source.speaker_at(Duration::from_millis(1200)), None);
+        assert_eq!(source.speaker_at(Duration::from_millis(1700)), Some(1));
+
+        // Rendering is deterministic
+        assert_eq!(samples, source.render(16000 * 3));
+    }
+
+    #[test]
+    fn test_tone_stays_exact_in_long_runs() {
+        let rate = 8000;
+        let source = SignalSource::new(
+            Signal::Sine {
+                frequency: 440.0,
+                amplitude: 0.5,
+            },
+            rate,
+            1,
+        );
+
+        // Ten minutes in, well past where an f32 clock loses whole samples
+        let frames = 600 * rate as usize;
+        let samples = source.render(frames);
+        for (n, &sample) in samples.iter().enumerate().skip(frames - 1000) {
+            let cycles = (440 * n as u64 % rate as u64) as f64 / rate as f64;
+            let expected = 0.5 * (TAU * cycles).sin() as f32;
+            assert!((sample - expected).abs() < 1e-4, "{}: {} vs {}", n, sample, expected);
+        }
+    }
+}
@@ -0,0 +1,531 @@
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{
+    Device, FromSample, Sample, SampleFormat, SizedSample, Stream, StreamConfig, StreamError,
+    SupportedStreamConfig,
+};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::audio::devices::{self, DeviceSelector};
+use crate::audio::ring::CaptureProducer;
+use crate::utils::error::{Result, VoicePAError};
+
+/// Somewhere audio can be captured from: an input device, a file, a
+/// generator
+///
+/// A source is a description that can be cloned freely; [`start`] turns it
+/// into a running stream that pushes native samples into a
+/// [`SourceOutput`] until the returned handle is dropped. The handle stays on
+/// the thread that started it, so it need not be `Send`.
+///
+/// [`start`]: AudioSource::start
+pub trait AudioSource: Clone + Send + 'static {
+    /// Keeps audio flowing while alive
+    type Stream;
+
+    /// Human-readable name, e.g. the device name
+    fn name(&self) -> String;
+
+    /// Native format the source will deliver in
+    fn format(&mut self) -> Result<SourceFormat>;
+
+    /// Start delivering audio in `format()`
+    fn start(&mut self, output: SourceOutput) -> Result<Self::Stream>;
+
+    /// Source to resume on after this one was lost
+    fn reopen(&self) -> Result<Self> {
+        Err(VoicePAError::AudioStream(format!("{} cannot be reopened", self.name())))
+    }
+}
+
+/// Native format of a source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Format of the underlying samples before conversion to f32
+    pub sample_format: SampleFormat,
+}
+
+/// How fast file and generated sources deliver audio
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Pacing {
+    /// As fast as a device would
+    #[default]
+    RealTime,
+    /// A multiple of real time
+    Speed(f64),
+    /// As fast as the recorder can consume it
+    Unthrottled,
+}
+
+/// Messages from a running source to the thread supervising it
+pub(crate) enum Notice {
+    Error(String),
+    Lost(String),
+    Ended,
+    Release,
+    Stop,
+}
+
+/// Capture timestamps further apart than expected by less than this are
+/// treated as jitter
+const MIN_SKIP: Duration = Duration::from_millis(2);
+
+/// When a session's first sample was captured and how late the source is
+/// delivering audio, shared by every stream of the session
+pub(crate) struct CaptureClock {
+    /// Microseconds since the Unix epoch; zero until the first sample
+    anchor_micros: AtomicU64,
+    /// Microseconds; `u64::MAX` while unknown
+    latency_micros: AtomicU64,
+}
+
+impl CaptureClock {
+    pub fn new() -> Self {
+        Self {
+            anchor_micros: AtomicU64::new(0),
+            latency_micros: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// Wall-clock time the first sample was captured
+    pub fn anchor(&self) -> Option<SystemTime> {
+        match self.anchor_micros.load(Ordering::Acquire) {
+            0 => None,
+            micros => Some(UNIX_EPOCH + Duration::from_micros(micros)),
+        }
+    }
+
+    /// Delay between capture and delivery in the most recent callback, for
+    /// sources that report timestamps
+    pub fn latency(&self) -> Option<Duration> {
+        match self.latency_micros.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    fn set_anchor(&self, at: SystemTime) {
+        let micros = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        let _ = self.anchor_micros.compare_exchange(
+            0,
+            micros.max(1),
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        );
+    }
+
+    fn set_latency(&self, latency: Duration) {
+        self.latency_micros
+            .store(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Where a running source delivers interleaved f32 samples
+pub struct SourceOutput {
+    producer: CaptureProducer,
+    heartbeat: Arc<AtomicU64>,
+    clock: Arc<CaptureClock>,
+    anchored: bool,
+    sample_rate: f64,
+    channels: u64,
+    /// Capture time of the last timestamped samples
+    last_capture: Option<Duration>,
+    /// Samples pushed since then
+    samples_since: u64,
+    notifier: SourceNotifier,
+}
+
+impl SourceOutput {
+    pub(crate) fn new(
+        producer: CaptureProducer,
+        format: &SourceFormat,
+        heartbeat: Arc<AtomicU64>,
+        clock: Arc<CaptureClock>,
+        notices: Weak<Sender<Notice>>,
+    ) -> Self {
+        Self {
+            producer,
+            heartbeat,
+            clock,
+            anchored: false,
+            sample_rate: format.sample_rate.max(1) as f64,
+            channels: format.channels.max(1) as u64,
+            last_capture: None,
+            samples_since: 0,
+            notifier: SourceNotifier { notices },
+        }
+    }
+
+    /// Report when the samples about to be pushed were captured and when
+    /// the callback delivering them started, both on the source's own clock
+    ///
+    /// This anchors the session to wall-clock time and lets the recorder
+    /// notice audio the source skipped. Sources without timestamps need not
+    /// call it.
+    pub fn timestamp(&mut self, captured: Duration, delivered: Duration) {
+        let latency = delivered.saturating_sub(captured);
+        self.clock.set_latency(latency);
+        if !self.anchored {
+            self.clock.set_anchor(SystemTime::now() - latency);
+            self.anchored = true;
+        }
+
+        if let Some(last) = self.last_capture {
+            let span = Duration::from_secs_f64(
+                (self.samples_since / self.channels) as f64 / self.sample_rate,
+            );
+            let expected = last + span;
+            if captured > expected + MIN_SKIP.max(span / 2) {
+                self.producer.mark_skipped(captured - expected);
+            }
+        }
+        self.last_capture = Some(captured);
+        self.samples_since = 0;
+    }
+
+    /// Push as many samples as fit; returns how many were written
+    ///
+    /// Never blocks or allocates, so it is safe on a real-time thread.
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        self.push_iter(samples.len(), samples.iter().copied())
+    }
+
+    /// Push `len` samples produced by `samples`; returns how many were written
+    pub fn push_iter(&mut self, len: usize, samples: impl Iterator<Item = f32>) -> usize {
+        if !self.anchored {
+            self.clock.set_anchor(SystemTime::now());
+            self.anchored = true;
+        }
+        self.heartbeat.fetch_add(1, Ordering::Relaxed);
+        self.samples_since += len as u64;
+        self.producer.push_iter(len, samples)
+    }
+
+    /// Number of samples that can be pushed without dropping any
+    pub fn free_space(&self) -> usize {
+        self.producer.slots()
+    }
+
+    /// Handle for reporting errors from another callback or thread
+    pub fn notifier(&self) -> SourceNotifier {
+        self.notifier.clone()
+    }
+}
+
+/// Reports problems and the end of a source to the recorder
+///
+/// Notices sent after the recorder stopped are dropped.
+#[derive(Clone)]
+pub struct SourceNotifier {
+    notices: Weak<Sender<Notice>>,
+}
+
+impl SourceNotifier {
+    /// Report an error the source recovers from by itself
+    pub fn error(&self, message: impl Into<String>) {
+        self.send(Notice::Error(message.into()));
+    }
+
+    /// Report that the source stopped delivering audio and will not resume
+    pub fn lost(&self, reason: impl Into<String>) {
+        self.send(Notice::Lost(reason.into()));
+    }
+
+    /// Report that a finite source delivered all of its audio
+    pub fn ended(&self) {
+        self.send(Notice::Ended);
+    }
+
+    fn send(&self, notice: Notice) {
+        if let Some(notices) = self.notices.upgrade() {
+            let _ = notices.send(notice);
+        }
+    }
+}
+
+/// Captures from a physical input device through cpal
+#[derive(Clone)]
+pub struct CpalSource {
+    device: Device,
+    /// How the device was chosen, so a lost device is replaced the same way
+    selector: DeviceSelector,
+    format: Option<SupportedStreamConfig>,
+}
+
+impl CpalSource {
+    pub fn new(selector: &DeviceSelector) -> Result<Self> {
+        Ok(Self {
+            device: devices::resolve_device(selector)?,
+            selector: selector.clone(),
+            format: None,
+        })
+    }
+
+    /// Capture from `device`; once lost, recording resumes on the default
+    /// input device
+    pub fn from_device(device: Device) -> Self {
+        Self {
+            device,
+            selector: DeviceSelector::Default,
+            format: None,
+        }
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Query the device's default input config and remember it for `start`
+    fn query_config(&mut self) -> Result<SupportedStreamConfig> {
+        let format = self.device.default_input_config().map_err(|e| {
+            VoicePAError::AudioDevice(format!("Failed to get default input config: {}", e))
+        })?;
+        log::info!("Device default config: {:?}", format);
+        self.format = Some(format.clone());
+        Ok(format)
+    }
+}
+
+impl AudioSource for CpalSource {
+    type Stream = Stream;
+
+    fn name(&self) -> String {
+        self.device.name().unwrap_or_default()
+    }
+
+    fn format(&mut self) -> Result<SourceFormat> {
+        let format = self.query_config()?;
+        Ok(SourceFormat {
+            sample_rate: format.sample_rate().0,
+            channels: format.channels(),
+            sample_format: format.sample_format(),
+        })
+    }
+
+    fn start(&mut self, output: SourceOutput) -> Result<Stream> {
+        let format = match self.format.clone() {
+            Some(format) => format,
+            None => self.query_config()?,
+        };
+        let config = format.config();
+        let device = &self.device;
+
+        let stream = match format.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(device, &config, output)?,
+            SampleFormat::F64 => build_stream::<f64>(device, &config, output)?,
+            SampleFormat::I8 => build_stream::<i8>(device, &config, output)?,
+            SampleFormat::I16 => build_stream::<i16>(device, &config, output)?,
+            SampleFormat::I32 => build_stream::<i32>(device, &config, output)?,
+            SampleFormat::U8 => build_stream::<u8>(device, &config, output)?,
+            SampleFormat::U16 => build_stream::<u16>(device, &config, output)?,
+            SampleFormat::U32 => build_stream::<u32>(device, &config, output)?,
+            other => {
+                return Err(VoicePAError::AudioDevice(format!(
+                    "Unsupported sample format: {}",
+                    other
+                )))
+            }
+        };
+        stream.play()?;
+        Ok(stream)
+    }
+
+    /// The device selected the same way as this one, falling back to the
+    /// current default input when it is gone; a lost system audio monitor is
+    /// never replaced by a microphone
+    fn reopen(&self) -> Result<Self> {
+        CpalSource::new(&self.selector)
+    }
+}
+
+/// Open an input stream delivering samples of type `T`, normalized to f32
+/// before they enter the capture ring
+fn build_stream<T>(device: &Device, config: &StreamConfig, mut output: SourceOutput) -> Result<Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let notifier = output.notifier();
+    let err_fn = move |err| {
+        log::error!("Audio stream error: {}", err);
+        notifier.error(format!("{}", err));
+        if matches!(err, StreamError::DeviceNotAvailable) {
+            notifier.lost(format!("{}", err));
+        }
+    };
+
+    // Timestamps are measured from the first callback's capture time
+    let mut origin = None;
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], info: &cpal::InputCallbackInfo| {
+            let timestamp = info.timestamp();
+            let origin = *origin.get_or_insert(timestamp.capture);
+            if let (Some(captured), Some(delivered)) = (
+                timestamp.capture.duration_since(&origin),
+                timestamp.callback.duration_since(&origin),
+            ) {
+                output.timestamp(captured, delivered);
+            }
+            push_normalized(&mut output, data);
+        },
+        err_fn,
+        None,
+    )?;
+    Ok(stream)
+}
+
+fn push_normalized<T>(output: &mut SourceOutput, data: &[T])
+where
+    T: Sample,
+    f32: FromSample<T>,
+{
+    output.push_iter(data.len(), data.iter().map(|&s| s.to_sample::<f32>()));
+}
+
+/// Keeps a file or generator playback thread running until dropped
+pub struct PlaybackHandle {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for PlaybackHandle {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Deliver audio from `fill` at the given pace on a new thread
+///
+/// `fill` writes interleaved samples into the buffer it is given and returns
+/// how many it wrote; returning 0 ends playback.
+pub(crate) fn spawn_playback<F>(
+    name: &str,
+    mut output: SourceOutput,
+    format: SourceFormat,
+    pacing: Pacing,
+    mut fill: F,
+) -> Result<PlaybackHandle>
+where
+    F: FnMut(&mut [f32]) -> usize + Send + 'static,
+{
+    let running = Arc::new(AtomicBool::new(true));
+    let thread_running = Arc::clone(&running);
+
+    let handle = thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            // 10 ms per chunk, like a typical device callback
+            let channels = format.channels.max(1) as usize;
+            let chunk = (format.sample_rate as usize / 100).max(1) * channels;
+            let mut buffer = vec![0.0f32; chunk];
+            let started = Instant::now();
+            let mut frames_sent = 0u64;
+
+            while thread_running.load(Ordering::Acquire) {
+                let written = fill(&mut buffer);
+                if written == 0 {
+                    output.notifier().ended();
+                    return;
+                }
+
+                let speed = match pacing {
+                    Pacing::RealTime => Some(1.0),
+                    Pacing::Speed(speed) => Some(speed.max(f64::MIN_POSITIVE)),
+                    Pacing::Unthrottled => None,
+                };
+                if let Some(speed) = speed {
+                    let due = Duration::from_secs_f64(
+                        frames_sent as f64 / format.sample_rate as f64 / speed,
+                    );
+                    if let Some(wait) = due.checked_sub(started.elapsed()) {
+                        thread::sleep(wait);
+                    }
+                }
+
+                // Never drop audio that is only waiting on the consumer
+                while output.free_space() < written {
+                    if !thread_running.load(Ordering::Acquire) {
+                        return;
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                output.push(&buffer[..written]);
+                frames_sent += (written / channels) as u64;
+            }
+        })?;
+
+    Ok(PlaybackHandle {
+        running,
+        handle: Some(handle),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::ring::{capture_ring, CaptureConsumer, Discontinuity};
+
+    fn output_for(sample_rate: u32, channels: u16, capacity: usize) -> (SourceOutput, CaptureConsumer) {
+        let (producer, consumer) = capture_ring(capacity);
+        let format = SourceFormat {
+            sample_rate,
+            channels,
+            sample_format: SampleFormat::F32,
+        };
+        let output = SourceOutput::new(
+            producer,
+            &format,
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(CaptureClock::new()),
+            Weak::new(),
+        );
+        (output, consumer)
+    }
+
+    #[test]
+    fn test_integer_samples_are_normalized() {
+        let (mut output, mut consumer) = output_for(16000, 1, 16);
+        push_normalized(&mut output, &[i16::MIN, 0, i16::MAX]);
+        push_normalized(&mut output, &[0u16, 32768, u16::MAX]);
+
+        let mut out = Vec::new();
+        consumer.drain_into(&mut out);
+
+        let expected = [-1.0, 0.0, 1.0, -1.0, 0.0, 1.0];
+        for (sample, expected) in out.iter().zip(expected) {
+            assert!((sample - expected).abs() < 1e-4, "{} != {}", sample, expected);
+        }
+    }
+
+    #[test]
+    fn test_timestamp_jump_marks_skipped_audio() {
+        let (mut output, mut consumer) = output_for(1000, 2, 1000);
+        let ms = Duration::from_millis;
+
+        // 10 ms callbacks, then one arriving 30 ms after the last instead of
+        // 10 ms; a millisecond of jitter is not a break
+        output.timestamp(ms(0), ms(1));
+        output.push(&[0.0; 20]);
+        output.timestamp(ms(11), ms(12));
+        output.push(&[0.0; 20]);
+        output.timestamp(ms(41), ms(45));
+        output.push(&[0.0; 20]);
+
+        consumer.drain_into(&mut Vec::new());
+        assert_eq!(
+            consumer.next_discontinuity(),
+            Some(Discontinuity::Skipped { at: 40, missing: ms(20) })
+        );
+        assert_eq!(consumer.next_discontinuity(), None);
+        assert_eq!(output.clock.latency(), Some(ms(4)));
+        assert!(output.clock.anchor().is_some());
+    }
+}
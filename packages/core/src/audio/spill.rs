@@ -0,0 +1,310 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::audio::encoding::{WavEncoder, WavStream};
+use crate::audio::flac::repair_flac;
+use crate::audio::ogg::repair_ogg;
+use crate::utils::error::{Result, VoicePAError};
+
+/// Writes captured audio to a WAV file as it arrives
+///
+/// The header is rewritten about once per second of audio, so after a crash
+/// the file holds a valid WAV with at most the last second missing from the
+/// header; [`repair_wav`] recovers the rest.
+pub struct SpillWriter {
+    stream: WavStream<BufWriter<File>>,
+    path: PathBuf,
+}
+
+impl SpillWriter {
+    /// Start a new WAV file at `path`, in the sample format of `encoder`
+    ///
+    /// A file already there, such as one abandoned by a crash, is never
+    /// overwritten: it is repaired and renamed to `<name>.recovered.wav`
+    /// (or `<name>.recovered-2.wav` and so on) first.
+    pub fn create(
+        path: impl AsRef<Path>,
+        encoder: &WavEncoder,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            recover_previous(&path)?;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| {
+                VoicePAError::Storage(format!("Failed to create {}: {}", path.display(), e))
+            })?;
+        let stream = encoder.stream(BufWriter::new(file), sample_rate, channels)?;
+
+        Ok(Self { stream, path })
+    }
+
+    /// Append interleaved samples
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        self.stream.write(samples)
+    }
+
+    /// Number of sample frames written so far
+    pub fn frames(&self) -> u64 {
+        self.stream.frames()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the final header and close the file
+    pub fn finalize(self) -> Result<PathBuf> {
+        self.stream.finish()?;
+        Ok(self.path)
+    }
+
+    /// Close the file keeping only its first `frames` sample frames
+    pub fn finalize_at(self, frames: u64) -> Result<PathBuf> {
+        let block_align = self.stream.block_align();
+        let written = self.frames();
+        let path = self.finalize()?;
+
+        if written > frames {
+            // The pad byte after odd-sized data goes too; repair_wav re-adds it
+            let excess = (written - frames) * block_align + written * block_align % 2;
+            let file = OpenOptions::new().write(true).open(&path)?;
+            let len = file.metadata()?.len();
+            file.set_len(len - excess)?;
+            drop(file);
+            repair_wav(&path)?;
+        }
+        Ok(path)
+    }
+}
+
+/// Repair the file left at a spill path and move it out of the way
+fn recover_previous(path: &Path) -> Result<()> {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut recovered = path.with_file_name(format!("{}.recovered.wav", stem));
+    let mut attempt = 1;
+    while recovered.exists() {
+        attempt += 1;
+        recovered = path.with_file_name(format!("{}.recovered-{}.wav", stem, attempt));
+    }
+
+    // A file that cannot be repaired is still kept, as it was found
+    match repair_wav(path) {
+        Ok(frames) => log::warn!(
+            "Recovered {} frames from {}, moving it to {}",
+            frames,
+            path.display(),
+            recovered.display()
+        ),
+        Err(e) => log::warn!(
+            "Moving unrepairable {} to {}: {}",
+            path.display(),
+            recovered.display(),
+            e
+        ),
+    }
+    std::fs::rename(path, &recovered).map_err(|e| {
+        VoicePAError::Storage(format!(
+            "Failed to move {} out of the way: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    Ok(())
+}
+
+/// Repair a WAV, FLAC or Ogg/Opus file left unfinished by a crash, telling
+/// the format from its first bytes
+///
+/// Returns the number of sample frames in the repaired file, at 48 kHz for
+/// Opus.
+pub fn repair_audio_file(path: impl AsRef<Path>) -> Result<u64> {
+    let path = path.as_ref();
+    let mut magic = [0u8; 4];
+    File::open(path)?.read_exact(&mut magic).map_err(|_| {
+        VoicePAError::Storage(format!("Cannot repair {}: file too short", path.display()))
+    })?;
+
+    match &magic {
+        b"RIFF" => repair_wav(path),
+        b"fLaC" => repair_flac(path),
+        b"OggS" => repair_ogg(path),
+        _ => Err(VoicePAError::Storage(format!(
+            "Cannot repair {}: unknown audio format",
+            path.display()
+        ))),
+    }
+}
+
+/// Fix the RIFF and data chunk sizes of a WAV file whose writer never
+/// finalized it, e.g. after a crash
+///
+/// The data chunk is assumed to run to the end of the file, as it does in
+/// files written by [`SpillWriter`]. A trailing partial frame is truncated.
+/// Returns the number of sample frames in the repaired file.
+pub fn repair_wav(path: impl AsRef<Path>) -> Result<u64> {
+    let path = path.as_ref();
+    let invalid = |reason: &str| {
+        VoicePAError::Storage(format!("Cannot repair {}: {}", path.display(), reason))
+    };
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header).map_err(|_| invalid("file too short"))?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(invalid("not a RIFF/WAVE file"));
+    }
+
+    let mut position = 12u64;
+    let mut block_align = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut chunk).map_err(|_| invalid("no data chunk"))?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                file.read_exact(&mut fmt).map_err(|_| invalid("truncated fmt chunk"))?;
+                block_align = Some(u16::from_le_bytes([fmt[12], fmt[13]]) as u64);
+            }
+            b"data" => break,
+            _ => {}
+        }
+        position += 8 + size + size % 2;
+    }
+
+    let block_align = block_align
+        .filter(|&align| align > 0)
+        .ok_or_else(|| invalid("missing fmt chunk"))?;
+    let data_start = position + 8;
+    let available = file_len.saturating_sub(data_start);
+    let data_len = available - available % block_align;
+    let end = data_start + data_len + data_len % 2;
+
+    file.set_len(end)?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&((end - 8) as u32).to_le_bytes())?;
+    file.seek(SeekFrom::Start(position + 4))?;
+    file.write_all(&(data_len as u32).to_le_bytes())?;
+    file.sync_all()?;
+
+    Ok(data_len / block_align)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::encoding::{AudioEncoder, WavSampleFormat};
+    use crate::audio::flac::FlacEncoder;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_repair_unfinalized_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("crashed.wav");
+
+        let mut writer = SpillWriter::create(&path, &WavEncoder::new(), 16000, 1).unwrap();
+        writer.write(&vec![0.25; 24000]).unwrap();
+        writer.finalize().unwrap();
+
+        // Simulate a crash before the header was ever updated, with more
+        // audio and half a sample on disk after the last header write
+        let mut raw = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        raw.seek(SeekFrom::Start(4)).unwrap();
+        raw.write_all(&0u32.to_le_bytes()).unwrap();
+        raw.seek(SeekFrom::End(0)).unwrap();
+        raw.write_all(&[0u8; 2 * 500 + 1]).unwrap();
+        let data_size_offset = 40;
+        raw.seek(SeekFrom::Start(data_size_offset)).unwrap();
+        raw.write_all(&0u32.to_le_bytes()).unwrap();
+        drop(raw);
+
+        assert_eq!(repair_wav(&path).unwrap(), 24500);
+
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.duration(), 24500);
+        assert_eq!(reader.spec().sample_rate, 16000);
+    }
+
+    #[test]
+    fn test_spill_in_configured_sample_format() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("spill.wav");
+        let encoder = WavEncoder::new().with_sample_format(WavSampleFormat::Int24);
+
+        // Odd-sized 24-bit mono data, cut back to another odd frame count
+        let mut writer = SpillWriter::create(&path, &encoder, 16000, 1).unwrap();
+        writer.write(&vec![0.25; 16_001]).unwrap();
+        writer.finalize_at(12_001).unwrap();
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, 24);
+        assert_eq!(reader.duration(), 12_001);
+        assert!(reader.samples::<i32>().all(|s| s.unwrap() == 1 << 21));
+        assert_eq!(std::fs::metadata(&path).unwrap().len() % 2, 0);
+    }
+
+    #[test]
+    fn test_new_recording_keeps_abandoned_spill_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("spill.wav");
+
+        let mut crashed = SpillWriter::create(&path, &WavEncoder::new(), 16000, 1).unwrap();
+        crashed.write(&vec![0.25; 40_000]).unwrap();
+        std::mem::forget(crashed);
+
+        let mut next = SpillWriter::create(&path, &WavEncoder::new(), 16000, 1).unwrap();
+        next.write(&vec![-0.5; 8000]).unwrap();
+        next.finalize().unwrap();
+
+        let recovered = hound::WavReader::open(dir.path().join("spill.recovered.wav")).unwrap();
+        assert!((32_000..=40_000).contains(&recovered.duration()));
+        assert_eq!(hound::WavReader::open(&path).unwrap().duration(), 8000);
+
+        // Another crash does not replace the first recovered file
+        SpillWriter::create(&path, &WavEncoder::new(), 16000, 1).unwrap();
+        assert!(dir.path().join("spill.recovered-2.wav").exists());
+    }
+
+    #[test]
+    fn test_repair_abandoned_streams() {
+        let dir = tempdir().unwrap();
+        let samples: Vec<f32> = (0..40_000).map(|i| ((i as f32) * 0.02).sin() * 0.5).collect();
+        let encoders: [Box<dyn AudioEncoder>; 2] =
+            [Box::new(WavEncoder::new()), Box::new(FlacEncoder::new())];
+
+        for encoder in encoders {
+            let path = dir.path().join(format!("abandoned.{}", encoder.file_extension()));
+            let mut stream = encoder.create_file(&path, 16000, 1).unwrap();
+            for chunk in samples.chunks(1600) {
+                stream.write(chunk).unwrap();
+            }
+            // Leak the stream as a crash would, losing whatever it buffered
+            std::mem::forget(stream);
+
+            // At least the audio up to the last checkpoint survives
+            let frames = repair_audio_file(&path).unwrap();
+            assert!((32_000..=40_000).contains(&frames), "{} frames", frames);
+            let decoded = crate::audio::decoding::FileDecoder::new()
+                .decode_file(&path)
+                .unwrap();
+            assert_eq!(decoded.frames() as u64, frames);
+        }
+
+        let path = dir.path().join("garbage.bin");
+        std::fs::write(&path, b"not audio").unwrap();
+        assert!(repair_audio_file(&path).is_err());
+    }
+}
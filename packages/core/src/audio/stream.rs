@@ -0,0 +1,365 @@
+// Ownership of the running source: starting it, watching it for failures
+// and reopening it when the recovery policy allows
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::broadcast;
+use crate::audio::channels::ChannelMapping;
+use crate::audio::events::{RecorderEvent, RecoveryPolicy};
+use crate::audio::recording::StreamGap;
+use crate::audio::resampler::{AudioResampler, ResampleQuality};
+use crate::audio::ring::{shared_capture_ring, CaptureConsumer, CaptureProducer};
+use crate::audio::source::{AudioSource, CaptureClock, Notice, SourceFormat, SourceOutput};
+use crate::utils::error::{Result, VoicePAError};
+
+/// Seconds of native audio the capture ring can hold before dropping samples
+const RING_BUFFER_SECONDS: usize = 2;
+
+/// How often the supervisor checks that the stream is still delivering audio
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A stream that delivers nothing for this long is treated as lost
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Source and native format of the stream feeding the recorder
+#[derive(Debug, Clone)]
+pub(crate) struct ActiveStream {
+    pub name: String,
+    pub format: SourceFormat,
+}
+
+/// A reopened stream's ring and the resampler for its native format
+pub(crate) struct Segment {
+    pub consumer: CaptureConsumer,
+    pub resampler: AudioResampler,
+}
+
+/// State shared between the recorder and its stream supervisor
+pub(crate) struct SupervisorContext {
+    pub policy: RecoveryPolicy,
+    pub output_rate: u32,
+    pub output_channels: u16,
+    pub quality: ResampleQuality,
+    pub mapping: ChannelMapping,
+    pub segments: Sender<Segment>,
+    pub events: broadcast::Sender<RecorderEvent>,
+    pub active: Arc<Mutex<Option<ActiveStream>>>,
+    pub gaps: Arc<Mutex<Vec<StreamGap>>>,
+    pub recorded_frames: Arc<AtomicU64>,
+    pub dropped: Arc<AtomicU64>,
+    pub clock: Arc<CaptureClock>,
+    pub failed: Arc<AtomicBool>,
+}
+
+/// Create a capture ring sized for a source in `format`
+pub(crate) fn ring_for(
+    format: &SourceFormat,
+    dropped: Arc<AtomicU64>,
+) -> (CaptureProducer, CaptureConsumer) {
+    let capacity = format.sample_rate as usize * format.channels as usize * RING_BUFFER_SECONDS;
+    shared_capture_ring(capacity, dropped)
+}
+
+/// Handle to the thread that owns the running source
+///
+/// cpal streams cannot move between threads, so the source is started, kept
+/// and reopened on this thread for the whole recording.
+///
+/// Dropping the handle stops the thread and closes the source, like
+/// [`stop`](Self::stop). The handle holds the only strong sender of the
+/// thread's notices; the stream callbacks hold weak ones, so the thread also
+/// ends if the handle is ever lost without being dropped normally.
+pub(crate) struct StreamSupervisor {
+    notices: Arc<Sender<Notice>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StreamSupervisor {
+    /// Start `source` feeding `producer`, then watch it until stopped
+    ///
+    /// `source.format()` must already have been resolved.
+    pub fn spawn<S: AudioSource>(
+        mut source: S,
+        format: SourceFormat,
+        producer: CaptureProducer,
+        context: SupervisorContext,
+    ) -> Result<Self> {
+        let (notices, receiver) = mpsc::channel();
+        let notices = Arc::new(notices);
+        let (ready_tx, ready_rx) = mpsc::sync_channel(1);
+        let thread_notices = Arc::downgrade(&notices);
+
+        let handle = thread::Builder::new()
+            .name("voice-pa-stream".to_string())
+            .spawn(move || {
+                let heartbeat = Arc::new(AtomicU64::new(0));
+                let output = SourceOutput::new(
+                    producer,
+                    &format,
+                    Arc::clone(&heartbeat),
+                    Arc::clone(&context.clock),
+                    thread_notices.clone(),
+                );
+                let stream = match source.start(output) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                *context.active.lock().unwrap() = Some(ActiveStream {
+                    name: source.name(),
+                    format,
+                });
+                let _ = ready_tx.send(Ok(()));
+
+                Supervisor {
+                    source,
+                    context,
+                    notices: thread_notices,
+                    heartbeat,
+                    stream: Some(stream),
+                    ended: false,
+                    gap: None,
+                }
+                .run(receiver);
+            })?;
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Self {
+                notices,
+                handle: Some(handle),
+            }),
+            Ok(Err(e)) => {
+                let _ = handle.join();
+                Err(e)
+            }
+            Err(_) => Err(VoicePAError::AudioStream("Stream thread exited unexpectedly".to_string())),
+        }
+    }
+
+    /// Close the stream early, e.g. after an automatic stop, without ending
+    /// the thread
+    pub fn releaser(&self) -> impl FnOnce() + Send {
+        let notices = Arc::downgrade(&self.notices);
+        move || {
+            if let Some(notices) = notices.upgrade() {
+                let _ = notices.send(Notice::Release);
+            }
+        }
+    }
+
+    /// Close the stream and wait for the thread to finish its bookkeeping
+    pub fn stop(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        let Some(handle) = self.handle.take() else {
+            return Ok(());
+        };
+        let _ = self.notices.send(Notice::Stop);
+        handle
+            .join()
+            .map_err(|_| VoicePAError::AudioStream("Stream supervisor thread panicked".to_string()))
+    }
+}
+
+impl Drop for StreamSupervisor {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            log::error!("{}", e);
+        }
+    }
+}
+
+struct Supervisor<S: AudioSource> {
+    source: S,
+    context: SupervisorContext,
+    notices: Weak<Sender<Notice>>,
+    heartbeat: Arc<AtomicU64>,
+    stream: Option<S::Stream>,
+    ended: bool,
+    gap: Option<(StreamGap, Instant)>,
+}
+
+impl<S: AudioSource> Supervisor<S> {
+    fn run(mut self, receiver: Receiver<Notice>) {
+        let mut last_beat = self.heartbeat.load(Ordering::Relaxed);
+        let mut last_progress = Instant::now();
+
+        loop {
+            let notice = match self.wait_interval() {
+                Some(interval) => receiver.recv_timeout(interval),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match notice {
+                Ok(Notice::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(Notice::Error(message)) => {
+                    self.emit(RecorderEvent::StreamError { message });
+                }
+                Ok(Notice::Lost(reason)) => {
+                    if self.stream.is_some() {
+                        self.lose(reason);
+                    }
+                }
+                Ok(Notice::Release) => {
+                    self.stream = None;
+                    self.ended = true;
+                    self.close_gap();
+                }
+                Ok(Notice::Ended) => {
+                    log::info!("Source {} ended", self.source.name());
+                    self.ended = true;
+                    self.emit(RecorderEvent::SourceEnded);
+                }
+                Err(RecvTimeoutError::Timeout) if self.stream.is_some() => {
+                    let beat = self.heartbeat.load(Ordering::Relaxed);
+                    if beat != last_beat {
+                        last_beat = beat;
+                        last_progress = Instant::now();
+                    } else if last_progress.elapsed() >= STALL_TIMEOUT {
+                        self.lose(format!("no audio for {:?}", STALL_TIMEOUT));
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.recover() {
+                        last_progress = Instant::now();
+                    }
+                }
+            }
+        }
+
+        self.stream = None;
+        self.close_gap();
+    }
+
+    fn close_gap(&mut self) {
+        if let Some((mut gap, since)) = self.gap.take() {
+            gap.duration = since.elapsed();
+            self.context.gaps.lock().unwrap().push(gap);
+        }
+    }
+
+    /// How long to wait for a notice; `None` once there is nothing left to
+    /// do but wait for the recording to stop
+    fn wait_interval(&self) -> Option<Duration> {
+        if self.ended {
+            return None;
+        }
+        if self.stream.is_some() {
+            return Some(WATCHDOG_INTERVAL);
+        }
+        match self.context.policy {
+            RecoveryPolicy::ReopenDefault { retry_interval, .. }
+                if !self.context.failed.load(Ordering::Acquire) =>
+            {
+                Some(retry_interval)
+            }
+            _ => None,
+        }
+    }
+
+    fn lose(&mut self, reason: String) {
+        self.stream = None;
+
+        let frames = self.context.recorded_frames.load(Ordering::Relaxed);
+        let gap = StreamGap {
+            position: Duration::from_secs_f64(frames as f64 / self.context.output_rate as f64),
+            started_at: SystemTime::now(),
+            duration: Duration::ZERO,
+        };
+        self.gap = Some((gap, Instant::now()));
+
+        let device_name = self.source.name();
+        log::error!("Lost input stream on {}: {}", device_name, reason);
+        self.emit(RecorderEvent::StreamLost { device_name, reason });
+
+        if self.context.policy == RecoveryPolicy::Disabled {
+            self.context.failed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Try once to reopen capture; returns whether the stream is back
+    fn recover(&mut self) -> bool {
+        let RecoveryPolicy::ReopenDefault { give_up_after, .. } = self.context.policy else {
+            return false;
+        };
+        let Some((_, since)) = &self.gap else {
+            return false;
+        };
+
+        if since.elapsed() >= give_up_after {
+            let reason = format!("no input available after {:?}", give_up_after);
+            log::error!("Giving up on stream recovery: {}", reason);
+            self.context.failed.store(true, Ordering::Release);
+            self.emit(RecorderEvent::RecoveryFailed { reason });
+            return false;
+        }
+
+        match self.reopen() {
+            Ok(()) => {
+                let device_name = self.source.name();
+                if let Some((mut gap, since)) = self.gap.take() {
+                    gap.duration = since.elapsed();
+                    log::info!("Recovered input stream on {} after {:?}", device_name, gap.duration);
+                    self.emit(RecorderEvent::StreamRecovered {
+                        device_name,
+                        gap: gap.duration,
+                    });
+                    self.context.gaps.lock().unwrap().push(gap);
+                }
+                true
+            }
+            Err(e) => {
+                log::warn!("Stream recovery attempt failed: {}", e);
+                false
+            }
+        }
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        let mut source = self.source.reopen()?;
+        let format = source.format()?;
+
+        let resampler = AudioResampler::new(
+            format.sample_rate,
+            format.channels,
+            self.context.output_rate,
+            self.context.output_channels,
+            self.context.quality,
+        )?
+        .with_channel_mapping(self.context.mapping);
+        let (producer, consumer) = ring_for(&format, Arc::clone(&self.context.dropped));
+        let output = SourceOutput::new(
+            producer,
+            &format,
+            Arc::clone(&self.heartbeat),
+            Arc::clone(&self.context.clock),
+            self.notices.clone(),
+        );
+        let stream = source.start(output)?;
+
+        self.context
+            .segments
+            .send(Segment { consumer, resampler })
+            .map_err(|_| VoicePAError::AudioStream("Capture consumer thread is gone".to_string()))?;
+
+        *self.context.active.lock().unwrap() = Some(ActiveStream {
+            name: source.name(),
+            format,
+        });
+        self.source = source;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn emit(&self, event: RecorderEvent) {
+        // Having no subscribers is fine
+        let _ = self.context.events.send(event);
+    }
+}
@@ -0,0 +1,370 @@
+// Frame-based voice activity detection producing timestamped speech segments
+
+use std::f32::consts::PI;
+use std::time::Duration;
+
+/// How quickly the noise floor estimate may rise, in dB per second
+const NOISE_FLOOR_RISE_DB: f32 = 3.0;
+
+/// Frames whose zero-crossing rate is above this sound like hiss, not voice
+const MAX_ZERO_CROSSING_RATE: f32 = 0.35;
+
+/// Share of a frame's energy that must fall in the speech band
+const MIN_SPEECH_BAND_RATIO: f32 = 0.4;
+
+/// Band holding most of the energy of voiced speech, from the lowest
+/// fundamentals up to the telephone band edge, in Hz
+const SPEECH_BAND: (f32, f32) = (100.0, 3400.0);
+
+/// Features a frame is judged on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VadMode {
+    /// Level relative to the noise floor only
+    #[default]
+    Energy,
+    /// Level, plus a low zero-crossing rate and most energy in the speech
+    /// band, which rejects hiss, clicks and rumble
+    Spectral,
+}
+
+/// Settings of the [`SpeechDetector`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VadConfig {
+    /// Length of the frames judged one at a time
+    pub frame_duration: Duration,
+    /// How far above the estimated noise floor, in dB, speech must be
+    pub threshold_db: f32,
+    /// Level in dBFS below which nothing counts as speech, however quiet
+    /// the background
+    pub min_level_db: f32,
+    /// Speech must be heard this long before a segment opens
+    pub attack: Duration,
+    /// Audio kept after the last speech so trailing words are not clipped
+    pub hangover: Duration,
+    /// Shorter segments are dropped
+    pub min_speech: Duration,
+    /// Shorter pauses, after the hangover, join their segments
+    pub min_silence: Duration,
+    pub mode: VadMode,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            frame_duration: Duration::from_millis(20),
+            threshold_db: 9.0,
+            min_level_db: -50.0,
+            attack: Duration::from_millis(60),
+            hangover: Duration::from_millis(300),
+            min_speech: Duration::from_millis(250),
+            min_silence: Duration::from_millis(200),
+            mode: VadMode::Energy,
+        }
+    }
+}
+
+/// A stretch of audio holding speech
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeechSegment {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl SpeechSegment {
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// Finds speech in audio of any level of steady background noise
+///
+/// Each frame is compared against a noise floor that follows the quietest
+/// recent frames. Segments open after [`VadConfig::attack`] of speech and
+/// close once [`VadConfig::hangover`] and [`VadConfig::min_silence`] have
+/// passed without any.
+pub struct SpeechDetector {
+    config: VadConfig,
+    sample_rate: u32,
+    channels: usize,
+    frame_len: usize,
+    attack_frames: u32,
+    hangover: u64,
+    close_after: u64,
+    min_speech: u64,
+    floor_rise: f32,
+    bands: BandSplitter,
+    /// Mono samples of the frame being filled
+    frame: Vec<f32>,
+    /// Sample frames judged so far
+    position: u64,
+    noise_floor: Option<f32>,
+    speech_run: u32,
+    /// Start of the open segment and end of the last speech frame in it
+    open: Option<(u64, u64)>,
+}
+
+impl SpeechDetector {
+    pub fn new(config: VadConfig, sample_rate: u32, channels: u16) -> Self {
+        let to_frames = |duration: Duration| (duration.as_secs_f64() * sample_rate as f64) as u64;
+        let frame_len = to_frames(config.frame_duration).max(1);
+        let frames_per_sec = sample_rate as f32 / frame_len as f32;
+
+        Self {
+            sample_rate,
+            channels: channels.max(1) as usize,
+            frame_len: frame_len as usize,
+            attack_frames: to_frames(config.attack).div_ceil(frame_len).max(1) as u32,
+            hangover: to_frames(config.hangover),
+            close_after: to_frames(config.hangover + config.min_silence),
+            min_speech: to_frames(config.min_speech),
+            floor_rise: NOISE_FLOOR_RISE_DB / frames_per_sec,
+            bands: BandSplitter::new(sample_rate),
+            frame: Vec::with_capacity(frame_len as usize),
+            position: 0,
+            noise_floor: None,
+            speech_run: 0,
+            open: None,
+            config,
+        }
+    }
+
+    /// Analyse interleaved samples, returning the segments that ended
+    pub fn process(&mut self, samples: &[f32]) -> Vec<SpeechSegment> {
+        let mut segments = Vec::new();
+        for frame in samples.chunks_exact(self.channels) {
+            self.frame.push(frame.iter().sum::<f32>() / self.channels as f32);
+            if self.frame.len() == self.frame_len {
+                let speech = self.is_speech();
+                self.frame.clear();
+                self.position += self.frame_len as u64;
+                segments.extend(self.advance(speech));
+            }
+        }
+        segments
+    }
+
+    /// Close the segment still open at the end of the audio
+    pub fn finish(mut self) -> Vec<SpeechSegment> {
+        self.position += self.frame.len() as u64;
+        match self.open.take() {
+            Some((start, last_speech)) => {
+                let end = (last_speech + self.hangover).min(self.position);
+                self.segment(start, end).into_iter().collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether speech is being heard, allowing for attack and hangover
+    pub fn in_speech(&self) -> bool {
+        self.open.is_some()
+    }
+
+    /// Whether the last frame was silent and no segment is open, so the
+    /// audio is safe to treat as background
+    pub fn hears_silence(&self) -> bool {
+        self.open.is_none() && self.speech_run == 0
+    }
+
+    /// Current noise floor estimate in dBFS
+    pub fn noise_floor_db(&self) -> Option<f32> {
+        self.noise_floor
+    }
+
+    /// Judge the frame just filled
+    fn is_speech(&mut self) -> bool {
+        let mut total = 0.0;
+        let mut band = 0.0;
+        let mut crossings = 0;
+        let mut previous = self.frame[0];
+        for &sample in &self.frame {
+            total += sample * sample;
+            let banded = self.bands.process(sample);
+            band += banded * banded;
+            if (sample >= 0.0) != (previous >= 0.0) {
+                crossings += 1;
+            }
+            previous = sample;
+        }
+
+        let level = 10.0 * (total / self.frame.len() as f32 + 1e-12).log10();
+        // The floor drops straight to quieter frames and creeps up otherwise
+        let floor = match self.noise_floor {
+            Some(floor) if level > floor => floor + self.floor_rise.min(level - floor),
+            _ => level,
+        };
+        self.noise_floor = Some(floor);
+
+        let loud = level > self.config.min_level_db && level > floor + self.config.threshold_db;
+        match self.config.mode {
+            VadMode::Energy => loud,
+            VadMode::Spectral => {
+                let zero_crossing_rate = crossings as f32 / self.frame.len() as f32;
+                loud && zero_crossing_rate <= MAX_ZERO_CROSSING_RATE
+                    && band >= total * MIN_SPEECH_BAND_RATIO
+            }
+        }
+    }
+
+    /// Move the segment state machine past one frame
+    fn advance(&mut self, speech: bool) -> Option<SpeechSegment> {
+        let end = self.position;
+        self.speech_run = if speech { self.speech_run + 1 } else { 0 };
+
+        match self.open.as_mut() {
+            Some((_, last_speech)) if speech => {
+                *last_speech = end;
+                None
+            }
+            Some((start, last_speech)) => {
+                if end - *last_speech < self.close_after {
+                    return None;
+                }
+                let (start, last_speech) = (*start, *last_speech);
+                self.open = None;
+                self.segment(start, last_speech + self.hangover)
+            }
+            None => {
+                if self.speech_run >= self.attack_frames {
+                    let start = end - self.speech_run as u64 * self.frame_len as u64;
+                    self.open = Some((start, end));
+                }
+                None
+            }
+        }
+    }
+
+    fn segment(&self, start: u64, end: u64) -> Option<SpeechSegment> {
+        let to_duration =
+            |frames: u64| Duration::from_secs_f64(frames as f64 / self.sample_rate as f64);
+        (end - start >= self.min_speech).then(|| SpeechSegment {
+            start: to_duration(start),
+            end: to_duration(end),
+        })
+    }
+}
+
+/// One-pole filters isolating the speech band
+struct BandSplitter {
+    high_coefficient: f32,
+    low_coefficient: f32,
+    below_band: f32,
+    band: f32,
+}
+
+impl BandSplitter {
+    fn new(sample_rate: u32) -> Self {
+        let coefficient = |cutoff: f32| {
+            let cutoff = cutoff.min(sample_rate as f32 * 0.45);
+            1.0 - (-2.0 * PI * cutoff / sample_rate as f32).exp()
+        };
+        Self {
+            high_coefficient: coefficient(SPEECH_BAND.0),
+            low_coefficient: coefficient(SPEECH_BAND.1),
+            below_band: 0.0,
+            band: 0.0,
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        self.below_band += self.high_coefficient * (sample - self.below_band);
+        self.band += self.low_coefficient * (sample - self.below_band - self.band);
+        self.band
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    /// White noise at `rms`, from a fixed seed
+    fn noise(frames: usize, rms: f32, seed: &mut u32) -> Vec<f32> {
+        (0..frames)
+            .map(|_| {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 17;
+                *seed ^= *seed << 5;
+                (*seed as f32 / u32::MAX as f32 * 2.0 - 1.0) * rms * 3f32.sqrt()
+            })
+            .collect()
+    }
+
+    /// A vowel-like tone at 150 Hz with harmonics
+    fn voice(frames: usize, amplitude: f32) -> Vec<f32> {
+        (0..frames)
+            .map(|i| {
+                let t = i as f32 / RATE as f32;
+                amplitude
+                    * (0.6 * (2.0 * PI * 150.0 * t).sin()
+                        + 0.3 * (2.0 * PI * 450.0 * t).sin()
+                        + 0.1 * (2.0 * PI * 900.0 * t).sin())
+            })
+            .collect()
+    }
+
+    /// Background noise with `bursts` of voice laid over it at (start, len)
+    /// in milliseconds
+    fn scene(total_ms: usize, noise_rms: f32, bursts: &[(usize, usize)]) -> Vec<f32> {
+        let ms = RATE as usize / 1000;
+        let mut samples = noise(total_ms * ms, noise_rms, &mut 0x1234_5678);
+        for &(start, len) in bursts {
+            for (sample, v) in samples[start * ms..].iter_mut().zip(voice(len * ms, 0.3)) {
+                *sample += v;
+            }
+        }
+        samples
+    }
+
+    fn detect(config: VadConfig, samples: &[f32]) -> Vec<SpeechSegment> {
+        let mut detector = SpeechDetector::new(config, RATE, 1);
+        let mut segments = Vec::new();
+        for chunk in samples.chunks(1000) {
+            segments.extend(detector.process(chunk));
+        }
+        segments.extend(detector.finish());
+        segments
+    }
+
+    fn assert_near(actual: Duration, expected_ms: u64) {
+        let diff = (actual.as_secs_f64() * 1000.0 - expected_ms as f64).abs();
+        assert!(diff <= 40.0, "{:?} vs {} ms", actual, expected_ms);
+    }
+
+    #[test]
+    fn test_segments_adapt_to_noise_floor() {
+        // Two utterances, the first with a short pause that the hangover
+        // bridges, and a click too short to count
+        let bursts = [(1000, 900), (2000, 800), (4500, 600), (6000, 40)];
+        for noise_rms in [0.001, 0.01, 0.03] {
+            let segments = detect(VadConfig::default(), &scene(7000, noise_rms, &bursts));
+            assert_eq!(segments.len(), 2, "noise {}: {:?}", noise_rms, segments);
+            assert_near(segments[0].start, 1000);
+            assert_near(segments[0].end, 2800 + 300);
+            assert_near(segments[1].start, 4500);
+            assert_near(segments[1].end, 5100 + 300);
+        }
+    }
+
+    #[test]
+    fn test_spectral_mode_rejects_hiss() {
+        // A second of loud hiss above the speech band, then speech
+        let mut samples = scene(3500, 0.002, &[(2000, 800)]);
+        for (i, sample) in samples[8000..24000].iter_mut().enumerate() {
+            *sample += (2.0 * PI * 6000.0 * i as f32 / RATE as f32).sin() * 0.3;
+        }
+
+        let energy = detect(VadConfig::default(), &samples);
+        assert_eq!(energy.len(), 2);
+
+        let config = VadConfig {
+            mode: VadMode::Spectral,
+            ..VadConfig::default()
+        };
+        let spectral = detect(config, &samples);
+        assert_eq!(spectral.len(), 1, "{:?}", spectral);
+        assert_near(spectral[0].start, 2000);
+        assert_near(spectral[0].end, 2800 + 300);
+    }
+}
@@ -0,0 +1,490 @@
+use std::time::Duration;
+use symphonia::core::audio::Channels;
+use symphonia::core::codecs::{
+    CodecParameters, CodecType, CODEC_TYPE_AAC, CODEC_TYPE_OPUS, CODEC_TYPE_PCM_F32LE,
+    CODEC_TYPE_PCM_F64LE, CODEC_TYPE_PCM_S16LE, CODEC_TYPE_PCM_S24LE, CODEC_TYPE_PCM_S32LE,
+    CODEC_TYPE_VORBIS,
+};
+use crate::utils::error::{Result, VoicePAError};
+
+const EBML: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const BIT_DEPTH: u32 = 0x6264;
+const CLUSTER: u32 = 0x1F43_B675;
+const CLUSTER_TIMECODE: u32 = 0xE7;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Matroska track type of audio tracks
+const TRACK_TYPE_AUDIO: u64 = 2;
+
+/// Nanoseconds per timecode tick unless the file says otherwise
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+/// The first audio track of a WebM/Matroska file and its packets
+pub(crate) struct MatroskaTrack<'a> {
+    pub params: CodecParameters,
+    pub packets: Vec<MatroskaPacket<'a>>,
+}
+
+pub(crate) struct MatroskaPacket<'a> {
+    pub data: &'a [u8],
+    /// When the packet starts, from its block's timecode; `None` for the
+    /// later frames of a laced block, which follow the one before directly
+    pub timestamp: Option<Duration>,
+}
+
+#[derive(Default)]
+struct TrackEntry<'a> {
+    number: u64,
+    track_type: u64,
+    codec_id: String,
+    codec_private: Option<&'a [u8]>,
+    sample_rate: f64,
+    channels: u64,
+    bit_depth: u64,
+}
+
+/// Check for the EBML header that starts WebM and Matroska files
+pub(crate) fn is_matroska(data: &[u8]) -> bool {
+    data.starts_with(&EBML.to_be_bytes())
+}
+
+/// Pull the first audio track out of a WebM/Matroska file
+///
+/// Elements are read in file order without relying on sizes, cues or a
+/// duration, so files that MediaRecorder never finalized, with segments and
+/// clusters of unknown size or a truncated last cluster, are read up to
+/// their last complete block.
+pub(crate) fn demux(data: &[u8]) -> Result<MatroskaTrack<'_>> {
+    let mut tracks: Vec<TrackEntry> = Vec::new();
+    let mut timecode_scale = DEFAULT_TIMECODE_SCALE;
+    // Each block with the timecode of its cluster
+    let mut blocks: Vec<(u64, &[u8])> = Vec::new();
+    let mut cluster_timecode = 0;
+
+    let mut pos = 0;
+    while let Some((id, size, body)) = read_element_header(data, pos) {
+        // Containers are entered rather than skipped, so their size may be
+        // unknown
+        if matches!(id, SEGMENT | INFO | TRACKS | TRACK_ENTRY | AUDIO | CLUSTER | BLOCK_GROUP) {
+            if id == TRACK_ENTRY {
+                tracks.push(TrackEntry::default());
+            }
+            pos = body;
+            continue;
+        }
+
+        let Some(size) = size else {
+            return Err(VoicePAError::Decoding(format!(
+                "Matroska element {:#X} has an unknown size",
+                id
+            )));
+        };
+        let Some(content) = data.get(body..body + size as usize) else {
+            break;
+        };
+        pos = body + size as usize;
+
+        if let Some(track) = tracks.last_mut() {
+            match id {
+                TRACK_NUMBER => track.number = read_uint(content),
+                TRACK_TYPE => track.track_type = read_uint(content),
+                CODEC_ID => {
+                    track.codec_id = String::from_utf8_lossy(content)
+                        .trim_end_matches('\0')
+                        .to_string()
+                }
+                CODEC_PRIVATE => track.codec_private = Some(content),
+                SAMPLING_FREQUENCY => track.sample_rate = read_float(content),
+                CHANNELS => track.channels = read_uint(content),
+                BIT_DEPTH => track.bit_depth = read_uint(content),
+                _ => {}
+            }
+        }
+        match id {
+            TIMECODE_SCALE => timecode_scale = read_uint(content).max(1),
+            CLUSTER_TIMECODE => cluster_timecode = read_uint(content),
+            SIMPLE_BLOCK | BLOCK => blocks.push((cluster_timecode, content)),
+            _ => {}
+        }
+    }
+
+    let track = tracks
+        .into_iter()
+        .find(|track| track.track_type == TRACK_TYPE_AUDIO)
+        .ok_or_else(|| VoicePAError::Decoding("No audio track found".to_string()))?;
+
+    let mut packets = Vec::new();
+    for (cluster_timecode, block) in blocks {
+        let Some((number, len)) = read_vint(block) else {
+            continue;
+        };
+        if number != track.number {
+            continue;
+        }
+        let block = &block[len..];
+        let (Some(relative), Some(frames)) = (block.get(..2), unlace(block)) else {
+            continue;
+        };
+        // Block timecodes are signed and relative to their cluster's
+        let relative = i16::from_be_bytes([relative[0], relative[1]]) as i64;
+        let ticks = (cluster_timecode as i64).saturating_add(relative).max(0) as u64;
+        let timestamp = Duration::from_nanos(ticks.saturating_mul(timecode_scale));
+        packets.extend(frames.into_iter().enumerate().map(|(index, data)| MatroskaPacket {
+            data,
+            timestamp: (index == 0).then_some(timestamp),
+        }));
+    }
+
+    let frames: Vec<&[u8]> = packets.iter().map(|packet| packet.data).collect();
+    let params = codec_parameters(&track, &frames)?;
+    Ok(MatroskaTrack { params, packets })
+}
+
+fn codec_parameters(track: &TrackEntry, packets: &[&[u8]]) -> Result<CodecParameters> {
+    let channels = track.channels.clamp(1, 32) as u16;
+    let mut params = CodecParameters::new();
+    params
+        .with_sample_rate(track.sample_rate.round() as u32)
+        .with_channels(Channels::from_bits_truncate(((1u64 << channels) - 1) as u32));
+
+    let codec = match track.codec_id.as_str() {
+        "A_OPUS" => CODEC_TYPE_OPUS,
+        "A_VORBIS" => {
+            // CodecPrivate laces the identification, comment and setup
+            // headers; the decoder wants the first and last back to back
+            let headers = track.codec_private.and_then(unlace_xiph).unwrap_or_default();
+            if let [ident, _, setup] = headers.as_slice() {
+                params.with_extra_data([*ident, *setup].concat().into_boxed_slice());
+            }
+            CODEC_TYPE_VORBIS
+        }
+        id if id.starts_with("A_AAC") => CODEC_TYPE_AAC,
+        "A_PCM/INT/LIT" | "A_PCM/FLOAT/IEEE" => {
+            let codec = pcm_codec(&track.codec_id, track.bit_depth)?;
+            let frame_bytes = channels as usize * track.bit_depth as usize / 8;
+            let max_frames = packets.iter().map(|p| p.len() / frame_bytes).max().unwrap_or(0);
+            params
+                .with_bits_per_sample(track.bit_depth as u32)
+                .with_max_frames_per_packet(max_frames.max(1) as u64);
+            codec
+        }
+        id => {
+            return Err(VoicePAError::Decoding(format!(
+                "Unsupported Matroska audio codec: {}",
+                id
+            )))
+        }
+    };
+    params.for_codec(codec);
+    if params.extra_data.is_none() {
+        if let Some(private) = track.codec_private {
+            params.with_extra_data(private.to_vec().into_boxed_slice());
+        }
+    }
+    Ok(params)
+}
+
+fn pcm_codec(codec_id: &str, bit_depth: u64) -> Result<CodecType> {
+    Ok(match (codec_id, bit_depth) {
+        ("A_PCM/INT/LIT", 16) => CODEC_TYPE_PCM_S16LE,
+        ("A_PCM/INT/LIT", 24) => CODEC_TYPE_PCM_S24LE,
+        ("A_PCM/INT/LIT", 32) => CODEC_TYPE_PCM_S32LE,
+        ("A_PCM/FLOAT/IEEE", 32) => CODEC_TYPE_PCM_F32LE,
+        ("A_PCM/FLOAT/IEEE", 64) => CODEC_TYPE_PCM_F64LE,
+        (id, bits) => {
+            return Err(VoicePAError::Decoding(format!(
+                "Unsupported Matroska PCM format: {} at {} bits",
+                id, bits
+            )))
+        }
+    })
+}
+
+/// Read an element ID and size at `pos`; returns the ID, the size (`None`
+/// if unknown) and where the element's content starts
+fn read_element_header(data: &[u8], pos: usize) -> Option<(u32, Option<u64>, usize)> {
+    let first = *data.get(pos)?;
+    let id_len = first.leading_zeros() as usize + 1;
+    if id_len > 4 {
+        return None;
+    }
+    let id = data
+        .get(pos..pos + id_len)?
+        .iter()
+        .fold(0u32, |id, &b| (id << 8) | b as u32);
+
+    let size_at = pos + id_len;
+    let (size, size_len) = read_vint(data.get(size_at..)?)?;
+    let unknown = size == (1u64 << (7 * size_len)) - 1;
+    Some((id, (!unknown).then_some(size), size_at + size_len))
+}
+
+/// Read a variable-length integer with its length marker removed; returns
+/// the value and the number of bytes it took
+fn read_vint(data: &[u8]) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+    let bytes = data.get(..len)?;
+    let marker_free = if len == 8 { 0 } else { first as u64 & (0xFF >> len) };
+    let value = bytes[1..]
+        .iter()
+        .fold(marker_free, |value, &b| (value << 8) | b as u64);
+    Some((value, len))
+}
+
+fn read_uint(content: &[u8]) -> u64 {
+    content.iter().fold(0, |value, &b| (value << 8) | b as u64)
+}
+
+fn read_float(content: &[u8]) -> f64 {
+    match content.len() {
+        4 => f32::from_be_bytes(content.try_into().unwrap()) as f64,
+        8 => f64::from_be_bytes(content.try_into().unwrap()),
+        _ => 0.0,
+    }
+}
+
+/// Split a block, after its track number, into its frames
+fn unlace(block: &[u8]) -> Option<Vec<&[u8]>> {
+    // 16-bit relative timecode, then flags with the lacing in bits 1-2
+    let flags = *block.get(2)?;
+    let payload = &block[3..];
+    match (flags >> 1) & 0b11 {
+        0b00 => Some(vec![payload]),
+        0b01 => unlace_xiph(payload),
+        0b10 => {
+            let count = *payload.first()? as usize + 1;
+            let frames = &payload[1..];
+            if frames.is_empty() || !frames.len().is_multiple_of(count) {
+                return None;
+            }
+            Some(frames.chunks(frames.len() / count).collect())
+        }
+        _ => unlace_ebml(payload),
+    }
+}
+
+/// Xiph lacing: a frame count, then each size but the last as a run of 255s
+/// ended by a smaller byte
+fn unlace_xiph(payload: &[u8]) -> Option<Vec<&[u8]>> {
+    let count = *payload.first()? as usize + 1;
+    let mut pos = 1;
+    let mut sizes = Vec::with_capacity(count);
+    for _ in 1..count {
+        let mut size = 0;
+        loop {
+            let b = *payload.get(pos)?;
+            pos += 1;
+            size += b as usize;
+            if b < 255 {
+                break;
+            }
+        }
+        sizes.push(size);
+    }
+    split_frames(&payload[pos..], &sizes)
+}
+
+/// EBML lacing: a frame count, the first size as a variable-length integer,
+/// then each following size but the last as a signed difference
+fn unlace_ebml(payload: &[u8]) -> Option<Vec<&[u8]>> {
+    let count = *payload.first()? as usize + 1;
+    let (first, len) = read_vint(&payload[1..])?;
+    let mut pos = 1 + len;
+    let mut sizes = vec![first as usize];
+    for _ in 2..count {
+        let (raw, len) = read_vint(&payload[pos..])?;
+        pos += len;
+        let bias = (1i64 << (7 * len - 1)) - 1;
+        let previous = *sizes.last()? as i64;
+        sizes.push(usize::try_from(previous + raw as i64 - bias).ok()?);
+    }
+    split_frames(&payload[pos..], &sizes)
+}
+
+/// Cut `data` into frames of the given sizes plus a last frame with the rest
+fn split_frames<'a>(mut data: &'a [u8], sizes: &[usize]) -> Option<Vec<&'a [u8]>> {
+    let mut frames = Vec::with_capacity(sizes.len() + 1);
+    for &size in sizes {
+        frames.push(data.get(..size)?);
+        data = &data[size..];
+    }
+    frames.push(data);
+    Some(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::decoding::{AudioDecoder, FileDecoder};
+
+    const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let id = id.to_be_bytes();
+        let skip = id.iter().take_while(|&&b| b == 0).count();
+        let mut out = id[skip..].to_vec();
+        out.push(0x01);
+        out.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn simple_block(track: u8, timecode: i16, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let header = [&[0x80 | track][..], &timecode.to_be_bytes(), &[flags]].concat();
+        element(SIMPLE_BLOCK, &[&header[..], payload].concat())
+    }
+
+    fn f32_bytes(samples: &[f32]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    /// A MediaRecorder-style file: segment and clusters of unknown size, no
+    /// cues, and a video track ahead of the audio
+    fn unfinalized_webm(samples: &[f32]) -> Vec<u8> {
+        let video = element(
+            TRACK_ENTRY,
+            &[element(TRACK_NUMBER, &[1]), element(TRACK_TYPE, &[1]), element(CODEC_ID, b"V_VP8")]
+                .concat(),
+        );
+        let audio = element(
+            TRACK_ENTRY,
+            &[
+                element(TRACK_NUMBER, &[2]),
+                element(TRACK_TYPE, &[2]),
+                element(CODEC_ID, b"A_PCM/FLOAT/IEEE"),
+                element(
+                    AUDIO,
+                    &[
+                        element(SAMPLING_FREQUENCY, &8000f32.to_be_bytes()),
+                        element(CHANNELS, &[1]),
+                        element(BIT_DEPTH, &[32]),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        );
+
+        let (first, rest) = samples.split_at(samples.len() / 2);
+        let (second, third) = rest.split_at(rest.len() / 2);
+        // The second block laces two frames with Xiph lacing
+        let laced = [&[1, (second.len() * 4) as u8][..], &f32_bytes(second), &f32_bytes(third)]
+            .concat();
+
+        let mut file = element(EBML, &element(0x4282, b"webm"));
+        file.extend_from_slice(&SEGMENT.to_be_bytes());
+        file.extend_from_slice(&UNKNOWN_SIZE);
+        file.extend(element(TRACKS, &[video, audio].concat()));
+        for blocks in [
+            vec![simple_block(1, 0, 0x80, &[0xAA; 7]), simple_block(2, 0, 0x80, &f32_bytes(first))],
+            vec![simple_block(2, 0, 0x82, &laced)],
+        ] {
+            file.extend_from_slice(&CLUSTER.to_be_bytes());
+            file.extend_from_slice(&UNKNOWN_SIZE);
+            file.extend(element(CLUSTER_TIMECODE, &[0]));
+            file.extend(blocks.concat());
+        }
+        file
+    }
+
+    #[test]
+    fn test_reads_unfinalized_webm() {
+        let samples: Vec<f32> = (0..40).map(|i| i as f32 / 40.0 - 0.5).collect();
+        let mut file = unfinalized_webm(&samples);
+
+        let decoded = FileDecoder::new().decode(&file).unwrap();
+        assert_eq!((decoded.sample_rate, decoded.channels), (8000, 1));
+        assert_eq!(decoded.samples, samples);
+
+        // A recording cut off mid-block keeps everything before that block
+        file.truncate(file.len() - 5);
+        let decoded = FileDecoder::new().decode(&file).unwrap();
+        assert_eq!(decoded.samples, samples[..20]);
+    }
+
+    #[test]
+    fn test_block_timecodes_keep_gaps() {
+        let audio = element(
+            TRACK_ENTRY,
+            &[
+                element(TRACK_NUMBER, &[1]),
+                element(TRACK_TYPE, &[2]),
+                element(CODEC_ID, b"A_PCM/FLOAT/IEEE"),
+                element(
+                    AUDIO,
+                    &[
+                        element(SAMPLING_FREQUENCY, &8000f32.to_be_bytes()),
+                        element(CHANNELS, &[1]),
+                        element(BIT_DEPTH, &[32]),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        );
+
+        // Ticks of half a millisecond; 50 ms of audio at 0 ms, then 50 ms at
+        // 250 ms and 300 ms, the first of them in a later cluster
+        let mut file = element(EBML, &element(0x4282, b"webm"));
+        file.extend_from_slice(&SEGMENT.to_be_bytes());
+        file.extend_from_slice(&UNKNOWN_SIZE);
+        file.extend(element(INFO, &element(TIMECODE_SCALE, &500_000u32.to_be_bytes())));
+        file.extend(element(TRACKS, &audio));
+        for (timecode, blocks) in [
+            (0u16, simple_block(1, 0, 0x80, &f32_bytes(&[0.25; 400]))),
+            (
+                400,
+                [
+                    simple_block(1, 100, 0x80, &f32_bytes(&[0.5; 400])),
+                    simple_block(1, 200, 0x80, &f32_bytes(&[0.75; 400])),
+                ]
+                .concat(),
+            ),
+        ] {
+            file.extend_from_slice(&CLUSTER.to_be_bytes());
+            file.extend_from_slice(&UNKNOWN_SIZE);
+            file.extend(element(CLUSTER_TIMECODE, &timecode.to_be_bytes()));
+            file.extend(blocks);
+        }
+
+        let decoded = FileDecoder::new().decode(&file).unwrap();
+        let expected = [vec![0.25; 400], vec![0.0; 1600], vec![0.5; 400], vec![0.75; 400]].concat();
+        assert_eq!(decoded.samples, expected);
+    }
+
+    #[test]
+    fn test_ebml_and_fixed_lacing() {
+        let frames: [&[u8]; 3] = [&[1; 300], &[2; 4], &[3; 10]];
+        // Sizes 300 and 4: 4 - 300 = -296, biased by 8191 in two bytes
+        let diff = (8191 - 296) as u16 | 0x4000;
+        let ebml = [
+            &[0x00, 0x00, 0x06][..],
+            &[2, 0x41, 0x2C],
+            &diff.to_be_bytes(),
+            &frames.concat(),
+        ]
+        .concat();
+        assert_eq!(unlace(&ebml).unwrap(), frames);
+
+        let fixed = [&[0x00, 0x00, 0x04, 1][..], &[5; 8]].concat();
+        assert_eq!(unlace(&fixed).unwrap(), vec![&[5u8; 4][..], &[5; 4]]);
+    }
+}
@@ -22,6 +22,7 @@ pub extern "C" fn voice_pa_version() -> *const c_char {
 
 /// Free a string allocated by the library
 #[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn voice_pa_free_string(s: *mut c_char) {
     if !s.is_null() {
         unsafe {
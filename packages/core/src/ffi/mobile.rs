@@ -1,7 +1,10 @@
 // Mobile FFI bindings using UniFFI
 
 use std::sync::Mutex;
-use crate::audio::{AudioRecorder, WavEncoder, AudioEncoder};
+use std::time::Duration;
+use crate::audio::{AudioConfig, AudioRecorder, InputLevel, StopPolicy, AudioEncoder, FlacEncoder, WavEncoder};
+#[cfg(feature = "opus")]
+use crate::audio::OpusEncoder;
 use crate::transcription::WhisperClient;
 
 /// Called automatically when System.loadLibrary("uniffi_voice_pa_core") is invoked.
@@ -41,9 +44,35 @@ impl From<crate::utils::error::VoicePAError> for MobileError {
     }
 }
 
+/// Audio format `MobileRecorder::transcribe` uploads recordings in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UploadFormat {
+    #[default]
+    Wav,
+    Flac,
+    /// Smallest uploads over mobile data; needs the `opus` feature
+    Opus,
+}
+
+impl UploadFormat {
+    fn encoder(self) -> Result<Box<dyn AudioEncoder>, MobileError> {
+        match self {
+            UploadFormat::Wav => Ok(Box::new(WavEncoder::new())),
+            UploadFormat::Flac => Ok(Box::new(FlacEncoder::new())),
+            #[cfg(feature = "opus")]
+            UploadFormat::Opus => Ok(Box::new(OpusEncoder::new())),
+            #[cfg(not(feature = "opus"))]
+            UploadFormat::Opus => Err(MobileError::General {
+                msg: "Opus uploads require the opus feature".to_string(),
+            }),
+        }
+    }
+}
+
 /// Simplified interface for mobile platforms
 pub struct MobileRecorder {
     recorder: Mutex<AudioRecorder>,
+    upload_format: Mutex<UploadFormat>,
 }
 
 // SAFETY: AudioRecorder's interior state is protected by Mutex.
@@ -56,6 +85,29 @@ impl MobileRecorder {
     pub fn new() -> Result<Self, MobileError> {
         Ok(Self {
             recorder: Mutex::new(AudioRecorder::new()?),
+            upload_format: Mutex::new(UploadFormat::default()),
+        })
+    }
+
+    /// Create a recorder that stops by itself after `max_duration_secs`,
+    /// `silence_timeout_secs` of silence or `max_bytes` of audio
+    pub fn with_stop_policy(
+        max_duration_secs: Option<f64>,
+        silence_timeout_secs: Option<f64>,
+        max_bytes: Option<u64>,
+    ) -> Result<Self, MobileError> {
+        let config = AudioConfig {
+            stop_policy: StopPolicy {
+                max_duration: seconds("max_duration_secs", max_duration_secs)?,
+                silence_timeout: seconds("silence_timeout_secs", silence_timeout_secs)?,
+                max_bytes,
+                ..StopPolicy::default()
+            },
+            ..AudioConfig::default()
+        };
+        Ok(Self {
+            recorder: Mutex::new(AudioRecorder::with_config(config)?),
+            upload_format: Mutex::new(UploadFormat::default()),
         })
     }
 
@@ -75,6 +127,27 @@ impl MobileRecorder {
             .map_err(Into::into)
     }
 
+    pub fn pause(&self) -> Result<(), MobileError> {
+        let mut recorder = self.recorder.lock().unwrap();
+        tokio::runtime::Runtime::new()
+            .map_err(|e| MobileError::General { msg: e.to_string() })?
+            .block_on(recorder.pause_recording())
+            .map_err(Into::into)
+    }
+
+    pub fn resume(&self) -> Result<(), MobileError> {
+        let mut recorder = self.recorder.lock().unwrap();
+        tokio::runtime::Runtime::new()
+            .map_err(|e| MobileError::General { msg: e.to_string() })?
+            .block_on(recorder.resume_recording())
+            .map_err(Into::into)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        let recorder = self.recorder.lock().unwrap();
+        recorder.is_paused()
+    }
+
     pub fn is_recording(&self) -> bool {
         let recorder = self.recorder.lock().unwrap();
         recorder.is_recording()
@@ -85,15 +158,33 @@ impl MobileRecorder {
         recorder.duration()
     }
 
+    /// Current input level, for a VU meter or muted/too loud warnings
+    pub fn input_level(&self) -> InputLevel {
+        let recorder = self.recorder.lock().unwrap();
+        recorder.input_level()
+    }
+
+    /// Format `transcribe` uploads in; WAV unless changed
+    pub fn set_upload_format(&self, format: UploadFormat) -> Result<(), MobileError> {
+        format.encoder()?;
+        *self.upload_format.lock().unwrap() = format;
+        Ok(())
+    }
+
+    pub fn upload_format(&self) -> UploadFormat {
+        *self.upload_format.lock().unwrap()
+    }
+
     pub fn transcribe(&self, samples: Vec<f32>) -> Result<String, MobileError> {
         const API_KEY: &str = "";
 
         let recorder = self.recorder.lock().unwrap();
-        let sample_rate = recorder.actual_sample_rate();
-        let channels = recorder.actual_channels();
+        let sample_rate = recorder.output_sample_rate();
+        let channels = recorder.output_channels();
         drop(recorder);
 
-        let wav_data = WavEncoder::new()
+        let encoder = self.upload_format().encoder()?;
+        let audio_data = encoder
             .encode(&samples, sample_rate, channels)
             .map_err(|e| MobileError::General { msg: e.to_string() })?;
 
@@ -102,9 +193,46 @@ impl MobileRecorder {
             .map_err(|e| MobileError::General { msg: e.to_string() })?;
 
         let transcript = rt
-            .block_on(client.transcribe(&wav_data))
+            .block_on(client.transcribe_encoded(&audio_data, encoder.as_ref(), None))
             .map_err(|e| MobileError::General { msg: e.to_string() })?;
 
         Ok(transcript.text)
     }
 }
+
+/// Convert seconds received over FFI, rejecting negative, NaN and
+/// out-of-range values instead of panicking
+fn seconds(name: &str, value: Option<f64>) -> Result<Option<Duration>, MobileError> {
+    value
+        .map(|secs| {
+            Duration::try_from_secs_f64(secs).map_err(|e| MobileError::General {
+                msg: format!("Invalid {} {}: {}", name, secs, e),
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_stop_policy_is_an_error() {
+        for secs in [-1.0, f64::NAN, f64::INFINITY, 1e30] {
+            let result = MobileRecorder::with_stop_policy(Some(secs), None, None);
+            assert!(matches!(result, Err(MobileError::General { .. })), "{}", secs);
+            let result = MobileRecorder::with_stop_policy(None, Some(secs), None);
+            assert!(matches!(result, Err(MobileError::General { .. })), "{}", secs);
+        }
+        let valid = seconds("max_duration_secs", Some(1.5)).unwrap();
+        assert_eq!(valid, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_upload_format_encoders() {
+        assert_eq!(UploadFormat::default(), UploadFormat::Wav);
+        assert_eq!(UploadFormat::Wav.encoder().unwrap().mime_type(), "audio/wav");
+        assert_eq!(UploadFormat::Flac.encoder().unwrap().mime_type(), "audio/flac");
+        assert_eq!(UploadFormat::Opus.encoder().is_ok(), cfg!(feature = "opus"));
+    }
+}
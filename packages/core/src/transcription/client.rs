@@ -1,5 +1,6 @@
 use reqwest::multipart::{Form, Part};
 use serde::{Deserialize, Serialize};
+use crate::audio::AudioEncoder;
 use crate::utils::error::{Result, VoicePAError};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,57 +44,7 @@ impl WhisperClient {
 
     /// Transcribe audio data using OpenAI Whisper API
     pub async fn transcribe(&self, audio_data: &[u8]) -> Result<Transcript> {
-        let form = Form::new()
-            .part(
-                "file",
-                Part::bytes(audio_data.to_vec())
-                    .file_name("audio.wav")
-                    .mime_str("audio/wav")
-                    .map_err(|e| VoicePAError::Transcription(e.to_string()))?,
-            )
-            .text("model", "whisper-1")
-            .text("response_format", "verbose_json")
-            .text("timestamp_granularities[]", "segment");
-
-        let response = self
-            .client
-            .post(format!("{}/audio/transcriptions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .multipart(form)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(VoicePAError::Transcription(format!(
-                "API error: {}",
-                error_text
-            )));
-        }
-
-        let whisper_response: WhisperResponse = response.json().await?;
-        
-        // Convert Whisper response to our Transcript format
-        let segments: Vec<TranscriptSegment> = whisper_response
-            .segments
-            .into_iter()
-            .enumerate()
-            .map(|(i, seg)| TranscriptSegment {
-                id: i as u32,
-                speaker_id: None, // Speaker diarization happens separately
-                text: seg.text,
-                start_time: seg.start,
-                end_time: seg.end,
-                confidence: seg.avg_logprob.exp(), // Convert log prob to confidence
-            })
-            .collect();
-
-        Ok(Transcript {
-            text: whisper_response.text,
-            language: whisper_response.language,
-            segments,
-            speakers: Vec::new(), // Will be populated by diarization
-        })
+        self.upload(audio_data, "audio.wav", "audio/wav", None).await
     }
 
     /// Transcribe with language hint
@@ -102,16 +53,42 @@ impl WhisperClient {
         audio_data: &[u8],
         language: &str,
     ) -> Result<Transcript> {
-        let form = Form::new()
+        self.upload(audio_data, "audio.wav", "audio/wav", Some(language))
+            .await
+    }
+
+    /// Transcribe audio produced by `encoder`, uploaded with its file type
+    pub async fn transcribe_encoded(
+        &self,
+        audio_data: &[u8],
+        encoder: &dyn AudioEncoder,
+        language: Option<&str>,
+    ) -> Result<Transcript> {
+        let file_name = format!("audio.{}", encoder.file_extension());
+        self.upload(audio_data, &file_name, encoder.mime_type(), language)
+            .await
+    }
+
+    async fn upload(
+        &self,
+        audio_data: &[u8],
+        file_name: &str,
+        mime_type: &str,
+        language: Option<&str>,
+    ) -> Result<Transcript> {
+        let mut form = Form::new()
             .part(
                 "file",
                 Part::bytes(audio_data.to_vec())
-                    .file_name("audio.wav")
-                    .mime_str("audio/wav")
+                    .file_name(file_name.to_string())
+                    .mime_str(mime_type)
                     .map_err(|e| VoicePAError::Transcription(e.to_string()))?,
             )
-            .text("model", "whisper-1")
-            .text("language", language.to_string())
+            .text("model", "whisper-1");
+        if let Some(language) = language {
+            form = form.text("language", language.to_string());
+        }
+        let form = form
             .text("response_format", "verbose_json")
             .text("timestamp_granularities[]", "segment");
 
@@ -133,17 +110,18 @@ impl WhisperClient {
 
         let whisper_response: WhisperResponse = response.json().await?;
         
+        // Convert Whisper response to our Transcript format
         let segments: Vec<TranscriptSegment> = whisper_response
             .segments
             .into_iter()
             .enumerate()
             .map(|(i, seg)| TranscriptSegment {
                 id: i as u32,
-                speaker_id: None,
+                speaker_id: None, // Speaker diarization happens separately
                 text: seg.text,
                 start_time: seg.start,
                 end_time: seg.end,
-                confidence: seg.avg_logprob.exp(),
+                confidence: seg.avg_logprob.exp(), // Convert log prob to confidence
             })
             .collect();
 
@@ -151,7 +129,7 @@ impl WhisperClient {
             text: whisper_response.text,
             language: whisper_response.language,
             segments,
-            speakers: Vec::new(),
+            speakers: Vec::new(), // Will be populated by diarization
         })
     }
 }
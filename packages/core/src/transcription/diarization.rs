@@ -2,6 +2,7 @@ use crate::transcription::{Transcript, Speaker};
 use crate::utils::error::Result;
 
 /// Speaker diarization - identifies who spoke when
+#[derive(Default)]
 pub struct SpeakerDiarizer;
 
 impl SpeakerDiarizer {
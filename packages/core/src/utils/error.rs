@@ -13,6 +13,12 @@ pub enum VoicePAError {
     #[error("Encoding error: {0}")]
     Encoding(String),
 
+    #[error("Decoding error: {0}")]
+    Decoding(String),
+
+    #[error("Audio processing error: {0}")]
+    Processing(String),
+
     #[error("Transcription error: {0}")]
     Transcription(String),
 
@@ -59,3 +65,24 @@ impl From<hound::Error> for VoicePAError {
         VoicePAError::Encoding(err.to_string())
     }
 }
+
+impl From<symphonia::core::errors::Error> for VoicePAError {
+    fn from(err: symphonia::core::errors::Error) -> Self {
+        match err {
+            symphonia::core::errors::Error::IoError(err) => VoicePAError::Io(err),
+            err => VoicePAError::Decoding(err.to_string()),
+        }
+    }
+}
+
+impl From<rubato::ResampleError> for VoicePAError {
+    fn from(err: rubato::ResampleError) -> Self {
+        VoicePAError::Processing(err.to_string())
+    }
+}
+
+impl From<rubato::ResamplerConstructionError> for VoicePAError {
+    fn from(err: rubato::ResamplerConstructionError) -> Self {
+        VoicePAError::Processing(err.to_string())
+    }
+}
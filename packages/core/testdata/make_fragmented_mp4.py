@@ -0,0 +1,79 @@
+"""Write a fragmented MP4 with one second of an AAC-LC tone, laid out like
+MediaRecorder's MP4 output: an empty sample table in the movie header and
+the audio in movie fragments.
+
+Usage: python3 make_fragmented_mp4.py tone-fragmented.m4a
+"""
+import struct
+import sys
+
+
+
+def box(kind, *payload):
+    body = b"".join(payload)
+    return struct.pack(">I", 8 + len(body)) + kind + body
+
+
+def full(kind, version, flags, *payload):
+    return box(kind, struct.pack(">I", (version << 24) | flags), *payload)
+
+
+def desc(tag, body):
+    return bytes([tag, len(body)]) + body
+
+RATE, CHANNELS, FRAME = 48000, 1, 1024
+
+
+def tone_frame(global_gain):
+    """A raw AAC-LC frame: a single channel element coding one spectral line
+    in bin 42, which decodes to a steady 1171.875 Hz tone"""
+    bits = "000" + "0000" + format(global_gain, "08b")
+    bits += "0" + "00" + "0" + format(12, "06b") + "0"  # long window, 12 bands
+    bits += "0000" + format(11, "05b") + "0001" + format(1, "05b")  # 11 zero bands, 1 in codebook 1
+    bits += "0"  # scale factor of band 11 equal to the global gain
+    bits += "0" + "0" + "0"  # no pulse, TNS or gain control data
+    bits += "10110" + "0"  # quads (0, 0, +1, 0) and (0, 0, 0, 0): bins 40-47
+    bits += "111"  # end element
+    bits += "0" * (-len(bits) % 8)
+    return bytes(int(bits[i:i + 8], 2) for i in range(0, len(bits), 8))
+
+
+# Quarter scale
+TONE = tone_frame(192)
+asc = struct.pack(">H", (2 << 11) | (3 << 7) | (CHANNELS << 3))
+esds = full(b"esds", 0, 0, desc(3, struct.pack(">HB", 1, 0)
+    + desc(4, bytes([0x40, 0x15]) + (0).to_bytes(3, "big") + struct.pack(">II", 128000, 128000) + desc(5, asc))
+    + desc(6, b"\x02")))
+mp4a = box(b"mp4a", b"\0" * 6, struct.pack(">H", 1), b"\0" * 8,
+           struct.pack(">HHHHI", CHANNELS, 16, 0, 0, RATE << 16), esds)
+matrix = struct.pack(">9I", 0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000)
+stbl = box(b"stbl", full(b"stsd", 0, 0, struct.pack(">I", 1), mp4a),
+           full(b"stts", 0, 0, struct.pack(">I", 0)), full(b"stsc", 0, 0, struct.pack(">I", 0)),
+           full(b"stsz", 0, 0, struct.pack(">II", 0, 0)), full(b"stco", 0, 0, struct.pack(">I", 0)))
+minf = box(b"minf", full(b"smhd", 0, 0, struct.pack(">HH", 0, 0)),
+           box(b"dinf", full(b"dref", 0, 0, struct.pack(">I", 1), full(b"url ", 0, 1))), stbl)
+mdia = box(b"mdia", full(b"mdhd", 0, 0, struct.pack(">IIIIHH", 0, 0, RATE, 0, 0x55C4, 0)),
+           full(b"hdlr", 0, 0, struct.pack(">I", 0), b"soun", b"\0" * 12, b"SoundHandler\0"), minf)
+trak = box(b"trak", full(b"tkhd", 0, 3, struct.pack(">IIIII", 0, 0, 1, 0, 0), b"\0" * 8,
+                         struct.pack(">hhHH", 0, 0, 0x0100, 0), matrix, struct.pack(">II", 0, 0)), mdia)
+mvhd = full(b"mvhd", 0, 0, struct.pack(">IIII", 0, 0, 1000, 0), struct.pack(">IH", 0x10000, 0x0100),
+            b"\0" * 10, matrix, b"\0" * 24, struct.pack(">I", 2))
+mvex = box(b"mvex", full(b"trex", 0, 0, struct.pack(">IIIII", 1, 1, 0, 0, 0)))
+out = box(b"ftyp", b"iso5", struct.pack(">I", 512), b"iso5iso6mp41") + box(b"moov", mvhd, trak, mvex)
+
+# MediaRecorder emits a fragment per timeslice; 47 frames of 1024 is about 1 s
+counts = [16, 16, 15]
+decode_time = 0
+for seq, count in enumerate(counts, 1):
+    samples = [TONE] * count
+    def moof(data_offset):
+        trun = full(b"trun", 0, 0x000301, struct.pack(">Ii", count, data_offset),
+                    b"".join(struct.pack(">II", FRAME, len(s)) for s in samples))
+        traf = box(b"traf", full(b"tfhd", 0, 0x020000, struct.pack(">I", 1)),
+                   full(b"tfdt", 1, 0, struct.pack(">Q", decode_time)), trun)
+        return box(b"moof", full(b"mfhd", 0, 0, struct.pack(">I", seq)), traf)
+    size = len(moof(0))
+    out += moof(size + 8) + box(b"mdat", *samples)
+    decode_time += count * FRAME
+open(sys.argv[1], "wb").write(out)
+print(len(out), "bytes,", sum(counts) * FRAME, "frames")
@@ -0,0 +1,61 @@
+"""Mux the Opus packets of an Ogg Opus file into WebM the way Chrome's
+MediaRecorder does: a segment and clusters of unknown size, no cues, seek
+head or duration, and 20 ms SimpleBlocks.
+
+Usage: python3 make_mediarecorder_webm.py tone.opus tone-mediarecorder.webm
+"""
+import struct
+import sys
+
+
+
+def read_ogg_packets(data):
+    packets, partial, pos = [], b"", 0
+    while pos < len(data):
+        assert data[pos:pos + 4] == b"OggS"
+        count = data[pos + 26]
+        lacing = data[pos + 27:pos + 27 + count]
+        body = pos + 27 + count
+        for size in lacing:
+            partial += data[body:body + size]
+            body += size
+            if size < 255:
+                packets.append(partial)
+                partial = b""
+        pos = body
+    return packets
+
+
+def vint_size(n):
+    return bytes([0x01]) + n.to_bytes(7, "big")
+
+
+def element(eid, body):
+    return eid.to_bytes((eid.bit_length() + 7) // 8, "big") + vint_size(len(body)) + body
+
+
+def uint(eid, value):
+    return element(eid, value.to_bytes(max(1, (value.bit_length() + 7) // 8), "big"))
+
+UNKNOWN = bytes([0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])
+packets = read_ogg_packets(open(sys.argv[1], "rb").read())
+head, audio = packets[0], packets[2:]
+assert head.startswith(b"OpusHead")
+
+out = element(0x1A45DFA3, uint(0x4286, 1) + uint(0x42F7, 1) + uint(0x42F2, 4) + uint(0x42F3, 8)
+              + element(0x4282, b"webm") + uint(0x4287, 4) + uint(0x4285, 2))
+out += (0x18538067).to_bytes(4, "big") + UNKNOWN
+out += element(0x1549A966, uint(0x2AD7B1, 1000000) + element(0x4D80, b"Chrome") + element(0x5741, b"Chrome"))
+out += element(0x1654AE6B, element(0xAE, uint(0xD7, 1) + uint(0x73C5, 0x5A3C9E21) + uint(0x83, 2)
+    + element(0x86, b"A_OPUS") + element(0x63A2, head)
+    + element(0xE1, element(0xB5, struct.pack(">d", 48000.0)) + uint(0x9F, head[9]) + uint(0x6264, 32))))
+
+# 20 ms packets, a new cluster about every second like MediaRecorder
+timestamp = 0
+for start in range(0, len(audio), 50):
+    out += (0x1F43B675).to_bytes(4, "big") + UNKNOWN + uint(0xE7, timestamp)
+    for index, packet in enumerate(audio[start:start + 50]):
+        out += element(0xA3, bytes([0x81]) + struct.pack(">h", index * 20) + bytes([0x80]) + packet)
+    timestamp += 20 * len(audio[start:start + 50])
+open(sys.argv[2], "wb").write(out)
+print(len(out), "bytes,", len(audio), "packets")